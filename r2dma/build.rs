@@ -11,9 +11,20 @@ fn main() {
     let mut include_paths = lib.include_paths.into_iter().collect::<HashSet<_>>();
     include_paths.insert(PathBuf::from("/usr/include"));
 
-    let builder = bindgen::Builder::default()
+    let rdmacm = env::var_os("CARGO_FEATURE_RDMACM").is_some();
+    let mut header = String::from("#include <infiniband/verbs.h>\n");
+    if rdmacm {
+        let lib = pkg_config::Config::new()
+            .statik(false)
+            .probe("librdmacm")
+            .unwrap_or_else(|_| panic!("please install librdmacm-dev and pkg-config"));
+        include_paths.extend(lib.include_paths);
+        header.push_str("#include <rdma/rdma_cma.h>\n");
+    }
+
+    let mut builder = bindgen::Builder::default()
         .clang_args(include_paths.iter().map(|p| format!("-I{p:?}")))
-        .header_contents("header.h", "#include <infiniband/verbs.h>")
+        .header_contents("header.h", &header)
         .derive_copy(true)
         .derive_debug(true)
         .derive_default(true)
@@ -29,37 +40,56 @@ fn main() {
         .opaque_type("pthread_cond_t")
         .opaque_type("pthread_mutex_t")
         .allowlist_type("ibv_access_flags")
+        .allowlist_type("ibv_ah")
+        .allowlist_type("ibv_async_event")
         .allowlist_type("ibv_comp_channel")
         .allowlist_type("ibv_context")
         .allowlist_type("ibv_cq")
         .allowlist_type("ibv_device")
+        .allowlist_type("ibv_device_attr_ex")
         .allowlist_type("ibv_gid")
         .allowlist_type("ibv_mr")
+        .allowlist_type("ibv_mw")
+        .allowlist_type("ibv_mw_bind_info")
+        .allowlist_type("ibv_mw_type")
+        .allowlist_type("ibv_odp_general_caps")
+        .allowlist_type("ibv_odp_transport_cap_bits")
         .allowlist_type("ibv_pd")
         .allowlist_type("ibv_port_attr")
         .allowlist_type("ibv_qp")
         .allowlist_type("ibv_qp_attr_mask")
         .allowlist_type("ibv_qp_init_attr")
+        .allowlist_type("ibv_query_device_ex_input")
         .allowlist_type("ibv_send_flags")
+        .allowlist_type("ibv_srq_attr_mask")
+        .allowlist_type("ibv_srq_init_attr")
         .allowlist_type("ibv_wc")
         .allowlist_type("ibv_wc_flags")
         .allowlist_type("ibv_wc_status")
+        .allowlist_type("verbs_context")
+        .allowlist_function("ibv_ack_async_event")
         .allowlist_function("ibv_ack_cq_events")
         .allowlist_function("ibv_alloc_pd")
         .allowlist_function("ibv_close_device")
+        .allowlist_function("ibv_create_ah")
         .allowlist_function("ibv_create_comp_channel")
         .allowlist_function("ibv_create_cq")
         .allowlist_function("ibv_create_qp")
+        .allowlist_function("ibv_create_srq")
         .allowlist_function("ibv_dealloc_pd")
+        .allowlist_function("ibv_destroy_ah")
         .allowlist_function("ibv_dereg_mr")
         .allowlist_function("ibv_destroy_comp_channel")
         .allowlist_function("ibv_destroy_cq")
         .allowlist_function("ibv_destroy_qp")
+        .allowlist_function("ibv_destroy_srq")
         .allowlist_function("ibv_free_device_list")
+        .allowlist_function("ibv_get_async_event")
         .allowlist_function("ibv_get_cq_event")
         .allowlist_function("ibv_get_device_guid")
         .allowlist_function("ibv_get_device_list")
         .allowlist_function("ibv_modify_qp")
+        .allowlist_function("ibv_modify_srq")
         .allowlist_function("ibv_req_notify_cq")
         .allowlist_function("ibv_poll_cq")
         .allowlist_function("ibv_post_recv")
@@ -67,18 +97,51 @@ fn main() {
         .allowlist_function("ibv_query_device")
         .allowlist_function("ibv_query_gid")
         .allowlist_function("ibv_query_port")
+        .allowlist_function("ibv_query_qp")
         .allowlist_function("ibv_open_device")
         .allowlist_function("ibv_reg_mr")
         .bitfield_enum("ibv_access_flags")
+        .bitfield_enum("ibv_odp_general_caps")
+        .bitfield_enum("ibv_odp_transport_cap_bits")
         .bitfield_enum("ibv_send_flags")
         .bitfield_enum("ibv_wc_flags")
         .bitfield_enum("ibv_qp_attr_mask")
+        .bitfield_enum("ibv_srq_attr_mask")
+        // devices may report values newer than the headers, which must not be undefined behavior.
+        .newtype_enum("ibv_atomic_cap")
+        .newtype_enum("ibv_event_type")
+        .newtype_enum("ibv_mtu")
+        .newtype_enum("ibv_port_state")
+        .newtype_enum("ibv_wc_status")
         .no_copy("ibv_context")
         .no_copy("ibv_cq")
         .no_copy("ibv_qp")
         .no_copy("ibv_srq")
+        .no_copy("verbs_context")
         .no_debug("ibv_device");
 
+    if rdmacm {
+        builder = builder
+            .allowlist_type("rdma_cm_event")
+            .allowlist_type("rdma_cm_id")
+            .allowlist_type("rdma_conn_param")
+            .allowlist_type("rdma_port_space")
+            .allowlist_function("rdma_accept")
+            .allowlist_function("rdma_bind_addr")
+            .allowlist_function("rdma_connect")
+            .allowlist_function("rdma_create_id")
+            .allowlist_function("rdma_destroy_id")
+            .allowlist_function("rdma_disconnect")
+            .allowlist_function("rdma_establish")
+            .allowlist_function("rdma_get_request")
+            .allowlist_function("rdma_init_qp_attr")
+            .allowlist_function("rdma_listen")
+            .allowlist_function("rdma_reject")
+            .allowlist_function("rdma_resolve_addr")
+            .allowlist_function("rdma_resolve_route")
+            .no_copy("rdma_cm_id");
+    }
+
     builder
         .generate()
         .expect("Unable to generate bindings")
@@ -0,0 +1,298 @@
+use super::VerbsBackend;
+use crate::verbs;
+use std::{
+    ffi::{c_int, c_uint, c_void},
+    path::Path,
+};
+
+/// The verbs backend which calls into libibverbs.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct IbVerbs;
+
+impl VerbsBackend for IbVerbs {
+    fn name(&self) -> &str {
+        "ibverbs"
+    }
+
+    unsafe fn get_device_list(&self, num_devices: *mut c_int) -> *mut *mut verbs::ibv_device {
+        verbs::ibv_get_device_list(num_devices)
+    }
+
+    unsafe fn free_device_list(&self, list: *mut *mut verbs::ibv_device) {
+        verbs::ibv_free_device_list(list)
+    }
+
+    unsafe fn get_device_guid(&self, device: *mut verbs::ibv_device) -> u64 {
+        verbs::ibv_get_device_guid(device)
+    }
+
+    unsafe fn open_device(&self, device: *mut verbs::ibv_device) -> *mut verbs::ibv_context {
+        verbs::ibv_open_device(device)
+    }
+
+    unsafe fn close_device(&self, context: *mut verbs::ibv_context) -> c_int {
+        verbs::ibv_close_device(context)
+    }
+
+    unsafe fn query_device(
+        &self,
+        context: *mut verbs::ibv_context,
+        device_attr: *mut verbs::ibv_device_attr,
+    ) -> c_int {
+        verbs::ibv_query_device(context, device_attr)
+    }
+
+    unsafe fn query_device_ex(
+        &self,
+        context: *mut verbs::ibv_context,
+        device_attr: *mut verbs::ibv_device_attr_ex,
+    ) -> c_int {
+        verbs::ibv_query_device_ex(context, std::ptr::null(), device_attr)
+    }
+
+    unsafe fn query_port(
+        &self,
+        context: *mut verbs::ibv_context,
+        port_num: u8,
+        port_attr: *mut verbs::ibv_port_attr,
+    ) -> c_int {
+        verbs::ibv_query_port(context, port_num, port_attr as _)
+    }
+
+    unsafe fn query_gid(
+        &self,
+        context: *mut verbs::ibv_context,
+        port_num: u8,
+        index: c_int,
+        gid: *mut verbs::ibv_gid,
+    ) -> c_int {
+        verbs::ibv_query_gid(context, port_num as _, index as _, gid)
+    }
+
+    fn read_gid_type(
+        &self,
+        ibdev_path: &Path,
+        port_num: u8,
+        gid_index: u16,
+    ) -> std::io::Result<String> {
+        let path = ibdev_path.join(format!("ports/{port_num}/gid_attrs/types/{gid_index}"));
+        std::fs::read_to_string(path)
+    }
+
+    fn read_port_counters(
+        &self,
+        ibdev_path: &Path,
+        port_num: u8,
+    ) -> std::io::Result<Vec<(String, u64)>> {
+        let mut counters = vec![];
+        for dir in ["counters", "hw_counters"] {
+            let entries =
+                match std::fs::read_dir(ibdev_path.join(format!("ports/{port_num}/{dir}"))) {
+                    Ok(entries) => entries,
+                    // devices such as Soft-RoCE provide only one of them.
+                    Err(err) if err.kind() == std::io::ErrorKind::NotFound => continue,
+                    Err(err) => return Err(err),
+                };
+            for entry in entries {
+                let entry = entry?;
+                // some counters are not readable, or not numbers such as `lifespan` on old kernels.
+                let Ok(content) = std::fs::read_to_string(entry.path()) else {
+                    continue;
+                };
+                if let Ok(value) = content.trim().parse() {
+                    counters.push((entry.file_name().to_string_lossy().into_owned(), value));
+                }
+            }
+        }
+        Ok(counters)
+    }
+
+    unsafe fn get_async_event(
+        &self,
+        context: *mut verbs::ibv_context,
+        event: *mut verbs::ibv_async_event,
+    ) -> c_int {
+        verbs::ibv_get_async_event(context, event)
+    }
+
+    unsafe fn ack_async_event(&self, event: *mut verbs::ibv_async_event) {
+        verbs::ibv_ack_async_event(event)
+    }
+
+    unsafe fn alloc_pd(&self, context: *mut verbs::ibv_context) -> *mut verbs::ibv_pd {
+        verbs::ibv_alloc_pd(context)
+    }
+
+    unsafe fn dealloc_pd(&self, pd: *mut verbs::ibv_pd) -> c_int {
+        verbs::ibv_dealloc_pd(pd)
+    }
+
+    unsafe fn reg_mr(
+        &self,
+        pd: *mut verbs::ibv_pd,
+        addr: *mut c_void,
+        length: usize,
+        access: c_int,
+    ) -> *mut verbs::ibv_mr {
+        verbs::ibv_reg_mr(pd, addr, length, access as _)
+    }
+
+    unsafe fn dereg_mr(&self, mr: *mut verbs::ibv_mr) -> c_int {
+        verbs::ibv_dereg_mr(mr)
+    }
+
+    unsafe fn alloc_mw(
+        &self,
+        pd: *mut verbs::ibv_pd,
+        mw_type: verbs::ibv_mw_type,
+    ) -> *mut verbs::ibv_mw {
+        verbs::ibv_alloc_mw(pd, mw_type)
+    }
+
+    unsafe fn dealloc_mw(&self, mw: *mut verbs::ibv_mw) -> c_int {
+        verbs::ibv_dealloc_mw(mw)
+    }
+
+    unsafe fn create_comp_channel(
+        &self,
+        context: *mut verbs::ibv_context,
+    ) -> *mut verbs::ibv_comp_channel {
+        verbs::ibv_create_comp_channel(context)
+    }
+
+    unsafe fn destroy_comp_channel(&self, channel: *mut verbs::ibv_comp_channel) -> c_int {
+        verbs::ibv_destroy_comp_channel(channel)
+    }
+
+    unsafe fn get_cq_event(
+        &self,
+        channel: *mut verbs::ibv_comp_channel,
+        cq: *mut *mut verbs::ibv_cq,
+        cq_context: *mut *mut c_void,
+    ) -> c_int {
+        verbs::ibv_get_cq_event(channel, cq, cq_context)
+    }
+
+    unsafe fn ack_cq_events(&self, cq: *mut verbs::ibv_cq, num_events: c_uint) {
+        verbs::ibv_ack_cq_events(cq, num_events)
+    }
+
+    unsafe fn create_cq(
+        &self,
+        context: *mut verbs::ibv_context,
+        cqe: c_int,
+        cq_context: *mut c_void,
+        channel: *mut verbs::ibv_comp_channel,
+        comp_vector: c_int,
+    ) -> *mut verbs::ibv_cq {
+        verbs::ibv_create_cq(context, cqe, cq_context, channel, comp_vector)
+    }
+
+    unsafe fn destroy_cq(&self, cq: *mut verbs::ibv_cq) -> c_int {
+        verbs::ibv_destroy_cq(cq)
+    }
+
+    unsafe fn poll_cq(
+        &self,
+        cq: *mut verbs::ibv_cq,
+        num_entries: c_int,
+        wc: *mut verbs::ibv_wc,
+    ) -> c_int {
+        verbs::ibv_poll_cq(cq, num_entries, wc)
+    }
+
+    unsafe fn req_notify_cq(&self, cq: *mut verbs::ibv_cq, solicited_only: c_int) -> c_int {
+        verbs::ibv_req_notify_cq(cq, solicited_only)
+    }
+
+    unsafe fn create_qp(
+        &self,
+        pd: *mut verbs::ibv_pd,
+        init_attr: *mut verbs::ibv_qp_init_attr,
+    ) -> *mut verbs::ibv_qp {
+        verbs::ibv_create_qp(pd, init_attr)
+    }
+
+    unsafe fn destroy_qp(&self, qp: *mut verbs::ibv_qp) -> c_int {
+        verbs::ibv_destroy_qp(qp)
+    }
+
+    unsafe fn modify_qp(
+        &self,
+        qp: *mut verbs::ibv_qp,
+        attr: *mut verbs::ibv_qp_attr,
+        attr_mask: c_int,
+    ) -> c_int {
+        verbs::ibv_modify_qp(qp, attr, attr_mask)
+    }
+
+    unsafe fn query_qp(
+        &self,
+        qp: *mut verbs::ibv_qp,
+        attr: *mut verbs::ibv_qp_attr,
+        attr_mask: c_int,
+        init_attr: *mut verbs::ibv_qp_init_attr,
+    ) -> c_int {
+        verbs::ibv_query_qp(qp, attr, attr_mask, init_attr)
+    }
+
+    unsafe fn post_send(
+        &self,
+        qp: *mut verbs::ibv_qp,
+        wr: *mut verbs::ibv_send_wr,
+        bad_wr: *mut *mut verbs::ibv_send_wr,
+    ) -> c_int {
+        verbs::ibv_post_send(qp, wr, bad_wr)
+    }
+
+    unsafe fn post_recv(
+        &self,
+        qp: *mut verbs::ibv_qp,
+        wr: *mut verbs::ibv_recv_wr,
+        bad_wr: *mut *mut verbs::ibv_recv_wr,
+    ) -> c_int {
+        verbs::ibv_post_recv(qp, wr, bad_wr)
+    }
+
+    unsafe fn create_srq(
+        &self,
+        pd: *mut verbs::ibv_pd,
+        init_attr: *mut verbs::ibv_srq_init_attr,
+    ) -> *mut verbs::ibv_srq {
+        verbs::ibv_create_srq(pd, init_attr)
+    }
+
+    unsafe fn destroy_srq(&self, srq: *mut verbs::ibv_srq) -> c_int {
+        verbs::ibv_destroy_srq(srq)
+    }
+
+    unsafe fn modify_srq(
+        &self,
+        srq: *mut verbs::ibv_srq,
+        attr: *mut verbs::ibv_srq_attr,
+        attr_mask: c_int,
+    ) -> c_int {
+        verbs::ibv_modify_srq(srq, attr, attr_mask)
+    }
+
+    unsafe fn post_srq_recv(
+        &self,
+        srq: *mut verbs::ibv_srq,
+        wr: *mut verbs::ibv_recv_wr,
+        bad_wr: *mut *mut verbs::ibv_recv_wr,
+    ) -> c_int {
+        verbs::ibv_post_srq_recv(srq, wr, bad_wr)
+    }
+
+    unsafe fn create_ah(
+        &self,
+        pd: *mut verbs::ibv_pd,
+        attr: *mut verbs::ibv_ah_attr,
+    ) -> *mut verbs::ibv_ah {
+        verbs::ibv_create_ah(pd, attr)
+    }
+
+    unsafe fn destroy_ah(&self, ah: *mut verbs::ibv_ah) -> c_int {
+        verbs::ibv_destroy_ah(ah)
+    }
+}
@@ -0,0 +1,1606 @@
+use super::VerbsBackend;
+use crate::{verbs, DeviceConfig, Devices, Result};
+use std::{
+    collections::{HashMap, VecDeque},
+    ffi::{c_char, c_int, c_uint, c_void},
+    mem::MaybeUninit,
+    net::Ipv6Addr,
+    path::Path,
+    sync::{Arc, Mutex},
+};
+
+const MOCK_PORT_NUM: u8 = 1;
+const MOCK_GID_TBL_LEN: c_int = 2;
+const MOCK_COUNTERS: [&str; 12] = [
+    "port_xmit_data",
+    "port_rcv_data",
+    "port_xmit_packets",
+    "port_rcv_packets",
+    "port_xmit_discards",
+    "port_rcv_errors",
+    "symbol_error",
+    "link_downed",
+    "out_of_sequence",
+    "packet_seq_err",
+    "local_ack_timeout_err",
+    "rnr_nak_retry_err",
+];
+
+fn fail(errno: c_int) -> c_int {
+    unsafe { *libc::__errno_location() = errno };
+    errno
+}
+
+fn fail_null<T>(errno: c_int) -> *mut T {
+    fail(errno);
+    std::ptr::null_mut()
+}
+
+fn copy_str(dst: &mut [c_char], src: &str) {
+    let len = src.len().min(dst.len() - 1);
+    for (d, s) in dst.iter_mut().zip(&src.as_bytes()[..len]) {
+        *d = *s as c_char;
+    }
+    dst[len] = 0;
+}
+
+fn has_access(access: u32, flag: verbs::ibv_access_flags) -> bool {
+    access & flag.0 != 0
+}
+
+/// Returns the index of the mock device owning the GID, which ends with the device index plus one.
+fn gid_device_index(gid: &verbs::ibv_gid) -> usize {
+    let raw = unsafe { gid.raw };
+    u16::from_be_bytes([raw[14], raw[15]]).saturating_sub(1) as usize
+}
+
+/// Identifies a queue pair by its device index and number, which is only unique within the device.
+type QpKey = (usize, u32);
+
+struct MockMemoryRegion {
+    device_index: usize,
+    addr: usize,
+    length: usize,
+    access: u32,
+    lkey: u32,
+    rkey: u32,
+}
+
+impl MockMemoryRegion {
+    fn contains(&self, addr: usize, length: usize) -> bool {
+        addr >= self.addr && addr + length <= self.addr + self.length
+    }
+}
+
+#[derive(Default)]
+struct MockCompQueue {
+    entries: VecDeque<verbs::ibv_wc>,
+    channel: usize,
+    armed: bool,
+}
+
+struct MockBinding {
+    qp_num: u32,
+    addr: usize,
+    length: usize,
+    access: u32,
+}
+
+struct MockMemoryWindow {
+    device_index: usize,
+    rkey: u32,
+    bound: Option<MockBinding>,
+}
+
+struct MockCompChannel {
+    fd: c_int,
+    events: VecDeque<usize>,
+}
+
+struct MockRecv {
+    wr_id: u64,
+    sges: Vec<verbs::ibv_sge>,
+}
+
+struct MockQueuePair {
+    ptr: usize,
+    device_index: usize,
+    send_cq: usize,
+    recv_cq: usize,
+    sq_sig_all: bool,
+    cap: verbs::ibv_qp_cap,
+    attr: verbs::ibv_qp_attr,
+    recvs: VecDeque<MockRecv>,
+}
+
+#[derive(Default)]
+struct MockState {
+    next_handle: u32,
+    contexts: HashMap<usize, usize>,
+    pds: HashMap<usize, usize>,
+    mrs: HashMap<usize, MockMemoryRegion>,
+    mws: HashMap<usize, MockMemoryWindow>,
+    channels: HashMap<usize, MockCompChannel>,
+    cqs: HashMap<usize, MockCompQueue>,
+    qps: HashMap<QpKey, MockQueuePair>,
+    next_qp_nums: HashMap<usize, u32>,
+    counters: HashMap<(usize, &'static str), u64>,
+}
+
+impl MockState {
+    fn next_handle(&mut self) -> u32 {
+        self.next_handle += 1;
+        self.next_handle
+    }
+
+    fn count(&mut self, device_index: usize, name: &'static str, value: u64) {
+        *self.counters.entry((device_index, name)).or_default() += value;
+    }
+
+    /// Counts a packet carrying `len` bytes, with data counted in 4-byte words as in sysfs.
+    fn count_transfer(&mut self, from: usize, to: usize, len: usize) {
+        let words = len.div_ceil(4) as u64;
+        self.count(from, "port_xmit_data", words);
+        self.count(from, "port_xmit_packets", 1);
+        self.count(to, "port_rcv_data", words);
+        self.count(to, "port_rcv_packets", 1);
+    }
+
+    /// Appends a completion, and notifies the completion channel if the queue is armed.
+    fn push_wc(&mut self, cq: usize, wc: verbs::ibv_wc) {
+        let Some(comp_queue) = self.cqs.get_mut(&cq) else {
+            return;
+        };
+        comp_queue.entries.push_back(wc);
+        if !std::mem::take(&mut comp_queue.armed) {
+            return;
+        }
+        if let Some(channel) = self.channels.get_mut(&comp_queue.channel) {
+            channel.events.push_back(cq);
+            let value = 1u64;
+            unsafe { libc::write(channel.fd, &value as *const u64 as *const c_void, 8) };
+        }
+    }
+
+    /// Moves the queue pair into the error state and flushes its posted receives.
+    fn set_error(&mut self, key: QpKey) {
+        let qp_num = key.1;
+        let Some(qp) = self.qps.get_mut(&key) else {
+            return;
+        };
+        qp.attr.qp_state = verbs::ibv_qp_state::IBV_QPS_ERR;
+        unsafe { (*(qp.ptr as *mut verbs::ibv_qp)).state = verbs::ibv_qp_state::IBV_QPS_ERR };
+        let recvs = std::mem::take(&mut qp.recvs);
+        let recv_cq = qp.recv_cq;
+        for recv in recvs {
+            self.push_wc(
+                recv_cq,
+                verbs::ibv_wc {
+                    wr_id: recv.wr_id,
+                    status: verbs::ibv_wc_status::IBV_WC_WR_FLUSH_ERR,
+                    opcode: verbs::ibv_wc_opcode::IBV_WC_RECV,
+                    qp_num,
+                    ..Default::default()
+                },
+            );
+        }
+    }
+
+    fn find_mr(
+        &self,
+        device_index: usize,
+        addr: usize,
+        length: usize,
+        key: u32,
+        remote: bool,
+    ) -> Option<&MockMemoryRegion> {
+        self.mrs.values().find(|mr| {
+            mr.device_index == device_index
+                && (if remote { mr.rkey } else { mr.lkey }) == key
+                && mr.contains(addr, length)
+        })
+    }
+
+    /// Checks a remote access through a memory region, or a memory window bound on the responder.
+    fn remote_access(
+        &self,
+        device_index: usize,
+        qp_num: u32,
+        addr: usize,
+        length: usize,
+        rkey: u32,
+        flag: verbs::ibv_access_flags,
+    ) -> bool {
+        let by_mr = self
+            .find_mr(device_index, addr, length, rkey, true)
+            .is_some_and(|mr| has_access(mr.access, flag));
+        by_mr
+            || self.mws.values().any(|mw| {
+                mw.device_index == device_index
+                    && mw.rkey == rkey
+                    && mw.bound.as_ref().is_some_and(|bound| {
+                        bound.qp_num == qp_num
+                            && addr >= bound.addr
+                            && addr + length <= bound.addr + bound.length
+                            && has_access(bound.access, flag)
+                    })
+            })
+    }
+
+    /// Binds a type 2 memory window, which must not be bound already.
+    fn bind_mw(&mut self, qp_num: u32, wr: &verbs::ibv_send_wr) -> verbs::ibv_wc_status {
+        use verbs::ibv_wc_status;
+
+        let bind = unsafe { wr.__bindgen_anon_2.bind_mw };
+        let info = bind.bind_info;
+        let (addr, length) = (info.addr as usize, info.length as usize);
+        let bindable = self.mrs.get(&(info.mr as usize)).is_some_and(|mr| {
+            has_access(mr.access, verbs::ibv_access_flags::IBV_ACCESS_MW_BIND)
+                && mr.contains(addr, length)
+        });
+        let Some(mw) = self.mws.get_mut(&(bind.mw as usize)) else {
+            return ibv_wc_status::IBV_WC_MW_BIND_ERR;
+        };
+        if !bindable || mw.bound.is_some() {
+            return ibv_wc_status::IBV_WC_MW_BIND_ERR;
+        }
+        mw.rkey = bind.rkey;
+        mw.bound = Some(MockBinding {
+            qp_num,
+            addr,
+            length,
+            access: info.mw_access_flags,
+        });
+        unsafe { (*bind.mw).rkey = bind.rkey };
+        ibv_wc_status::IBV_WC_SUCCESS
+    }
+
+    fn invalidate_mw(&mut self, device_index: usize, rkey: u32) -> verbs::ibv_wc_status {
+        let mw = self
+            .mws
+            .values_mut()
+            .find(|mw| mw.device_index == device_index && mw.rkey == rkey && mw.bound.is_some());
+        match mw {
+            Some(mw) => {
+                mw.bound = None;
+                verbs::ibv_wc_status::IBV_WC_SUCCESS
+            }
+            None => verbs::ibv_wc_status::IBV_WC_MW_BIND_ERR,
+        }
+    }
+
+    fn gather(&self, device_index: usize, sges: &[verbs::ibv_sge]) -> Option<Vec<u8>> {
+        let mut data = vec![];
+        for sge in sges {
+            let (addr, length) = (sge.addr as usize, sge.length as usize);
+            self.find_mr(device_index, addr, length, sge.lkey, false)?;
+            data.extend_from_slice(unsafe {
+                std::slice::from_raw_parts(addr as *const u8, length)
+            });
+        }
+        Some(data)
+    }
+
+    fn scatter(
+        &self,
+        device_index: usize,
+        sges: &[verbs::ibv_sge],
+        data: &[u8],
+    ) -> std::result::Result<(), verbs::ibv_wc_status> {
+        let capacity = sges.iter().map(|sge| sge.length as usize).sum::<usize>();
+        if data.len() > capacity {
+            return Err(verbs::ibv_wc_status::IBV_WC_LOC_LEN_ERR);
+        }
+        let mut offset = 0;
+        for sge in sges {
+            let (addr, length) = (sge.addr as usize, sge.length as usize);
+            let writable = self
+                .find_mr(device_index, addr, length, sge.lkey, false)
+                .is_some_and(|mr| {
+                    has_access(mr.access, verbs::ibv_access_flags::IBV_ACCESS_LOCAL_WRITE)
+                });
+            if !writable {
+                return Err(verbs::ibv_wc_status::IBV_WC_LOC_PROT_ERR);
+            }
+            let len = length.min(data.len() - offset);
+            unsafe {
+                std::ptr::copy_nonoverlapping(data[offset..].as_ptr(), addr as *mut u8, len);
+            }
+            offset += len;
+        }
+        Ok(())
+    }
+
+    /// Executes a send work request immediately, returning the status, opcode and length of its completion.
+    fn execute_send(
+        &mut self,
+        key: QpKey,
+        wr: &verbs::ibv_send_wr,
+    ) -> (verbs::ibv_wc_status, verbs::ibv_wc_opcode, u32) {
+        use verbs::{ibv_qp_state::*, ibv_wc_opcode::*, ibv_wc_status, ibv_wr_opcode::*};
+
+        let (device_index, qp_num) = key;
+        let local = &self.qps[&key];
+        let dest = (
+            gid_device_index(&local.attr.ah_attr.grh.dgid),
+            local.attr.dest_qp_num,
+        );
+        let dest_qp_num = dest.1;
+        let sges = if wr.sg_list.is_null() || wr.num_sge <= 0 {
+            &[][..]
+        } else {
+            unsafe { std::slice::from_raw_parts(wr.sg_list, wr.num_sge as usize) }
+        };
+
+        let opcode = match wr.opcode {
+            IBV_WR_SEND | IBV_WR_SEND_WITH_IMM => IBV_WC_SEND,
+            IBV_WR_RDMA_WRITE => IBV_WC_RDMA_WRITE,
+            IBV_WR_RDMA_READ => IBV_WC_RDMA_READ,
+            IBV_WR_BIND_MW => return (self.bind_mw(qp_num, wr), IBV_WC_BIND_MW, 0),
+            IBV_WR_LOCAL_INV => {
+                let rkey = unsafe { wr.__bindgen_anon_1.invalidate_rkey };
+                return (self.invalidate_mw(device_index, rkey), IBV_WC_LOCAL_INV, 0);
+            }
+            _ => return (ibv_wc_status::IBV_WC_LOC_QP_OP_ERR, IBV_WC_SEND, 0),
+        };
+
+        let peer = match self.qps.get(&dest) {
+            Some(peer) if matches!(peer.attr.qp_state, IBV_QPS_RTR | IBV_QPS_RTS) => peer,
+            _ => return (ibv_wc_status::IBV_WC_RETRY_EXC_ERR, opcode, 0),
+        };
+        let peer_device_index = peer.device_index;
+        let peer_recv_cq = peer.recv_cq;
+
+        match wr.opcode {
+            IBV_WR_SEND | IBV_WR_SEND_WITH_IMM => {
+                let Some(data) = self.gather(device_index, sges) else {
+                    return (ibv_wc_status::IBV_WC_LOC_PROT_ERR, opcode, 0);
+                };
+                let peer = self.qps.get_mut(&dest).unwrap();
+                let Some(recv) = peer.recvs.pop_front() else {
+                    self.count(device_index, "rnr_nak_retry_err", 1);
+                    return (ibv_wc_status::IBV_WC_RNR_RETRY_EXC_ERR, opcode, 0);
+                };
+                let recv_status = match self.scatter(peer_device_index, &recv.sges, &data) {
+                    Ok(()) => ibv_wc_status::IBV_WC_SUCCESS,
+                    Err(status) => status,
+                };
+                let mut recv_wc = verbs::ibv_wc {
+                    wr_id: recv.wr_id,
+                    status: recv_status,
+                    opcode: IBV_WC_RECV,
+                    byte_len: data.len() as u32,
+                    qp_num: dest_qp_num,
+                    src_qp: qp_num,
+                    ..Default::default()
+                };
+                if wr.opcode == IBV_WR_SEND_WITH_IMM {
+                    recv_wc.wc_flags = verbs::ibv_wc_flags::IBV_WC_WITH_IMM.0;
+                    recv_wc.__bindgen_anon_1.imm_data = unsafe { wr.__bindgen_anon_1.imm_data };
+                }
+                self.push_wc(peer_recv_cq, recv_wc);
+                if recv_status != ibv_wc_status::IBV_WC_SUCCESS {
+                    self.set_error(dest);
+                    return (ibv_wc_status::IBV_WC_REM_INV_REQ_ERR, opcode, 0);
+                }
+                self.count_transfer(device_index, peer_device_index, data.len());
+                (ibv_wc_status::IBV_WC_SUCCESS, opcode, data.len() as u32)
+            }
+            IBV_WR_RDMA_WRITE => {
+                let Some(data) = self.gather(device_index, sges) else {
+                    return (ibv_wc_status::IBV_WC_LOC_PROT_ERR, opcode, 0);
+                };
+                let rdma = unsafe { wr.wr.rdma };
+                let remote_addr = rdma.remote_addr as usize;
+                let writable = self.remote_access(
+                    peer_device_index,
+                    dest_qp_num,
+                    remote_addr,
+                    data.len(),
+                    rdma.rkey,
+                    verbs::ibv_access_flags::IBV_ACCESS_REMOTE_WRITE,
+                );
+                if !writable {
+                    return (ibv_wc_status::IBV_WC_REM_ACCESS_ERR, opcode, 0);
+                }
+                unsafe {
+                    std::ptr::copy_nonoverlapping(data.as_ptr(), remote_addr as *mut u8, data.len())
+                };
+                self.count_transfer(device_index, peer_device_index, data.len());
+                (ibv_wc_status::IBV_WC_SUCCESS, opcode, data.len() as u32)
+            }
+            IBV_WR_RDMA_READ => {
+                let length = sges.iter().map(|sge| sge.length as usize).sum::<usize>();
+                let rdma = unsafe { wr.wr.rdma };
+                let remote_addr = rdma.remote_addr as usize;
+                let readable = self.remote_access(
+                    peer_device_index,
+                    dest_qp_num,
+                    remote_addr,
+                    length,
+                    rdma.rkey,
+                    verbs::ibv_access_flags::IBV_ACCESS_REMOTE_READ,
+                );
+                if !readable {
+                    return (ibv_wc_status::IBV_WC_REM_ACCESS_ERR, opcode, 0);
+                }
+                let data = unsafe { std::slice::from_raw_parts(remote_addr as *const u8, length) }
+                    .to_vec();
+                match self.scatter(device_index, sges, &data) {
+                    Ok(()) => {
+                        self.count_transfer(peer_device_index, device_index, length);
+                        (ibv_wc_status::IBV_WC_SUCCESS, opcode, length as u32)
+                    }
+                    Err(status) => (status, opcode, 0),
+                }
+            }
+            _ => unreachable!(),
+        }
+    }
+}
+
+/// An in-process verbs backend which emulates devices, protection domains, memory regions,
+/// type 2 memory windows, completion queues, completion channels and RC queue pairs with ordinary memory.
+/// Work requests are executed synchronously when posted, so tests are deterministic, and are tallied
+/// in the port counters.
+/// Shared receive queues, address handles and async events are not supported.
+pub struct MockVerbs {
+    devices: Vec<*mut verbs::ibv_device>,
+    state: Mutex<MockState>,
+    relaxed_ordering: bool,
+    odp: bool,
+    implicit_odp: bool,
+    max_mr_size: u64,
+    max_mr: usize,
+}
+
+unsafe impl Send for MockVerbs {}
+unsafe impl Sync for MockVerbs {}
+
+impl MockVerbs {
+    pub fn new(num_devices: usize) -> Self {
+        let mut devices = Vec::with_capacity(num_devices + 1);
+        for index in 0..num_devices {
+            let mut device = MaybeUninit::<verbs::ibv_device>::zeroed();
+            let ptr = device.as_mut_ptr();
+            let device = unsafe {
+                (*ptr).node_type = verbs::ibv_node_type::IBV_NODE_CA;
+                (*ptr).transport_type = verbs::ibv_transport_type::IBV_TRANSPORT_IB;
+                copy_str(&mut (*ptr).name, &format!("mock_{index}"));
+                copy_str(&mut (*ptr).dev_name, &format!("uverbs{index}"));
+                copy_str(
+                    &mut (*ptr).dev_path,
+                    &format!("/dev/infiniband/uverbs{index}"),
+                );
+                copy_str(
+                    &mut (*ptr).ibdev_path,
+                    &format!("/sys/class/infiniband/mock_{index}"),
+                );
+                device.assume_init()
+            };
+            devices.push(Box::into_raw(Box::new(device)));
+        }
+        devices.push(std::ptr::null_mut());
+
+        Self {
+            devices,
+            state: Default::default(),
+            relaxed_ordering: true,
+            odp: false,
+            implicit_odp: false,
+            max_mr_size: 1 << 40,
+            max_mr: 1024,
+        }
+    }
+
+    /// Sets the size and number of memory regions each device allows.
+    pub fn with_mr_limits(mut self, max_mr_size: u64, max_mr: usize) -> Self {
+        self.max_mr_size = max_mr_size;
+        self.max_mr = max_mr;
+        self
+    }
+
+    /// Sets whether memory registrations accept relaxed ordering, otherwise they fail with EINVAL.
+    pub fn with_relaxed_ordering(mut self, supported: bool) -> Self {
+        self.relaxed_ordering = supported;
+        self
+    }
+
+    /// Sets whether devices support on-demand paging, and implicit registration of the whole
+    /// address space. Without it, on-demand registrations fail with EOPNOTSUPP as on Soft-RoCE.
+    pub fn with_odp(mut self, supported: bool, implicit: bool) -> Self {
+        self.odp = supported;
+        self.implicit_odp = supported && implicit;
+        self
+    }
+
+    /// Opens `num_devices` mock devices.
+    pub fn devices(num_devices: usize) -> Result<Devices> {
+        Devices::open_with_backend(&DeviceConfig::default(), Arc::new(Self::new(num_devices)))
+    }
+
+    fn device_index(&self, device: *mut verbs::ibv_device) -> Option<usize> {
+        self.devices
+            .iter()
+            .position(|&d| !d.is_null() && d == device)
+    }
+
+    fn num_devices(&self) -> usize {
+        self.devices.len() - 1
+    }
+
+    unsafe fn qp_key(&self, qp: *mut verbs::ibv_qp) -> QpKey {
+        let device_index = self.device_index((*(*qp).context).device);
+        (device_index.unwrap_or_default(), (*qp).qp_num)
+    }
+}
+
+impl Drop for MockVerbs {
+    fn drop(&mut self) {
+        for &device in &self.devices {
+            if !device.is_null() {
+                drop(unsafe { Box::from_raw(device) });
+            }
+        }
+    }
+}
+
+impl VerbsBackend for MockVerbs {
+    fn name(&self) -> &str {
+        "mock"
+    }
+
+    unsafe fn get_device_list(&self, num_devices: *mut c_int) -> *mut *mut verbs::ibv_device {
+        if !num_devices.is_null() {
+            *num_devices = self.num_devices() as c_int;
+        }
+        self.devices.as_ptr() as *mut _
+    }
+
+    unsafe fn free_device_list(&self, _list: *mut *mut verbs::ibv_device) {}
+
+    unsafe fn get_device_guid(&self, device: *mut verbs::ibv_device) -> u64 {
+        let index = self.device_index(device).unwrap_or_default() as u64;
+        u64::to_be(0x0200_0000_0000_0000 | (index + 1))
+    }
+
+    unsafe fn open_device(&self, device: *mut verbs::ibv_device) -> *mut verbs::ibv_context {
+        let Some(index) = self.device_index(device) else {
+            return fail_null(libc::ENODEV);
+        };
+        let async_fd = libc::eventfd(0, libc::EFD_NONBLOCK | libc::EFD_CLOEXEC);
+        if async_fd < 0 {
+            return std::ptr::null_mut();
+        }
+
+        let mut context = MaybeUninit::<verbs::ibv_context>::zeroed();
+        let ptr = context.as_mut_ptr();
+        (*ptr).device = device;
+        (*ptr).cmd_fd = -1;
+        (*ptr).async_fd = async_fd;
+        (*ptr).num_comp_vectors = 1;
+        let ptr = Box::into_raw(Box::new(context.assume_init()));
+        self.state
+            .lock()
+            .unwrap()
+            .contexts
+            .insert(ptr as usize, index);
+        ptr
+    }
+
+    unsafe fn close_device(&self, context: *mut verbs::ibv_context) -> c_int {
+        if self
+            .state
+            .lock()
+            .unwrap()
+            .contexts
+            .remove(&(context as usize))
+            .is_none()
+        {
+            return fail(libc::EINVAL);
+        }
+        let context = Box::from_raw(context);
+        libc::close(context.async_fd);
+        0
+    }
+
+    unsafe fn query_device(
+        &self,
+        context: *mut verbs::ibv_context,
+        device_attr: *mut verbs::ibv_device_attr,
+    ) -> c_int {
+        if !self
+            .state
+            .lock()
+            .unwrap()
+            .contexts
+            .contains_key(&(context as usize))
+        {
+            return fail(libc::EINVAL);
+        }
+        let mut attr = verbs::ibv_device_attr {
+            node_guid: self.get_device_guid((*context).device),
+            sys_image_guid: self.get_device_guid((*context).device),
+            max_mr_size: self.max_mr_size,
+            page_size_cap: 4096,
+            max_qp: 1024,
+            max_qp_wr: 4096,
+            max_sge: 16,
+            max_sge_rd: 16,
+            max_cq: 1024,
+            max_cqe: 65536,
+            max_mr: self.max_mr as c_int,
+            max_pd: 1024,
+            max_qp_rd_atom: 16,
+            max_qp_init_rd_atom: 16,
+            max_pkeys: 1,
+            phys_port_cnt: MOCK_PORT_NUM,
+            ..Default::default()
+        };
+        copy_str(&mut attr.fw_ver, "mock");
+        *device_attr = attr;
+        0
+    }
+
+    unsafe fn query_device_ex(
+        &self,
+        context: *mut verbs::ibv_context,
+        device_attr: *mut verbs::ibv_device_attr_ex,
+    ) -> c_int {
+        use verbs::{ibv_odp_general_caps::*, ibv_odp_transport_cap_bits::*};
+
+        let mut attr = verbs::ibv_device_attr_ex::default();
+        let ret = self.query_device(context, &mut attr.orig_attr);
+        if ret != 0 {
+            return ret;
+        }
+        if self.odp {
+            let mut general_caps = IBV_ODP_SUPPORT;
+            if self.implicit_odp {
+                general_caps |= IBV_ODP_SUPPORT_IMPLICIT;
+            }
+            attr.odp_caps.general_caps = general_caps.0 as u64;
+            attr.odp_caps.per_transport_caps.rc_odp_caps = (IBV_ODP_SUPPORT_SEND
+                | IBV_ODP_SUPPORT_RECV
+                | IBV_ODP_SUPPORT_WRITE
+                | IBV_ODP_SUPPORT_READ
+                | IBV_ODP_SUPPORT_ATOMIC)
+                .0;
+        }
+        *device_attr = attr;
+        0
+    }
+
+    unsafe fn query_port(
+        &self,
+        _context: *mut verbs::ibv_context,
+        port_num: u8,
+        port_attr: *mut verbs::ibv_port_attr,
+    ) -> c_int {
+        if port_num != MOCK_PORT_NUM {
+            return fail(libc::EINVAL);
+        }
+        *port_attr = verbs::ibv_port_attr {
+            state: verbs::ibv_port_state::IBV_PORT_ACTIVE,
+            max_mtu: verbs::ibv_mtu::IBV_MTU_4096,
+            active_mtu: verbs::ibv_mtu::IBV_MTU_1024,
+            gid_tbl_len: MOCK_GID_TBL_LEN,
+            max_msg_sz: 1 << 31,
+            pkey_tbl_len: 1,
+            link_layer: verbs::IBV_LINK_LAYER::ETHERNET as u8,
+            active_width: 2,
+            active_speed: 32,
+            phys_state: 5,
+            ..Default::default()
+        };
+        0
+    }
+
+    unsafe fn query_gid(
+        &self,
+        context: *mut verbs::ibv_context,
+        port_num: u8,
+        index: c_int,
+        gid: *mut verbs::ibv_gid,
+    ) -> c_int {
+        if port_num != MOCK_PORT_NUM || !(0..MOCK_GID_TBL_LEN).contains(&index) {
+            return fail(libc::EINVAL);
+        }
+        let device_index = self.device_index((*context).device).unwrap_or_default() as u16;
+        let ip = if index == 0 {
+            Ipv6Addr::new(0xfe80, 0, 0, 0, 0, 0, 0, device_index + 1)
+        } else {
+            Ipv6Addr::new(0, 0, 0, 0, 0, 0xffff, 0x0a00, device_index + 1)
+        };
+        (*gid).raw = ip.octets();
+        0
+    }
+
+    fn read_gid_type(
+        &self,
+        _ibdev_path: &Path,
+        _port_num: u8,
+        _gid_index: u16,
+    ) -> std::io::Result<String> {
+        Ok("RoCE v2\n".to_string())
+    }
+
+    fn read_port_counters(
+        &self,
+        ibdev_path: &Path,
+        port_num: u8,
+    ) -> std::io::Result<Vec<(String, u64)>> {
+        let device_index = ibdev_path
+            .file_name()
+            .and_then(|name| name.to_str()?.strip_prefix("mock_")?.parse::<usize>().ok())
+            .filter(|&index| index < self.num_devices() && port_num == MOCK_PORT_NUM)
+            .ok_or(std::io::ErrorKind::NotFound)?;
+        let state = self.state.lock().unwrap();
+        Ok(MOCK_COUNTERS
+            .iter()
+            .map(|&name| {
+                let value = state.counters.get(&(device_index, name)).copied();
+                (name.to_string(), value.unwrap_or_default())
+            })
+            .collect())
+    }
+
+    unsafe fn get_async_event(
+        &self,
+        _context: *mut verbs::ibv_context,
+        _event: *mut verbs::ibv_async_event,
+    ) -> c_int {
+        fail(libc::EAGAIN);
+        -1
+    }
+
+    unsafe fn ack_async_event(&self, _event: *mut verbs::ibv_async_event) {}
+
+    unsafe fn alloc_pd(&self, context: *mut verbs::ibv_context) -> *mut verbs::ibv_pd {
+        let mut state = self.state.lock().unwrap();
+        let Some(&device_index) = state.contexts.get(&(context as usize)) else {
+            return fail_null(libc::EINVAL);
+        };
+        let handle = state.next_handle();
+        let ptr = Box::into_raw(Box::new(verbs::ibv_pd { context, handle }));
+        state.pds.insert(ptr as usize, device_index);
+        ptr
+    }
+
+    unsafe fn dealloc_pd(&self, pd: *mut verbs::ibv_pd) -> c_int {
+        if self
+            .state
+            .lock()
+            .unwrap()
+            .pds
+            .remove(&(pd as usize))
+            .is_none()
+        {
+            return fail(libc::EINVAL);
+        }
+        drop(Box::from_raw(pd));
+        0
+    }
+
+    unsafe fn reg_mr(
+        &self,
+        pd: *mut verbs::ibv_pd,
+        addr: *mut c_void,
+        length: usize,
+        access: c_int,
+    ) -> *mut verbs::ibv_mr {
+        let mut state = self.state.lock().unwrap();
+        let Some(&device_index) = state.pds.get(&(pd as usize)) else {
+            return fail_null(libc::EINVAL);
+        };
+        use verbs::ibv_access_flags::*;
+        let access = access as u32;
+        let needs_local_write = IBV_ACCESS_REMOTE_WRITE.0 | IBV_ACCESS_REMOTE_ATOMIC.0;
+        if access & needs_local_write != 0 && access & IBV_ACCESS_LOCAL_WRITE.0 == 0 {
+            return fail_null(libc::EINVAL);
+        }
+        if !self.relaxed_ordering && access & IBV_ACCESS_RELAXED_ORDERING.0 != 0 {
+            return fail_null(libc::EINVAL);
+        }
+        let on_demand = access & IBV_ACCESS_ON_DEMAND.0 != 0;
+        if on_demand && !self.odp {
+            return fail_null(libc::EOPNOTSUPP);
+        }
+        // a null address with the maximum length registers the whole address space.
+        let implicit = addr.is_null() && length == usize::MAX;
+        if implicit && !(on_demand && self.implicit_odp) {
+            return fail_null(libc::EINVAL);
+        }
+        if length as u64 > self.max_mr_size && !implicit {
+            return fail_null(libc::EINVAL);
+        }
+        let num_mrs = state
+            .mrs
+            .values()
+            .filter(|mr| mr.device_index == device_index)
+            .count();
+        if num_mrs >= self.max_mr {
+            return fail_null(libc::ENOMEM);
+        }
+        let handle = state.next_handle();
+        let ptr = Box::into_raw(Box::new(verbs::ibv_mr {
+            context: (*pd).context,
+            pd,
+            addr,
+            length,
+            handle,
+            lkey: handle,
+            rkey: handle,
+        }));
+        state.mrs.insert(
+            ptr as usize,
+            MockMemoryRegion {
+                device_index,
+                addr: addr as usize,
+                length,
+                access,
+                lkey: handle,
+                rkey: handle,
+            },
+        );
+        ptr
+    }
+
+    unsafe fn dereg_mr(&self, mr: *mut verbs::ibv_mr) -> c_int {
+        if self
+            .state
+            .lock()
+            .unwrap()
+            .mrs
+            .remove(&(mr as usize))
+            .is_none()
+        {
+            return fail(libc::EINVAL);
+        }
+        drop(Box::from_raw(mr));
+        0
+    }
+
+    unsafe fn alloc_mw(
+        &self,
+        pd: *mut verbs::ibv_pd,
+        mw_type: verbs::ibv_mw_type,
+    ) -> *mut verbs::ibv_mw {
+        let mut state = self.state.lock().unwrap();
+        let Some(&device_index) = state.pds.get(&(pd as usize)) else {
+            return fail_null(libc::EINVAL);
+        };
+        if mw_type != verbs::ibv_mw_type::IBV_MW_TYPE_2 {
+            return fail_null(libc::EOPNOTSUPP);
+        }
+        let handle = state.next_handle();
+        let rkey = 0x8000_0000 | (handle << 8);
+        let ptr = Box::into_raw(Box::new(verbs::ibv_mw {
+            context: (*pd).context,
+            pd,
+            rkey,
+            handle,
+            type_: mw_type,
+        }));
+        state.mws.insert(
+            ptr as usize,
+            MockMemoryWindow {
+                device_index,
+                rkey,
+                bound: None,
+            },
+        );
+        ptr
+    }
+
+    unsafe fn dealloc_mw(&self, mw: *mut verbs::ibv_mw) -> c_int {
+        if self
+            .state
+            .lock()
+            .unwrap()
+            .mws
+            .remove(&(mw as usize))
+            .is_none()
+        {
+            return fail(libc::EINVAL);
+        }
+        drop(Box::from_raw(mw));
+        0
+    }
+
+    unsafe fn create_comp_channel(
+        &self,
+        context: *mut verbs::ibv_context,
+    ) -> *mut verbs::ibv_comp_channel {
+        let mut state = self.state.lock().unwrap();
+        if !state.contexts.contains_key(&(context as usize)) {
+            return fail_null(libc::EINVAL);
+        }
+        let fd = libc::eventfd(
+            0,
+            libc::EFD_NONBLOCK | libc::EFD_CLOEXEC | libc::EFD_SEMAPHORE,
+        );
+        if fd < 0 {
+            return std::ptr::null_mut();
+        }
+        let ptr = Box::into_raw(Box::new(verbs::ibv_comp_channel {
+            context,
+            fd,
+            refcnt: 0,
+        }));
+        state.channels.insert(
+            ptr as usize,
+            MockCompChannel {
+                fd,
+                events: VecDeque::new(),
+            },
+        );
+        ptr
+    }
+
+    unsafe fn destroy_comp_channel(&self, channel: *mut verbs::ibv_comp_channel) -> c_int {
+        let mut state = self.state.lock().unwrap();
+        if state.cqs.values().any(|cq| cq.channel == channel as usize) {
+            return fail(libc::EBUSY);
+        }
+        let Some(mock_channel) = state.channels.remove(&(channel as usize)) else {
+            return fail(libc::EINVAL);
+        };
+        libc::close(mock_channel.fd);
+        drop(Box::from_raw(channel));
+        0
+    }
+
+    unsafe fn get_cq_event(
+        &self,
+        channel: *mut verbs::ibv_comp_channel,
+        cq: *mut *mut verbs::ibv_cq,
+        cq_context: *mut *mut c_void,
+    ) -> c_int {
+        let mut state = self.state.lock().unwrap();
+        let Some(mock_channel) = state.channels.get_mut(&(channel as usize)) else {
+            fail(libc::EINVAL);
+            return -1;
+        };
+        let Some(event) = mock_channel.events.pop_front() else {
+            fail(libc::EAGAIN);
+            return -1;
+        };
+        let mut value = 0u64;
+        libc::read(mock_channel.fd, &mut value as *mut u64 as *mut c_void, 8);
+        *cq = event as *mut verbs::ibv_cq;
+        *cq_context = (**cq).cq_context;
+        0
+    }
+
+    unsafe fn ack_cq_events(&self, _cq: *mut verbs::ibv_cq, _num_events: c_uint) {}
+
+    unsafe fn create_cq(
+        &self,
+        context: *mut verbs::ibv_context,
+        cqe: c_int,
+        cq_context: *mut c_void,
+        channel: *mut verbs::ibv_comp_channel,
+        _comp_vector: c_int,
+    ) -> *mut verbs::ibv_cq {
+        let mut state = self.state.lock().unwrap();
+        if !state.contexts.contains_key(&(context as usize))
+            || cqe <= 0
+            || !(channel.is_null() || state.channels.contains_key(&(channel as usize)))
+        {
+            return fail_null(libc::EINVAL);
+        }
+        let mut cq = MaybeUninit::<verbs::ibv_cq>::zeroed();
+        let ptr = cq.as_mut_ptr();
+        (*ptr).context = context;
+        (*ptr).channel = channel;
+        (*ptr).cq_context = cq_context;
+        (*ptr).handle = state.next_handle();
+        (*ptr).cqe = cqe;
+        let ptr = Box::into_raw(Box::new(cq.assume_init()));
+        state.cqs.insert(
+            ptr as usize,
+            MockCompQueue {
+                channel: channel as usize,
+                ..Default::default()
+            },
+        );
+        ptr
+    }
+
+    unsafe fn destroy_cq(&self, cq: *mut verbs::ibv_cq) -> c_int {
+        if self
+            .state
+            .lock()
+            .unwrap()
+            .cqs
+            .remove(&(cq as usize))
+            .is_none()
+        {
+            return fail(libc::EINVAL);
+        }
+        drop(Box::from_raw(cq));
+        0
+    }
+
+    unsafe fn poll_cq(
+        &self,
+        cq: *mut verbs::ibv_cq,
+        num_entries: c_int,
+        wc: *mut verbs::ibv_wc,
+    ) -> c_int {
+        let mut state = self.state.lock().unwrap();
+        let Some(comp_queue) = state.cqs.get_mut(&(cq as usize)) else {
+            return -fail(libc::EINVAL);
+        };
+        let mut num = 0;
+        while num < num_entries.max(0) as usize {
+            let Some(entry) = comp_queue.entries.pop_front() else {
+                break;
+            };
+            *wc.add(num) = entry;
+            num += 1;
+        }
+        num as c_int
+    }
+
+    unsafe fn req_notify_cq(&self, cq: *mut verbs::ibv_cq, _solicited_only: c_int) -> c_int {
+        let mut state = self.state.lock().unwrap();
+        match state.cqs.get_mut(&(cq as usize)) {
+            Some(comp_queue) if comp_queue.channel != 0 => {
+                comp_queue.armed = true;
+                0
+            }
+            _ => fail(libc::EINVAL),
+        }
+    }
+
+    unsafe fn create_qp(
+        &self,
+        pd: *mut verbs::ibv_pd,
+        init_attr: *mut verbs::ibv_qp_init_attr,
+    ) -> *mut verbs::ibv_qp {
+        let mut state = self.state.lock().unwrap();
+        let Some(&device_index) = state.pds.get(&(pd as usize)) else {
+            return fail_null(libc::EINVAL);
+        };
+        let init_attr = &*init_attr;
+        if init_attr.qp_type != verbs::ibv_qp_type::IBV_QPT_RC || !init_attr.srq.is_null() {
+            return fail_null(libc::EOPNOTSUPP);
+        }
+        if !state.cqs.contains_key(&(init_attr.send_cq as usize))
+            || !state.cqs.contains_key(&(init_attr.recv_cq as usize))
+        {
+            return fail_null(libc::EINVAL);
+        }
+
+        let handle = state.next_handle();
+        // numbered per device like real devices, so the same number appears on several devices.
+        let next_qp_num = state.next_qp_nums.entry(device_index).or_insert(0x100);
+        *next_qp_num += 1;
+        let qp_num = *next_qp_num;
+        let mut qp = MaybeUninit::<verbs::ibv_qp>::zeroed();
+        let ptr = qp.as_mut_ptr();
+        (*ptr).context = (*pd).context;
+        (*ptr).qp_context = init_attr.qp_context;
+        (*ptr).pd = pd;
+        (*ptr).send_cq = init_attr.send_cq;
+        (*ptr).recv_cq = init_attr.recv_cq;
+        (*ptr).handle = handle;
+        (*ptr).qp_num = qp_num;
+        (*ptr).state = verbs::ibv_qp_state::IBV_QPS_RESET;
+        (*ptr).qp_type = verbs::ibv_qp_type::IBV_QPT_RC;
+        let ptr = Box::into_raw(Box::new(qp.assume_init()));
+
+        state.qps.insert(
+            (device_index, qp_num),
+            MockQueuePair {
+                ptr: ptr as usize,
+                device_index,
+                send_cq: init_attr.send_cq as usize,
+                recv_cq: init_attr.recv_cq as usize,
+                sq_sig_all: init_attr.sq_sig_all != 0,
+                cap: init_attr.cap,
+                attr: verbs::ibv_qp_attr {
+                    qp_state: verbs::ibv_qp_state::IBV_QPS_RESET,
+                    cap: init_attr.cap,
+                    ..Default::default()
+                },
+                recvs: VecDeque::new(),
+            },
+        );
+        ptr
+    }
+
+    unsafe fn destroy_qp(&self, qp: *mut verbs::ibv_qp) -> c_int {
+        if self
+            .state
+            .lock()
+            .unwrap()
+            .qps
+            .remove(&self.qp_key(qp))
+            .is_none()
+        {
+            return fail(libc::EINVAL);
+        }
+        drop(Box::from_raw(qp));
+        0
+    }
+
+    unsafe fn modify_qp(
+        &self,
+        qp: *mut verbs::ibv_qp,
+        attr: *mut verbs::ibv_qp_attr,
+        attr_mask: c_int,
+    ) -> c_int {
+        use verbs::{ibv_qp_attr_mask as mask, ibv_qp_state::*};
+
+        let key = self.qp_key(qp);
+        let attr = &*attr;
+        let attr_mask = attr_mask as u32;
+        let has = |m: verbs::ibv_qp_attr_mask| attr_mask & m.0 != 0;
+
+        let mut state = self.state.lock().unwrap();
+        let Some(mock_qp) = state.qps.get_mut(&key) else {
+            return fail(libc::EINVAL);
+        };
+        if has(mask::IBV_QP_STATE) {
+            let valid = matches!(
+                (mock_qp.attr.qp_state, attr.qp_state),
+                (_, IBV_QPS_RESET | IBV_QPS_ERR)
+                    | (IBV_QPS_RESET | IBV_QPS_INIT, IBV_QPS_INIT)
+                    | (IBV_QPS_INIT, IBV_QPS_RTR)
+                    | (IBV_QPS_RTR | IBV_QPS_RTS, IBV_QPS_RTS)
+            );
+            if !valid {
+                return fail(libc::EINVAL);
+            }
+        }
+
+        let stored = &mut mock_qp.attr;
+        if has(mask::IBV_QP_PKEY_INDEX) {
+            stored.pkey_index = attr.pkey_index;
+        }
+        if has(mask::IBV_QP_PORT) {
+            stored.port_num = attr.port_num;
+        }
+        if has(mask::IBV_QP_ACCESS_FLAGS) {
+            stored.qp_access_flags = attr.qp_access_flags;
+        }
+        if has(mask::IBV_QP_QKEY) {
+            stored.qkey = attr.qkey;
+        }
+        if has(mask::IBV_QP_AV) {
+            stored.ah_attr = attr.ah_attr;
+        }
+        if has(mask::IBV_QP_PATH_MTU) {
+            stored.path_mtu = attr.path_mtu;
+        }
+        if has(mask::IBV_QP_DEST_QPN) {
+            stored.dest_qp_num = attr.dest_qp_num;
+        }
+        if has(mask::IBV_QP_RQ_PSN) {
+            stored.rq_psn = attr.rq_psn;
+        }
+        if has(mask::IBV_QP_SQ_PSN) {
+            stored.sq_psn = attr.sq_psn;
+        }
+        if has(mask::IBV_QP_TIMEOUT) {
+            stored.timeout = attr.timeout;
+        }
+        if has(mask::IBV_QP_RETRY_CNT) {
+            stored.retry_cnt = attr.retry_cnt;
+        }
+        if has(mask::IBV_QP_RNR_RETRY) {
+            stored.rnr_retry = attr.rnr_retry;
+        }
+        if has(mask::IBV_QP_MIN_RNR_TIMER) {
+            stored.min_rnr_timer = attr.min_rnr_timer;
+        }
+        if has(mask::IBV_QP_MAX_QP_RD_ATOMIC) {
+            stored.max_rd_atomic = attr.max_rd_atomic;
+        }
+        if has(mask::IBV_QP_MAX_DEST_RD_ATOMIC) {
+            stored.max_dest_rd_atomic = attr.max_dest_rd_atomic;
+        }
+
+        if has(mask::IBV_QP_STATE) {
+            match attr.qp_state {
+                IBV_QPS_ERR => state.set_error(key),
+                new_state => {
+                    mock_qp.attr.qp_state = new_state;
+                    (*qp).state = new_state;
+                    if new_state == IBV_QPS_RESET {
+                        mock_qp.recvs.clear();
+                    }
+                }
+            }
+        }
+        0
+    }
+
+    unsafe fn query_qp(
+        &self,
+        qp: *mut verbs::ibv_qp,
+        attr: *mut verbs::ibv_qp_attr,
+        _attr_mask: c_int,
+        init_attr: *mut verbs::ibv_qp_init_attr,
+    ) -> c_int {
+        let state = self.state.lock().unwrap();
+        let Some(mock_qp) = state.qps.get(&self.qp_key(qp)) else {
+            return fail(libc::EINVAL);
+        };
+        *attr = mock_qp.attr;
+        *init_attr = verbs::ibv_qp_init_attr {
+            qp_context: (*qp).qp_context,
+            send_cq: (*qp).send_cq,
+            recv_cq: (*qp).recv_cq,
+            srq: std::ptr::null_mut(),
+            cap: mock_qp.cap,
+            qp_type: verbs::ibv_qp_type::IBV_QPT_RC,
+            sq_sig_all: mock_qp.sq_sig_all as c_int,
+        };
+        0
+    }
+
+    unsafe fn post_send(
+        &self,
+        qp: *mut verbs::ibv_qp,
+        wr: *mut verbs::ibv_send_wr,
+        bad_wr: *mut *mut verbs::ibv_send_wr,
+    ) -> c_int {
+        use verbs::{ibv_qp_state::*, ibv_wc_status};
+
+        let key = self.qp_key(qp);
+        let qp_num = key.1;
+        let mut state = self.state.lock().unwrap();
+        let mut wr_ptr = wr;
+        while !wr_ptr.is_null() {
+            let wr = &*wr_ptr;
+            let Some(mock_qp) = state.qps.get(&key) else {
+                *bad_wr = wr_ptr;
+                return fail(libc::EINVAL);
+            };
+            let send_cq = mock_qp.send_cq;
+            let signaled = mock_qp.sq_sig_all
+                || wr.send_flags & verbs::ibv_send_flags::IBV_SEND_SIGNALED.0 != 0;
+
+            let (status, opcode, byte_len) = match mock_qp.attr.qp_state {
+                IBV_QPS_ERR => (
+                    ibv_wc_status::IBV_WC_WR_FLUSH_ERR,
+                    verbs::ibv_wc_opcode::IBV_WC_SEND,
+                    0,
+                ),
+                IBV_QPS_RTS if wr.num_sge as u32 <= mock_qp.cap.max_send_sge => {
+                    state.execute_send(key, wr)
+                }
+                _ => {
+                    *bad_wr = wr_ptr;
+                    return fail(libc::EINVAL);
+                }
+            };
+
+            if status != ibv_wc_status::IBV_WC_SUCCESS || signaled {
+                state.push_wc(
+                    send_cq,
+                    verbs::ibv_wc {
+                        wr_id: wr.wr_id,
+                        status,
+                        opcode,
+                        byte_len,
+                        qp_num,
+                        ..Default::default()
+                    },
+                );
+            }
+            if status != ibv_wc_status::IBV_WC_SUCCESS
+                && status != ibv_wc_status::IBV_WC_WR_FLUSH_ERR
+            {
+                state.set_error(key);
+            }
+            wr_ptr = wr.next;
+        }
+        0
+    }
+
+    unsafe fn post_recv(
+        &self,
+        qp: *mut verbs::ibv_qp,
+        wr: *mut verbs::ibv_recv_wr,
+        bad_wr: *mut *mut verbs::ibv_recv_wr,
+    ) -> c_int {
+        use verbs::ibv_qp_state::*;
+
+        let key = self.qp_key(qp);
+        let qp_num = key.1;
+        let mut state = self.state.lock().unwrap();
+        let mut wr_ptr = wr;
+        while !wr_ptr.is_null() {
+            let wr = &*wr_ptr;
+            let Some(mock_qp) = state.qps.get_mut(&key) else {
+                *bad_wr = wr_ptr;
+                return fail(libc::EINVAL);
+            };
+            match mock_qp.attr.qp_state {
+                IBV_QPS_RESET => {
+                    *bad_wr = wr_ptr;
+                    return fail(libc::EINVAL);
+                }
+                IBV_QPS_ERR => {
+                    let recv_cq = mock_qp.recv_cq;
+                    state.push_wc(
+                        recv_cq,
+                        verbs::ibv_wc {
+                            wr_id: wr.wr_id,
+                            status: verbs::ibv_wc_status::IBV_WC_WR_FLUSH_ERR,
+                            opcode: verbs::ibv_wc_opcode::IBV_WC_RECV,
+                            qp_num,
+                            ..Default::default()
+                        },
+                    );
+                }
+                _ => {
+                    if wr.num_sge as u32 > mock_qp.cap.max_recv_sge {
+                        *bad_wr = wr_ptr;
+                        return fail(libc::EINVAL);
+                    }
+                    if mock_qp.recvs.len() >= mock_qp.cap.max_recv_wr as usize {
+                        *bad_wr = wr_ptr;
+                        return fail(libc::ENOMEM);
+                    }
+                    let sges = if wr.sg_list.is_null() || wr.num_sge <= 0 {
+                        vec![]
+                    } else {
+                        std::slice::from_raw_parts(wr.sg_list, wr.num_sge as usize).to_vec()
+                    };
+                    mock_qp.recvs.push_back(MockRecv {
+                        wr_id: wr.wr_id,
+                        sges,
+                    });
+                }
+            }
+            wr_ptr = wr.next;
+        }
+        0
+    }
+
+    unsafe fn create_srq(
+        &self,
+        _pd: *mut verbs::ibv_pd,
+        _init_attr: *mut verbs::ibv_srq_init_attr,
+    ) -> *mut verbs::ibv_srq {
+        fail_null(libc::EOPNOTSUPP)
+    }
+
+    unsafe fn destroy_srq(&self, _srq: *mut verbs::ibv_srq) -> c_int {
+        fail(libc::EOPNOTSUPP)
+    }
+
+    unsafe fn modify_srq(
+        &self,
+        _srq: *mut verbs::ibv_srq,
+        _attr: *mut verbs::ibv_srq_attr,
+        _attr_mask: c_int,
+    ) -> c_int {
+        fail(libc::EOPNOTSUPP)
+    }
+
+    unsafe fn post_srq_recv(
+        &self,
+        _srq: *mut verbs::ibv_srq,
+        _wr: *mut verbs::ibv_recv_wr,
+        _bad_wr: *mut *mut verbs::ibv_recv_wr,
+    ) -> c_int {
+        fail(libc::EOPNOTSUPP)
+    }
+
+    unsafe fn create_ah(
+        &self,
+        _pd: *mut verbs::ibv_pd,
+        _attr: *mut verbs::ibv_ah_attr,
+    ) -> *mut verbs::ibv_ah {
+        fail_null(libc::EOPNOTSUPP)
+    }
+
+    unsafe fn destroy_ah(&self, _ah: *mut verbs::ibv_ah) -> c_int {
+        fail(libc::EOPNOTSUPP)
+    }
+}
+
+/// Setup shared by the tests of the crate.
+#[cfg(test)]
+pub(crate) mod testing {
+    use crate::*;
+    use std::sync::Arc;
+
+    /// The capacity of the queue pairs created by tests.
+    pub(crate) fn cap() -> verbs::ibv_qp_cap {
+        verbs::ibv_qp_cap {
+            max_send_wr: 64,
+            max_recv_wr: 64,
+            max_send_sge: 1,
+            max_recv_sge: 1,
+            max_inline_data: 0,
+        }
+    }
+
+    /// Creates two sockets on the first device, whose queue pairs use the given completion queues.
+    pub(crate) fn socket_pair(
+        devices: &Devices,
+        comp_queues_a: &Arc<CompQueues>,
+        comp_queues_b: &Arc<CompQueues>,
+        create: impl Fn(Arc<QueuePair>) -> Socket,
+    ) -> (Socket, Socket) {
+        let queue_pair_a = QueuePair::create(devices, 0, comp_queues_a, cap()).unwrap();
+        let queue_pair_b = QueuePair::create(devices, 0, comp_queues_b, cap()).unwrap();
+        (
+            create(Arc::new(queue_pair_a)),
+            create(Arc::new(queue_pair_b)),
+        )
+    }
+
+    /// Moves both sockets to ready to send, connected to each other.
+    pub(crate) fn connect(socket_a: &Socket, socket_b: &Socket) {
+        socket_a.init(socket_b.endpoint()).unwrap();
+        socket_b.init(socket_a.endpoint()).unwrap();
+    }
+
+    /// Opens `num_devices` mock devices and connects two sockets on the first one, which share a
+    /// set of completion queues.
+    pub(crate) fn connected_sockets(
+        num_devices: usize,
+    ) -> (Devices, Arc<CompQueues>, Socket, Socket) {
+        let devices = MockVerbs::devices(num_devices).unwrap();
+        let comp_queues = CompQueues::create(&devices, 128).unwrap();
+        let (socket_a, socket_b) =
+            socket_pair(&devices, &comp_queues, &comp_queues, Socket::create);
+        connect(&socket_a, &socket_b);
+        (devices, comp_queues, socket_a, socket_b)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        testing::{cap, connected_sockets},
+        *,
+    };
+    use crate::*;
+
+    #[test]
+    fn test_mock_devices() {
+        let devices = MockVerbs::devices(2).unwrap();
+        assert_eq!(devices.len(), 2);
+        for (index, device) in devices.iter().enumerate() {
+            let info = device.info();
+            assert_eq!(info.name, format!("mock_{index}"));
+            assert_eq!(info.ports.len(), 1);
+            assert_eq!(info.ports[0].state(), PortState::Active);
+            assert_eq!(info.ports[0].gids.len(), 2);
+            assert_eq!(info.attr().fw_ver, "mock");
+        }
+        assert!(devices
+            .refresh(&DeviceConfig::default())
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn test_mock_send_recv() {
+        let (devices, comp_queues, socket_a, socket_b) = connected_sockets(1);
+        assert_eq!(socket_a.query().unwrap().state, QueuePairState::ReadyToSend);
+        assert_eq!(socket_a.query().unwrap().dest_qp_num, socket_b.qp_num());
+
+        let buffer_pool = BufferPool::create(4096, 4, &devices).unwrap();
+        let recv_buf = buffer_pool.allocate().unwrap();
+        let recv_slice: &[u8] = unsafe { std::mem::transmute(&*recv_buf) };
+        socket_b.post_recv(1, recv_buf).unwrap();
+
+        let mut send_buf = buffer_pool.allocate().unwrap();
+        send_buf.fill(7);
+        socket_a.post_send(2, send_buf).unwrap();
+
+        let mut wcs = vec![verbs::ibv_wc::default(); 128];
+        let comp = comp_queues.poll_cq(&mut wcs).unwrap();
+        assert_eq!(comp.len(), 2);
+        let send = comp
+            .iter()
+            .find(|wc| wc.qp_num == socket_a.qp_num())
+            .unwrap();
+        assert_eq!(send.wr_id, 2);
+        assert_eq!(send.result().unwrap(), 4096);
+
+        let recv = comp
+            .iter()
+            .find(|wc| wc.qp_num == socket_b.qp_num())
+            .unwrap();
+        assert_eq!(recv.wr_id, 1);
+        assert_eq!(recv.src_qp, socket_a.qp_num());
+        assert_eq!(recv.result().unwrap(), 4096);
+        assert!(recv_slice.iter().all(|&x| x == 7));
+    }
+
+    #[test]
+    fn test_mock_rdma_write_read() {
+        let (devices, comp_queues, socket_a, socket_b) = connected_sockets(1);
+        let queue_pair_a = socket_a.queue_pair();
+
+        let buffer_pool = BufferPool::create(4096, 4, &devices).unwrap();
+        let mut local = buffer_pool.allocate().unwrap();
+        let mut remote = buffer_pool.allocate().unwrap();
+        local.fill(1);
+        remote.fill(0);
+
+        let device = &devices[0];
+        let mut sge = verbs::ibv_sge {
+            addr: local.as_ptr() as _,
+            length: local.len() as _,
+            lkey: local.lkey(device),
+        };
+        let mut wr = verbs::ibv_send_wr {
+            wr_id: 1,
+            sg_list: &mut sge,
+            num_sge: 1,
+            opcode: verbs::ibv_wr_opcode::IBV_WR_RDMA_WRITE,
+            send_flags: verbs::ibv_send_flags::IBV_SEND_SIGNALED.0,
+            ..Default::default()
+        };
+        wr.wr.rdma.remote_addr = remote.as_ptr() as _;
+        wr.wr.rdma.rkey = remote.rkey(device);
+        assert_eq!(queue_pair_a.post_send(&mut wr), 0);
+        assert!(remote.iter().all(|&x| x == 1));
+
+        remote.fill(2);
+        wr.wr_id = 2;
+        wr.opcode = verbs::ibv_wr_opcode::IBV_WR_RDMA_READ;
+        assert_eq!(queue_pair_a.post_send(&mut wr), 0);
+        assert!(local.iter().all(|&x| x == 2));
+
+        let mut wcs = vec![verbs::ibv_wc::default(); 128];
+        let comp = comp_queues.poll_cq(&mut wcs).unwrap();
+        assert_eq!(comp.len(), 2);
+        assert_eq!(comp[0].opcode, verbs::ibv_wc_opcode::IBV_WC_RDMA_WRITE);
+        assert_eq!(comp[1].opcode, verbs::ibv_wc_opcode::IBV_WC_RDMA_READ);
+        assert!(comp.iter().all(|wc| wc.result().is_ok()));
+
+        // an invalid rkey fails with a remote access error and moves the queue pair into the error state.
+        wr.wr_id = 3;
+        wr.wr.rdma.rkey = 0;
+        assert_eq!(queue_pair_a.post_send(&mut wr), 0);
+        let comp = comp_queues.poll_cq(&mut wcs).unwrap();
+        assert_eq!(comp.len(), 1);
+        assert_eq!(comp[0].wc_status(), WcStatus::RemoteAccessError);
+        assert!(socket_a.is_error().unwrap());
+    }
+
+    #[test]
+    fn test_mock_error_completions() {
+        let (devices, comp_queues, socket_a, socket_b) = connected_sockets(1);
+        let buffer_pool = BufferPool::create(4096, 4, &devices).unwrap();
+
+        // no receive is posted by the peer.
+        socket_a
+            .post_send(1, buffer_pool.allocate().unwrap())
+            .unwrap();
+        let mut wcs = vec![verbs::ibv_wc::default(); 128];
+        let comp = comp_queues.poll_cq(&mut wcs).unwrap();
+        assert_eq!(comp.len(), 1);
+        assert_eq!(comp[0].wc_status(), WcStatus::RnrRetryExceeded);
+        assert!(socket_a.is_error().unwrap());
+
+        // work requests posted in the error state are flushed.
+        socket_a
+            .post_recv(2, buffer_pool.allocate().unwrap())
+            .unwrap();
+        socket_a
+            .post_send(3, buffer_pool.allocate().unwrap())
+            .unwrap();
+        let comp = comp_queues.poll_cq(&mut wcs).unwrap();
+        assert_eq!(comp.len(), 2);
+        assert!(comp.iter().all(|wc| wc.wc_status().is_flush()));
+
+        // the peer is not affected.
+        assert!(!socket_b.is_error().unwrap());
+        assert!(comp.iter().all(|wc| wc.qp_num == socket_a.qp_num()));
+    }
+}
@@ -0,0 +1,191 @@
+mod ib_verbs;
+pub use ib_verbs::IbVerbs;
+
+#[cfg(any(test, feature = "mock"))]
+mod mock;
+#[cfg(test)]
+pub(crate) use mock::testing;
+#[cfg(any(test, feature = "mock"))]
+pub use mock::MockVerbs;
+
+use crate::verbs;
+use std::{
+    ffi::{c_int, c_uint, c_void},
+    path::Path,
+    sync::Arc,
+};
+
+/// The verbs calls made by r2dma, which can be served by libibverbs or by an in-process mock.
+/// Methods mirror the corresponding `ibv_*` functions, and report errors through the return value and errno.
+#[allow(clippy::missing_safety_doc)]
+pub trait VerbsBackend: Send + Sync + 'static {
+    fn name(&self) -> &str;
+
+    unsafe fn get_device_list(&self, num_devices: *mut c_int) -> *mut *mut verbs::ibv_device;
+    unsafe fn free_device_list(&self, list: *mut *mut verbs::ibv_device);
+    unsafe fn get_device_guid(&self, device: *mut verbs::ibv_device) -> u64;
+    unsafe fn open_device(&self, device: *mut verbs::ibv_device) -> *mut verbs::ibv_context;
+    unsafe fn close_device(&self, context: *mut verbs::ibv_context) -> c_int;
+
+    unsafe fn query_device(
+        &self,
+        context: *mut verbs::ibv_context,
+        device_attr: *mut verbs::ibv_device_attr,
+    ) -> c_int;
+    unsafe fn query_device_ex(
+        &self,
+        context: *mut verbs::ibv_context,
+        device_attr: *mut verbs::ibv_device_attr_ex,
+    ) -> c_int;
+    unsafe fn query_port(
+        &self,
+        context: *mut verbs::ibv_context,
+        port_num: u8,
+        port_attr: *mut verbs::ibv_port_attr,
+    ) -> c_int;
+    unsafe fn query_gid(
+        &self,
+        context: *mut verbs::ibv_context,
+        port_num: u8,
+        index: c_int,
+        gid: *mut verbs::ibv_gid,
+    ) -> c_int;
+    /// Reads the type of a GID table entry, e.g. "RoCE v2".
+    fn read_gid_type(
+        &self,
+        ibdev_path: &Path,
+        port_num: u8,
+        gid_index: u16,
+    ) -> std::io::Result<String>;
+    /// Reads the counters of a port from `counters/` and `hw_counters/`, by name.
+    fn read_port_counters(
+        &self,
+        ibdev_path: &Path,
+        port_num: u8,
+    ) -> std::io::Result<Vec<(String, u64)>>;
+
+    unsafe fn get_async_event(
+        &self,
+        context: *mut verbs::ibv_context,
+        event: *mut verbs::ibv_async_event,
+    ) -> c_int;
+    unsafe fn ack_async_event(&self, event: *mut verbs::ibv_async_event);
+
+    unsafe fn alloc_pd(&self, context: *mut verbs::ibv_context) -> *mut verbs::ibv_pd;
+    unsafe fn dealloc_pd(&self, pd: *mut verbs::ibv_pd) -> c_int;
+
+    unsafe fn reg_mr(
+        &self,
+        pd: *mut verbs::ibv_pd,
+        addr: *mut c_void,
+        length: usize,
+        access: c_int,
+    ) -> *mut verbs::ibv_mr;
+    unsafe fn dereg_mr(&self, mr: *mut verbs::ibv_mr) -> c_int;
+
+    unsafe fn alloc_mw(
+        &self,
+        pd: *mut verbs::ibv_pd,
+        mw_type: verbs::ibv_mw_type,
+    ) -> *mut verbs::ibv_mw;
+    unsafe fn dealloc_mw(&self, mw: *mut verbs::ibv_mw) -> c_int;
+
+    unsafe fn create_comp_channel(
+        &self,
+        context: *mut verbs::ibv_context,
+    ) -> *mut verbs::ibv_comp_channel;
+    unsafe fn destroy_comp_channel(&self, channel: *mut verbs::ibv_comp_channel) -> c_int;
+    unsafe fn get_cq_event(
+        &self,
+        channel: *mut verbs::ibv_comp_channel,
+        cq: *mut *mut verbs::ibv_cq,
+        cq_context: *mut *mut c_void,
+    ) -> c_int;
+    unsafe fn ack_cq_events(&self, cq: *mut verbs::ibv_cq, num_events: c_uint);
+
+    unsafe fn create_cq(
+        &self,
+        context: *mut verbs::ibv_context,
+        cqe: c_int,
+        cq_context: *mut c_void,
+        channel: *mut verbs::ibv_comp_channel,
+        comp_vector: c_int,
+    ) -> *mut verbs::ibv_cq;
+    unsafe fn destroy_cq(&self, cq: *mut verbs::ibv_cq) -> c_int;
+    unsafe fn poll_cq(
+        &self,
+        cq: *mut verbs::ibv_cq,
+        num_entries: c_int,
+        wc: *mut verbs::ibv_wc,
+    ) -> c_int;
+    unsafe fn req_notify_cq(&self, cq: *mut verbs::ibv_cq, solicited_only: c_int) -> c_int;
+
+    unsafe fn create_qp(
+        &self,
+        pd: *mut verbs::ibv_pd,
+        init_attr: *mut verbs::ibv_qp_init_attr,
+    ) -> *mut verbs::ibv_qp;
+    unsafe fn destroy_qp(&self, qp: *mut verbs::ibv_qp) -> c_int;
+    unsafe fn modify_qp(
+        &self,
+        qp: *mut verbs::ibv_qp,
+        attr: *mut verbs::ibv_qp_attr,
+        attr_mask: c_int,
+    ) -> c_int;
+    unsafe fn query_qp(
+        &self,
+        qp: *mut verbs::ibv_qp,
+        attr: *mut verbs::ibv_qp_attr,
+        attr_mask: c_int,
+        init_attr: *mut verbs::ibv_qp_init_attr,
+    ) -> c_int;
+    unsafe fn post_send(
+        &self,
+        qp: *mut verbs::ibv_qp,
+        wr: *mut verbs::ibv_send_wr,
+        bad_wr: *mut *mut verbs::ibv_send_wr,
+    ) -> c_int;
+    unsafe fn post_recv(
+        &self,
+        qp: *mut verbs::ibv_qp,
+        wr: *mut verbs::ibv_recv_wr,
+        bad_wr: *mut *mut verbs::ibv_recv_wr,
+    ) -> c_int;
+
+    unsafe fn create_srq(
+        &self,
+        pd: *mut verbs::ibv_pd,
+        init_attr: *mut verbs::ibv_srq_init_attr,
+    ) -> *mut verbs::ibv_srq;
+    unsafe fn destroy_srq(&self, srq: *mut verbs::ibv_srq) -> c_int;
+    unsafe fn modify_srq(
+        &self,
+        srq: *mut verbs::ibv_srq,
+        attr: *mut verbs::ibv_srq_attr,
+        attr_mask: c_int,
+    ) -> c_int;
+    unsafe fn post_srq_recv(
+        &self,
+        srq: *mut verbs::ibv_srq,
+        wr: *mut verbs::ibv_recv_wr,
+        bad_wr: *mut *mut verbs::ibv_recv_wr,
+    ) -> c_int;
+
+    unsafe fn create_ah(
+        &self,
+        pd: *mut verbs::ibv_pd,
+        attr: *mut verbs::ibv_ah_attr,
+    ) -> *mut verbs::ibv_ah;
+    unsafe fn destroy_ah(&self, ah: *mut verbs::ibv_ah) -> c_int;
+}
+
+impl std::fmt::Debug for dyn VerbsBackend {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("VerbsBackend").field(&self.name()).finish()
+    }
+}
+
+/// Returns the default backend, which calls into libibverbs.
+pub fn default_backend() -> Arc<dyn VerbsBackend> {
+    Arc::new(IbVerbs)
+}
@@ -0,0 +1,431 @@
+use clap::{Parser, Subcommand, ValueEnum};
+use r2dma::{Result, *};
+use serde::Serialize;
+use std::{path::Path, time::Duration};
+
+#[derive(Parser, Debug)]
+#[command(version, about = "Inspect RDMA devices", long_about = None)]
+struct Args {
+    #[command(subcommand)]
+    command: Command,
+
+    /// print JSON instead of text.
+    #[arg(long, global = true, default_value_t = false)]
+    json: bool,
+
+    /// only open the devices with these names.
+    #[arg(long = "device", global = true)]
+    device_filter: Vec<String>,
+
+    /// only keep GIDs of these types.
+    #[arg(long = "gid-type", global = true, value_enum)]
+    gid_type_filter: Vec<GidTypeArg>,
+
+    /// skip ports which are not active.
+    #[arg(long, global = true, default_value_t = false)]
+    skip_inactive_port: bool,
+
+    /// skip RoCE v2 GIDs with link local addresses.
+    #[arg(long, global = true, default_value_t = false)]
+    skip_link_local_addr: bool,
+
+    /// enable verbose logging.
+    #[arg(long, short, global = true, default_value_t = false)]
+    verbose: bool,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// list the devices and their ports.
+    List,
+    /// show the ports, GIDs and limits of a device.
+    Info {
+        /// the device name.
+        device: String,
+    },
+    /// list the GIDs of all ports, with their types and network devices.
+    Gids,
+    /// show the port counters, or their rates over an interval.
+    Counters {
+        /// the device names, all devices if empty.
+        devices: Vec<String>,
+        /// sample twice this many seconds apart and show the rates.
+        #[arg(long, short, value_parser = parse_interval)]
+        interval: Option<f64>,
+    },
+}
+
+/// Accepts a positive number of seconds, which `Duration::from_secs_f64` can represent.
+fn parse_interval(s: &str) -> std::result::Result<f64, String> {
+    let secs = s.parse::<f64>().map_err(|err| err.to_string())?;
+    match Duration::try_from_secs_f64(secs) {
+        Ok(duration) if !duration.is_zero() => Ok(secs),
+        _ => Err(format!("{s} is not a positive number of seconds")),
+    }
+}
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+enum GidTypeArg {
+    #[value(name = "IB")]
+    IB,
+    #[value(name = "RoCEv1")]
+    RoCEv1,
+    #[value(name = "RoCEv2")]
+    RoCEv2,
+}
+
+impl From<GidTypeArg> for GidType {
+    fn from(gid_type: GidTypeArg) -> Self {
+        match gid_type {
+            GidTypeArg::IB => GidType::IB,
+            GidTypeArg::RoCEv1 => GidType::RoCEv1,
+            GidTypeArg::RoCEv2 => GidType::RoCEv2,
+        }
+    }
+}
+
+impl Args {
+    fn device_config(&self) -> DeviceConfig {
+        DeviceConfig {
+            device_filter: self.device_filter.iter().cloned().collect(),
+            gid_type_filter: self.gid_type_filter.iter().map(|&t| t.into()).collect(),
+            skip_inactive_port: self.skip_inactive_port,
+            roce_v2_skip_link_local_addr: self.skip_link_local_addr,
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct DeviceSummary {
+    index: usize,
+    name: String,
+    guid: String,
+    ports: Vec<PortSummary>,
+}
+
+#[derive(Serialize)]
+struct PortSummary {
+    port_num: u8,
+    state: PortState,
+    link_layer: LinkLayer,
+    active_mtu: Mtu,
+    bandwidth_gbps: Option<f64>,
+    gids: usize,
+}
+
+#[derive(Serialize)]
+struct GidRow {
+    device: String,
+    port_num: u8,
+    index: u16,
+    gid: String,
+    gid_type: GidType,
+    /// The network device of a RoCE GID.
+    netdev: Option<String>,
+}
+
+#[derive(Serialize)]
+struct PortDetail {
+    port_num: u8,
+    attr: PortAttr,
+    gids: Vec<GidRow>,
+}
+
+#[derive(Serialize)]
+struct DeviceDetail {
+    name: String,
+    guid: String,
+    ibdev_path: String,
+    limits: DeviceAttr,
+    odp_caps: OdpCaps,
+    ports: Vec<PortDetail>,
+}
+
+/// Formats a GUID stored in network byte order.
+fn format_guid(guid: u64) -> String {
+    u64::from_be(guid)
+        .to_be_bytes()
+        .chunks_exact(2)
+        .map(|b| format!("{:02x}{:02x}", b[0], b[1]))
+        .collect::<Vec<_>>()
+        .join(":")
+}
+
+fn read_netdev(ibdev_path: &Path, port_num: u8, gid_index: u16) -> Option<String> {
+    let path = ibdev_path.join(format!("ports/{port_num}/gid_attrs/ndevs/{gid_index}"));
+    let netdev = std::fs::read_to_string(path).ok()?;
+    Some(netdev.trim().to_string()).filter(|netdev| !netdev.is_empty())
+}
+
+fn gid_rows(info: &DeviceInfo, port: &Port) -> Vec<GidRow> {
+    port.gids
+        .iter()
+        .map(|(index, gid, gid_type)| GidRow {
+            device: info.name.clone(),
+            port_num: port.port_num,
+            index: *index,
+            gid: format!("{gid:?}"),
+            gid_type: gid_type.clone(),
+            netdev: read_netdev(&info.ibdev_path, port.port_num, *index),
+        })
+        .collect()
+}
+
+fn gid_type_name(gid_type: &GidType) -> String {
+    match gid_type {
+        GidType::IB => "IB".to_string(),
+        GidType::RoCEv1 => "RoCEv1".to_string(),
+        GidType::RoCEv2 => "RoCEv2".to_string(),
+        GidType::Other(other) => other.clone(),
+    }
+}
+
+fn to_json_err(err: serde_json::Error) -> Error {
+    Error::new(ErrorKind::SerializeFail, err.to_string())
+}
+
+fn print_json<T: Serialize>(value: &T) -> Result<()> {
+    println!(
+        "{}",
+        serde_json::to_string_pretty(value).map_err(to_json_err)?
+    );
+    Ok(())
+}
+
+fn find_device<'a>(devices: &'a Devices, name: &str) -> Result<&'a Device> {
+    devices
+        .iter()
+        .find(|device| device.info().name == name)
+        .ok_or_else(|| Error::new(ErrorKind::IBDeviceNotFound, name.to_string()))
+}
+
+fn list(devices: &Devices, json: bool) -> Result<()> {
+    let summaries = devices
+        .iter()
+        .map(|device| {
+            let info = device.info();
+            DeviceSummary {
+                index: info.index,
+                name: info.name.clone(),
+                guid: format_guid(info.guid),
+                ports: info
+                    .ports
+                    .iter()
+                    .map(|port| {
+                        let attr = port.attr();
+                        PortSummary {
+                            port_num: port.port_num,
+                            state: attr.state,
+                            link_layer: attr.link_layer,
+                            active_mtu: attr.active_mtu,
+                            bandwidth_gbps: attr.bandwidth_gbps,
+                            gids: port.gids.len(),
+                        }
+                    })
+                    .collect(),
+            }
+        })
+        .collect::<Vec<_>>();
+    if json {
+        return print_json(&summaries);
+    }
+
+    for device in summaries {
+        println!("{}: {} (guid {})", device.index, device.name, device.guid);
+        for port in device.ports {
+            let bandwidth = port
+                .bandwidth_gbps
+                .map_or("unknown".to_string(), |gbps| format!("{gbps} Gbps"));
+            println!(
+                "    port {}: {:?}, {:?}, mtu {}, {}, {} gids",
+                port.port_num,
+                port.state,
+                port.link_layer,
+                port.active_mtu.bytes(),
+                bandwidth,
+                port.gids
+            );
+        }
+    }
+    Ok(())
+}
+
+fn info(device: &Device, json: bool) -> Result<()> {
+    let info = device.info();
+    let detail = DeviceDetail {
+        name: info.name.clone(),
+        guid: format_guid(info.guid),
+        ibdev_path: info.ibdev_path.display().to_string(),
+        limits: info.attr(),
+        odp_caps: info.odp_caps,
+        ports: info
+            .ports
+            .iter()
+            .map(|port| PortDetail {
+                port_num: port.port_num,
+                attr: port.attr(),
+                gids: gid_rows(&info, port),
+            })
+            .collect(),
+    };
+    if json {
+        return print_json(&detail);
+    }
+
+    println!("device: {}", detail.name);
+    println!("guid: {}", detail.guid);
+    println!("ibdev_path: {}", detail.ibdev_path);
+    println!("limits:");
+    let limits = serde_json::to_value(&detail.limits).map_err(to_json_err)?;
+    for (name, value) in limits.as_object().into_iter().flatten() {
+        println!("    {name}: {value}");
+    }
+    println!(
+        "    odp: {}, implicit odp: {}",
+        detail.odp_caps.is_supported(),
+        detail.odp_caps.is_implicit_supported()
+    );
+    for port in detail.ports {
+        let attr = port.attr;
+        println!("port {}:", port.port_num);
+        println!("    state: {:?}", attr.state);
+        println!("    link_layer: {:?}", attr.link_layer);
+        println!(
+            "    mtu: {} (max {})",
+            attr.active_mtu.bytes(),
+            attr.max_mtu.bytes()
+        );
+        println!("    lid: {}", attr.lid);
+        if let Some(gbps) = attr.bandwidth_gbps {
+            println!("    bandwidth: {gbps} Gbps");
+        }
+        println!("    gids:");
+        for gid in port.gids {
+            println!(
+                "        {:>3} {} {} {}",
+                gid.index,
+                gid.gid,
+                gid_type_name(&gid.gid_type),
+                gid.netdev.as_deref().unwrap_or("-")
+            );
+        }
+    }
+    Ok(())
+}
+
+fn gids(devices: &Devices, json: bool) -> Result<()> {
+    let rows = devices
+        .iter()
+        .flat_map(|device| {
+            let info = device.info();
+            info.ports
+                .iter()
+                .flat_map(|port| gid_rows(&info, port))
+                .collect::<Vec<_>>()
+        })
+        .collect::<Vec<_>>();
+    if json {
+        return print_json(&rows);
+    }
+
+    println!(
+        "{:<12} {:>4} {:>5} {:<40} {:<8} NETDEV",
+        "DEV", "PORT", "INDEX", "GID", "TYPE"
+    );
+    for row in rows {
+        println!(
+            "{:<12} {:>4} {:>5} {:<40} {:<8} {}",
+            row.device,
+            row.port_num,
+            row.index,
+            row.gid,
+            gid_type_name(&row.gid_type),
+            row.netdev.as_deref().unwrap_or("-")
+        );
+    }
+    Ok(())
+}
+
+fn counters(devices: &Devices, names: &[String], interval: Option<f64>, json: bool) -> Result<()> {
+    let devices = if names.is_empty() {
+        devices.iter().collect::<Vec<_>>()
+    } else {
+        names
+            .iter()
+            .map(|name| find_device(devices, name))
+            .collect::<Result<Vec<_>>>()?
+    };
+    let earlier = devices
+        .iter()
+        .map(|device| device.counters())
+        .collect::<Result<Vec<_>>>()?;
+
+    let Some(interval) = interval else {
+        if json {
+            return print_json(&earlier);
+        }
+        for counters in earlier {
+            for port in &counters.ports {
+                println!("{} port {}:", counters.device, port.port_num);
+                for (name, value) in port.values() {
+                    println!("    {name}: {value}");
+                }
+            }
+        }
+        return Ok(());
+    };
+
+    std::thread::sleep(Duration::from_secs_f64(interval));
+    let mut rates = Vec::with_capacity(devices.len());
+    for (device, earlier) in devices.iter().zip(&earlier) {
+        rates.push(device.counters()?.rates(earlier));
+    }
+    if json {
+        return print_json(&rates);
+    }
+    for rates in rates {
+        for port in &rates.ports {
+            println!(
+                "{} port {} over {:.2}s:",
+                rates.device, port.port_num, rates.interval_secs
+            );
+            let bandwidth = [
+                ("xmit", port.xmit_bytes_per_sec),
+                ("rcv", port.rcv_bytes_per_sec),
+            ];
+            for (direction, rate) in bandwidth {
+                if let Some(rate) = rate {
+                    println!("    {direction}: {:.3} Gbps", rate * 8.0 / 1e9);
+                }
+            }
+            for (name, rate) in &port.rates {
+                println!("    {name}: {rate:.1}/s");
+            }
+        }
+    }
+    Ok(())
+}
+
+fn main() -> Result<()> {
+    let args = Args::parse();
+    tracing_subscriber::fmt()
+        .with_max_level(if args.verbose {
+            tracing::Level::DEBUG
+        } else {
+            tracing::Level::INFO
+        })
+        .with_writer(std::io::stderr)
+        .with_timer(tracing_subscriber::fmt::time::ChronoLocal::rfc_3339())
+        .init();
+
+    let devices = Devices::open(&args.device_config())?;
+    match &args.command {
+        Command::List => list(&devices, args.json),
+        Command::Info { device } => info(find_device(&devices, device)?, args.json),
+        Command::Gids => gids(&devices, args.json),
+        Command::Counters {
+            devices: names,
+            interval,
+        } => counters(&devices, names, *interval, args.json),
+    }
+}
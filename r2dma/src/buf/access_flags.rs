@@ -0,0 +1,224 @@
+use crate::{
+    verbs::{self, ibv_access_flags},
+    DeviceInfo,
+};
+
+/// The access granted by a memory registration, built up from local access.
+/// The default matches `verbs::ACCESS_FLAGS`: remote read and write with relaxed ordering.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AccessFlags(u32);
+
+impl AccessFlags {
+    /// Buffers can be sent from and received into, but not accessed by peers.
+    pub const fn local() -> Self {
+        Self(ibv_access_flags::IBV_ACCESS_LOCAL_WRITE.0)
+    }
+
+    /// Peers can read the buffers, and nothing writes into them through the device,
+    /// so they can not be received into either.
+    pub const fn remote_read_only() -> Self {
+        Self(ibv_access_flags::IBV_ACCESS_REMOTE_READ.0)
+    }
+
+    pub const fn remote_read(self) -> Self {
+        Self(self.0 | ibv_access_flags::IBV_ACCESS_REMOTE_READ.0)
+    }
+
+    /// Allows peers to write, which also requires local write access.
+    pub const fn remote_write(self) -> Self {
+        Self(
+            self.0
+                | ibv_access_flags::IBV_ACCESS_LOCAL_WRITE.0
+                | ibv_access_flags::IBV_ACCESS_REMOTE_WRITE.0,
+        )
+    }
+
+    /// Allows peers to run atomic operations, which also requires local write access.
+    pub const fn remote_atomic(self) -> Self {
+        Self(
+            self.0
+                | ibv_access_flags::IBV_ACCESS_LOCAL_WRITE.0
+                | ibv_access_flags::IBV_ACCESS_REMOTE_ATOMIC.0,
+        )
+    }
+
+    /// Requests relaxed ordering of PCIe writes, which is dropped on devices not supporting it.
+    pub const fn relaxed_ordering(self, enabled: bool) -> Self {
+        let flag = ibv_access_flags::IBV_ACCESS_RELAXED_ORDERING.0;
+        if enabled {
+            Self(self.0 | flag)
+        } else {
+            Self(self.0 & !flag)
+        }
+    }
+
+    /// Requests on-demand paging, so pages are faulted in by the device instead of being pinned
+    /// at registration. Dropped on devices not supporting it for the requested access.
+    pub const fn on_demand(self, enabled: bool) -> Self {
+        let flag = ibv_access_flags::IBV_ACCESS_ON_DEMAND.0;
+        if enabled {
+            Self(self.0 | flag)
+        } else {
+            Self(self.0 & !flag)
+        }
+    }
+
+    /// Allows binding memory windows to the buffers.
+    pub const fn memory_windows(self) -> Self {
+        Self(self.0 | ibv_access_flags::IBV_ACCESS_MW_BIND.0)
+    }
+
+    pub const fn bits(self) -> u32 {
+        self.0
+    }
+
+    pub const fn contains(self, flag: ibv_access_flags) -> bool {
+        self.0 & flag.0 == flag.0
+    }
+
+    pub fn is_remote_writable(self) -> bool {
+        self.contains(ibv_access_flags::IBV_ACCESS_REMOTE_WRITE)
+    }
+
+    /// Whether peers can access the buffers in any way.
+    pub fn is_remote(self) -> bool {
+        use ibv_access_flags::*;
+        self.0 & (IBV_ACCESS_REMOTE_READ.0 | IBV_ACCESS_REMOTE_WRITE.0 | IBV_ACCESS_REMOTE_ATOMIC.0)
+            != 0
+    }
+
+    pub fn is_remote_atomic(self) -> bool {
+        self.contains(ibv_access_flags::IBV_ACCESS_REMOTE_ATOMIC)
+    }
+
+    pub fn is_relaxed_ordering(self) -> bool {
+        self.contains(ibv_access_flags::IBV_ACCESS_RELAXED_ORDERING)
+    }
+
+    pub fn is_on_demand(self) -> bool {
+        self.contains(ibv_access_flags::IBV_ACCESS_ON_DEMAND)
+    }
+
+    /// Returns the flags the device can register with, pinning instead of on-demand paging
+    /// if the device does not support it.
+    pub(crate) fn supported_by(self, info: &DeviceInfo) -> Self {
+        if self.is_on_demand() && !info.odp_caps.supports_rc(self) {
+            tracing::warn!(
+                "{} does not support on-demand paging, pinning memory instead",
+                info.name
+            );
+            self.on_demand(false)
+        } else {
+            self
+        }
+    }
+}
+
+impl Default for AccessFlags {
+    fn default() -> Self {
+        Self(verbs::ACCESS_FLAGS)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{testing::connected_sockets, *};
+    use std::sync::Arc;
+
+    #[test]
+    fn test_access_flags() {
+        assert_eq!(
+            AccessFlags::default(),
+            AccessFlags::local()
+                .remote_read()
+                .remote_write()
+                .relaxed_ordering(true)
+        );
+        let flags = AccessFlags::remote_read_only().remote_atomic();
+        assert!(flags.contains(ibv_access_flags::IBV_ACCESS_LOCAL_WRITE));
+        assert!(!flags.is_remote_writable());
+        assert!(flags.is_remote());
+        assert!(!AccessFlags::local().memory_windows().is_remote());
+        assert!(!AccessFlags::default()
+            .relaxed_ordering(false)
+            .is_relaxed_ordering());
+    }
+
+    #[test]
+    fn test_access_flags_registration() {
+        let (devices, comp_queues, socket_a, _socket_b) = connected_sockets(1);
+        let queue_pair_a = socket_a.queue_pair();
+
+        let local_pool = BufferPool::create(4096, 2, &devices).unwrap();
+        let local = local_pool.allocate().unwrap();
+        let read_only_pool =
+            BufferPool::create_with_access(4096, 1, &devices, AccessFlags::remote_read_only())
+                .unwrap();
+        let remote = read_only_pool.allocate().unwrap();
+
+        let device = &devices[0];
+        let mut sge = verbs::ibv_sge {
+            addr: local.as_ptr() as _,
+            length: local.len() as _,
+            lkey: local.lkey(device),
+        };
+        let mut wr = verbs::ibv_send_wr {
+            wr_id: 1,
+            sg_list: &mut sge,
+            num_sge: 1,
+            opcode: verbs::ibv_wr_opcode::IBV_WR_RDMA_READ,
+            send_flags: verbs::ibv_send_flags::IBV_SEND_SIGNALED.0,
+            ..Default::default()
+        };
+        wr.wr.rdma.remote_addr = remote.as_ptr() as _;
+        wr.wr.rdma.rkey = remote.rkey(device);
+        assert_eq!(queue_pair_a.post_send(&mut wr), 0);
+
+        // the peer can read the region, but not write it.
+        wr.wr_id = 2;
+        wr.opcode = verbs::ibv_wr_opcode::IBV_WR_RDMA_WRITE;
+        assert_eq!(queue_pair_a.post_send(&mut wr), 0);
+        let mut wcs = vec![verbs::ibv_wc::default(); 128];
+        let comp = comp_queues.poll_cq(&mut wcs).unwrap();
+        assert_eq!(comp.len(), 2);
+        assert!(comp[0].result().is_ok());
+        assert_eq!(comp[1].wc_status(), WcStatus::RemoteAccessError);
+    }
+
+    #[test]
+    fn test_remote_atomic_unsupported() {
+        let devices = MockVerbs::devices(1).unwrap();
+        let access = AccessFlags::local().remote_atomic();
+        let err = RegisteredBuffer::create_with_access(&devices, 4096, access).unwrap_err();
+        assert_eq!(err.kind, ErrorKind::IBAtomicNotSupported);
+    }
+
+    #[test]
+    fn test_relaxed_ordering_fallback() {
+        let backend = MockVerbs::new(1).with_relaxed_ordering(false);
+        let devices =
+            Devices::open_with_backend(&DeviceConfig::default(), Arc::new(backend)).unwrap();
+        let buffer = RegisteredBuffer::create(&devices, 4096).unwrap();
+        assert_eq!(
+            buffer.access(0),
+            AccessFlags::default().relaxed_ordering(false)
+        );
+    }
+
+    #[test]
+    fn test_on_demand_fallback() {
+        let access = AccessFlags::default().on_demand(true);
+        let backend = MockVerbs::new(1).with_odp(true, false);
+        let devices =
+            Devices::open_with_backend(&DeviceConfig::default(), Arc::new(backend)).unwrap();
+        let buffer = RegisteredBuffer::create_with_access(&devices, 4096, access).unwrap();
+        assert!(buffer.access(0).is_on_demand());
+
+        // devices without on-demand paging pin the buffer instead of failing.
+        let devices = MockVerbs::devices(1).unwrap();
+        assert!(!devices[0].info().odp_caps.is_supported());
+        let buffer = RegisteredBuffer::create_with_access(&devices, 4096, access).unwrap();
+        assert_eq!(buffer.access(0), AccessFlags::default());
+    }
+}
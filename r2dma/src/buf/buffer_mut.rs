@@ -0,0 +1,172 @@
+use crate::*;
+use bytes::{buf::UninitSlice, BufMut, Bytes};
+use std::ops::{Deref, DerefMut};
+
+/// A writable view of a registered buffer block.
+/// It tracks the written length so it can be used as a `BufMut`, e.g. for serializing messages
+/// directly into registered memory, and then frozen into `Bytes` without copying.
+pub struct BufferMut {
+    buf: Buffer,
+    len: usize,
+}
+
+impl BufferMut {
+    pub fn new(buf: Buffer) -> Self {
+        Self { buf, len: 0 }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.buf.len()
+    }
+
+    pub fn clear(&mut self) {
+        self.len = 0;
+    }
+
+    pub fn lkey(&self, device: &Device) -> u32 {
+        self.buf.lkey(device)
+    }
+
+    pub fn rkey(&self, device: &Device) -> u32 {
+        self.buf.rkey(device)
+    }
+
+    /// Returns the underlying block, discarding the written length.
+    pub fn into_inner(self) -> Buffer {
+        self.buf
+    }
+
+    /// Converts the written part into `Bytes` without copying.
+    pub fn freeze(self) -> Bytes {
+        Bytes::from_owner(self)
+    }
+}
+
+impl From<Buffer> for BufferMut {
+    fn from(buf: Buffer) -> Self {
+        Self::new(buf)
+    }
+}
+
+impl Deref for BufferMut {
+    type Target = [u8];
+
+    fn deref(&self) -> &Self::Target {
+        &self.buf[..self.len]
+    }
+}
+
+impl DerefMut for BufferMut {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.buf[..self.len]
+    }
+}
+
+impl AsRef<[u8]> for BufferMut {
+    fn as_ref(&self) -> &[u8] {
+        self
+    }
+}
+
+unsafe impl BufMut for BufferMut {
+    fn remaining_mut(&self) -> usize {
+        self.buf.len() - self.len
+    }
+
+    unsafe fn advance_mut(&mut self, cnt: usize) {
+        assert!(
+            cnt <= self.remaining_mut(),
+            "cannot advance past `remaining_mut`: {} > {}",
+            cnt,
+            self.remaining_mut()
+        );
+        self.len += cnt;
+    }
+
+    fn chunk_mut(&mut self) -> &mut UninitSlice {
+        UninitSlice::new(&mut self.buf[self.len..])
+    }
+}
+
+impl From<BufferMut> for Bytes {
+    fn from(buf: BufferMut) -> Self {
+        buf.freeze()
+    }
+}
+
+impl std::fmt::Debug for BufferMut {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("BufferMut")
+            .field("addr", &self.buf.as_ptr())
+            .field("len", &self.len)
+            .field("capacity", &self.buf.len())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    #[test]
+    fn test_buffer_mut() {
+        const LEN: usize = 4096;
+        let devices = Devices::availables().unwrap();
+        let buffer_pool = BufferPool::create(LEN, 2, &devices).unwrap();
+
+        let mut buf = BufferMut::from(buffer_pool.allocate().unwrap());
+        assert!(buf.is_empty());
+        assert_eq!(buf.remaining_mut(), LEN);
+        buf.put_u32(0x01020304);
+        buf.put_slice(b"hello");
+        assert_eq!(&buf[..], b"\x01\x02\x03\x04hello");
+
+        let addr = buf.as_ptr();
+        let bytes = buf.freeze();
+        assert_eq!(bytes.as_ptr(), addr);
+        assert_eq!(&bytes[..], b"\x01\x02\x03\x04hello");
+
+        // the block is still reserved by the bytes.
+        let other = buffer_pool.allocate().unwrap();
+        assert!(buffer_pool.allocate().is_err());
+        drop(other);
+        drop(bytes);
+        let _a = buffer_pool.allocate().unwrap();
+        let _b = buffer_pool.allocate().unwrap();
+    }
+
+    #[test]
+    fn test_buffer_mut_writer_overflow() {
+        const LEN: usize = 4096;
+        let devices = Devices::availables().unwrap();
+        let buffer_pool = BufferPool::create(LEN, 1, &devices).unwrap();
+
+        let buf = BufferMut::from(buffer_pool.allocate().unwrap());
+        let mut writer = buf.writer();
+        assert!(writer.write_all(&[1u8; LEN + 1]).is_err());
+        assert_eq!(writer.get_ref().len(), LEN);
+    }
+
+    #[test]
+    fn test_buffer_into_bytes() {
+        const LEN: usize = 4096;
+        let devices = Devices::availables().unwrap();
+        let buffer_pool = BufferPool::create(LEN, 1, &devices).unwrap();
+
+        let mut buf = buffer_pool.allocate().unwrap();
+        buf.fill(7);
+        let addr = buf.as_ptr();
+        let bytes = Bytes::from(buf);
+        assert_eq!(bytes.as_ptr(), addr);
+        assert_eq!(bytes.len(), LEN);
+        assert!(buffer_pool.allocate().is_err());
+
+        let slice = bytes.slice(16..32);
+        drop(bytes);
+        assert!(buffer_pool.allocate().is_err());
+        assert!(slice.iter().all(|&x| x == 7));
+        drop(slice);
+        assert!(buffer_pool.allocate().is_ok());
+    }
+}
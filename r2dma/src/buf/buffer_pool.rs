@@ -1,7 +1,14 @@
 use crate::*;
+use serde::Serialize;
 use std::{
+    backtrace::Backtrace,
+    collections::HashMap,
     ops::{Deref, DerefMut},
-    sync::{Arc, Mutex},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
 };
 
 /// A pool of buffers that can be allocated and deallocated.
@@ -9,7 +16,42 @@ use std::{
 pub struct BufferPool {
     buffer: RegisteredBuffer,
     block_size: usize,
-    free_list: Mutex<Vec<usize>>,
+    block_count: usize,
+    tracking: AtomicBool,
+    state: Mutex<PoolState>,
+}
+
+struct PoolState {
+    free_list: Vec<usize>,
+    peak_in_use: usize,
+    alloc_failures: u64,
+    records: HashMap<usize, AllocRecord>,
+}
+
+struct AllocRecord {
+    since: Instant,
+    tag: Option<&'static str>,
+    backtrace: Option<Backtrace>,
+}
+
+/// Usage statistics of a buffer pool.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct BufferPoolStats {
+    pub block_size: usize,
+    pub total: usize,
+    pub free: usize,
+    pub in_use: usize,
+    pub peak_in_use: usize,
+    pub alloc_failures: u64,
+}
+
+/// A buffer which has been held for a while, reported when tracking is enabled.
+#[derive(Debug)]
+pub struct OutstandingBuffer {
+    pub index: usize,
+    pub held_for: Duration,
+    pub tag: Option<&'static str>,
+    pub backtrace: Option<String>,
 }
 
 pub struct Buffer {
@@ -39,48 +81,172 @@ impl DerefMut for Buffer {
     }
 }
 
+impl AsRef<[u8]> for Buffer {
+    fn as_ref(&self) -> &[u8] {
+        self
+    }
+}
+
+impl From<Buffer> for bytes::Bytes {
+    /// Converts the buffer into `Bytes` without copying, the block stays reserved until the last clone is dropped.
+    fn from(buf: Buffer) -> Self {
+        bytes::Bytes::from_owner(buf)
+    }
+}
+
 impl Buffer {
+    fn offset(&self) -> usize {
+        self.idx * self.pool.block_size
+    }
+
     pub fn lkey(&self, device: &Device) -> u32 {
-        self.pool.buffer.lkey(device.index())
+        self.pool.buffer.lkey_at(device.index(), self.offset())
     }
 
     pub fn rkey(&self, device: &Device) -> u32 {
-        self.pool.buffer.rkey(device.index())
+        self.pool.buffer.rkey_at(device.index(), self.offset())
+    }
+
+    pub(crate) fn memory_region(&self, device: &Device) -> *mut verbs::ibv_mr {
+        self.pool
+            .buffer
+            .memory_region(device.index(), self.offset())
     }
 }
 
 impl BufferPool {
     pub fn create(block_size: usize, block_count: usize, devices: &Devices) -> Result<Arc<Self>> {
+        Self::create_with_access(block_size, block_count, devices, AccessFlags::default())
+    }
+
+    /// Creates a pool registered with `access`, e.g. `AccessFlags::local()` for buffers which
+    /// peers must not access remotely.
+    pub fn create_with_access(
+        block_size: usize,
+        block_count: usize,
+        devices: &Devices,
+        access: AccessFlags,
+    ) -> Result<Arc<Self>> {
         let buffer_size = block_size * block_count;
-        let buffer = RegisteredBuffer::create(devices, buffer_size)?;
-        let free_list = Mutex::new((0..block_count).collect());
+        // blocks never span two memory regions of a buffer registered in chunks.
+        let buffer = RegisteredBuffer::create_impl(devices, buffer_size, access, block_size)?;
+        let state = Mutex::new(PoolState {
+            free_list: (0..block_count).collect(),
+            peak_in_use: 0,
+            alloc_failures: 0,
+            records: HashMap::new(),
+        });
         Ok(Arc::new(Self {
             buffer,
             block_size,
-            free_list,
+            block_count,
+            tracking: AtomicBool::new(false),
+            state,
         }))
     }
 
     pub fn allocate(self: &Arc<Self>) -> Result<Buffer> {
-        let mut free_list = self.free_list.lock().unwrap();
-        match free_list.pop() {
-            Some(idx) => Ok(Buffer {
-                pool: self.clone(),
-                idx,
-            }),
-            None => Err(ErrorKind::AllocMemoryFailed.into()),
+        self.allocate_impl(None)
+    }
+
+    /// Allocates a buffer with a caller tag, which is reported by `outstanding` when tracking is enabled.
+    pub fn allocate_tagged(self: &Arc<Self>, tag: &'static str) -> Result<Buffer> {
+        self.allocate_impl(Some(tag))
+    }
+
+    fn allocate_impl(self: &Arc<Self>, tag: Option<&'static str>) -> Result<Buffer> {
+        let record = if self.tracking.load(Ordering::Acquire) {
+            Some(AllocRecord {
+                since: Instant::now(),
+                tag,
+                backtrace: Some(Backtrace::force_capture()),
+            })
+        } else {
+            None
+        };
+
+        let mut state = self.state.lock().unwrap();
+        match state.free_list.pop() {
+            Some(idx) => {
+                let in_use = self.block_count - state.free_list.len();
+                state.peak_in_use = state.peak_in_use.max(in_use);
+                if let Some(record) = record {
+                    state.records.insert(idx, record);
+                }
+                Ok(Buffer {
+                    pool: self.clone(),
+                    idx,
+                })
+            }
+            None => {
+                state.alloc_failures += 1;
+                Err(ErrorKind::AllocMemoryFailed.into())
+            }
         }
     }
 
     fn deallocate(&self, idx: usize) {
-        let mut free_list = self.free_list.lock().unwrap();
-        free_list.push(idx);
+        let mut state = self.state.lock().unwrap();
+        state.records.remove(&idx);
+        state.free_list.push(idx);
+    }
+
+    /// Returns the current usage statistics of the pool.
+    pub fn stats(&self) -> BufferPoolStats {
+        let state = self.state.lock().unwrap();
+        let free = state.free_list.len();
+        BufferPoolStats {
+            block_size: self.block_size,
+            total: self.block_count,
+            free,
+            in_use: self.block_count - free,
+            peak_in_use: state.peak_in_use,
+            alloc_failures: state.alloc_failures,
+        }
+    }
+
+    /// Enables or disables allocation tracking.
+    /// When enabled, each allocation records its time, caller tag and backtrace, which is costly.
+    pub fn set_tracking(&self, enabled: bool) {
+        self.tracking.store(enabled, Ordering::Release);
+        if !enabled {
+            self.state.lock().unwrap().records.clear();
+        }
+    }
+
+    /// Returns tracked buffers which have been held for at least `min_held`, longest first.
+    pub fn outstanding(&self, min_held: Duration) -> Vec<OutstandingBuffer> {
+        let now = Instant::now();
+        let state = self.state.lock().unwrap();
+        let mut outstanding = state
+            .records
+            .iter()
+            .map(|(&index, record)| OutstandingBuffer {
+                index,
+                held_for: now.duration_since(record.since),
+                tag: record.tag,
+                backtrace: record.backtrace.as_ref().map(|b| b.to_string()),
+            })
+            .filter(|b| b.held_for >= min_held)
+            .collect::<Vec<_>>();
+        outstanding.sort_by(|a, b| b.held_for.cmp(&a.held_for));
+        outstanding
+    }
+}
+
+impl std::fmt::Debug for BufferPool {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("BufferPool")
+            .field("buffer", &self.buffer)
+            .field("stats", &self.stats())
+            .finish()
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::MockVerbs;
 
     #[test]
     fn test_buffer() {
@@ -105,4 +271,55 @@ mod tests {
         assert_eq!(another.len(), LEN);
         another.iter().all(|&x| x == 2);
     }
+
+    #[test]
+    fn test_buffer_pool_stats() {
+        const LEN: usize = 4096;
+        let devices = MockVerbs::devices(1).unwrap();
+        let buffer_pool = BufferPool::create(LEN, 2, &devices).unwrap();
+        let stats = buffer_pool.stats();
+        assert_eq!(stats.total, 2);
+        assert_eq!(stats.free, 2);
+        assert_eq!(stats.in_use, 0);
+
+        let a = buffer_pool.allocate().unwrap();
+        let b = buffer_pool.allocate().unwrap();
+        assert!(buffer_pool.allocate().is_err());
+        let stats = buffer_pool.stats();
+        assert_eq!(stats.free, 0);
+        assert_eq!(stats.in_use, 2);
+        assert_eq!(stats.peak_in_use, 2);
+        assert_eq!(stats.alloc_failures, 1);
+
+        drop(a);
+        drop(b);
+        let stats = buffer_pool.stats();
+        assert_eq!(stats.free, 2);
+        assert_eq!(stats.in_use, 0);
+        assert_eq!(stats.peak_in_use, 2);
+    }
+
+    #[test]
+    fn test_buffer_pool_tracking() {
+        const LEN: usize = 4096;
+        let devices = MockVerbs::devices(1).unwrap();
+        let buffer_pool = BufferPool::create(LEN, 4, &devices).unwrap();
+
+        let _untracked = buffer_pool.allocate().unwrap();
+        buffer_pool.set_tracking(true);
+        let held = buffer_pool.allocate_tagged("held").unwrap();
+        let dropped = buffer_pool.allocate_tagged("dropped").unwrap();
+        drop(dropped);
+
+        let outstanding = buffer_pool.outstanding(Duration::ZERO);
+        assert_eq!(outstanding.len(), 1);
+        assert_eq!(outstanding[0].tag, Some("held"));
+        assert!(outstanding[0].backtrace.is_some());
+        assert!(buffer_pool
+            .outstanding(Duration::from_secs(3600))
+            .is_empty());
+
+        drop(held);
+        assert!(buffer_pool.outstanding(Duration::ZERO).is_empty());
+    }
 }
@@ -1,3 +1,6 @@
+mod access_flags;
+pub use access_flags::AccessFlags;
+
 mod aligned_buffer;
 pub use aligned_buffer::AlignedBuffer;
 
@@ -5,4 +8,10 @@ mod rdma_buffer;
 pub use rdma_buffer::RegisteredBuffer;
 
 mod buffer_pool;
-pub use buffer_pool::{Buffer, BufferPool};
+pub use buffer_pool::{Buffer, BufferPool, BufferPoolStats, OutstandingBuffer};
+
+mod mr_cache;
+pub use mr_cache::{CachedRegion, MemoryRegionCache, MemoryRegionCacheStats, UserMemoryRegion};
+
+mod buffer_mut;
+pub use buffer_mut::BufferMut;
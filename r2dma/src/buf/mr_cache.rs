@@ -0,0 +1,502 @@
+use super::{aligned_buffer::ALIGN_SIZE, rdma_buffer::RawMemoryRegion};
+use crate::*;
+use serde::Serialize;
+use std::{
+    collections::{BTreeMap, HashMap},
+    marker::PhantomData,
+    ops::Bound,
+    sync::{Arc, Mutex, Weak},
+};
+
+/// A memory region registered on one device for memory owned by the caller.
+pub struct UserMemoryRegion {
+    mr: RawMemoryRegion,
+    addr: usize,
+    len: usize,
+    device_index: usize,
+}
+
+impl UserMemoryRegion {
+    pub fn addr(&self) -> usize {
+        self.addr
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn device_index(&self) -> usize {
+        self.device_index
+    }
+
+    pub fn lkey(&self) -> u32 {
+        self.mr.lkey
+    }
+
+    pub fn rkey(&self) -> u32 {
+        self.mr.rkey
+    }
+
+    pub fn contains(&self, addr: usize, len: usize) -> bool {
+        addr >= self.addr && addr + len <= self.addr + self.len
+    }
+}
+
+impl std::fmt::Debug for UserMemoryRegion {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("UserMemoryRegion")
+            .field("addr", &(self.addr as *const u8))
+            .field("len", &self.len)
+            .field("device_index", &self.device_index)
+            .field("lkey", &self.lkey())
+            .finish()
+    }
+}
+
+/// A region of the cache, which borrows the memory it was looked up for, so the memory can not be
+/// freed while the region is in use.
+#[derive(Clone)]
+pub struct CachedRegion<'a> {
+    region: Arc<UserMemoryRegion>,
+    _data: PhantomData<&'a [u8]>,
+}
+
+impl std::ops::Deref for CachedRegion<'_> {
+    type Target = UserMemoryRegion;
+
+    fn deref(&self) -> &Self::Target {
+        &self.region
+    }
+}
+
+impl std::fmt::Debug for CachedRegion<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.region.fmt(f)
+    }
+}
+
+/// Statistics of a memory region cache.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct MemoryRegionCacheStats {
+    pub entries: usize,
+    pub hits: u64,
+    pub misses: u64,
+    pub evictions: u64,
+}
+
+enum Cached {
+    // an on-demand region, which the device keeps coherent with the address space.
+    OnDemand(Arc<UserMemoryRegion>),
+    // a pinned region, which is reused only while it is in use.
+    Pinned(Weak<UserMemoryRegion>),
+}
+
+// the device index, start address and length of a region.
+type Key = (usize, usize, usize);
+
+/// The most cached regions starting at or below an address which a lookup checks.
+const MAX_LOOKUP: usize = 16;
+
+struct Entry {
+    cached: Cached,
+    last_used: u64,
+}
+
+impl Entry {
+    fn region(&self) -> Option<Arc<UserMemoryRegion>> {
+        match &self.cached {
+            Cached::OnDemand(region) => Some(region.clone()),
+            Cached::Pinned(region) => region.upgrade(),
+        }
+    }
+}
+
+#[derive(Default)]
+struct CacheState {
+    entries: BTreeMap<Key, Entry>,
+    // the keys of the entries by their last use, oldest first.
+    lru: BTreeMap<u64, Key>,
+    // the length of the largest entry, which bounds how far below an address a region may start.
+    max_len: usize,
+    // the regions of the whole address space, keyed by device index.
+    implicit: HashMap<usize, Arc<UserMemoryRegion>>,
+    clock: u64,
+    stats: MemoryRegionCacheStats,
+}
+
+impl CacheState {
+    fn tick(&mut self) -> u64 {
+        self.clock += 1;
+        self.clock
+    }
+
+    fn insert(&mut self, key: Key, cached: Cached) {
+        let last_used = self.tick();
+        if let Some(old) = self.entries.insert(key, Entry { cached, last_used }) {
+            self.lru.remove(&old.last_used);
+        }
+        self.lru.insert(last_used, key);
+        self.max_len = self.max_len.max(key.2);
+    }
+
+    fn remove(&mut self, key: &Key) {
+        if let Some(entry) = self.entries.remove(key) {
+            self.lru.remove(&entry.last_used);
+        }
+    }
+
+    fn touch(&mut self, key: Key) {
+        let clock = self.tick();
+        let entry = self.entries.get_mut(&key).unwrap();
+        self.lru.remove(&entry.last_used);
+        entry.last_used = clock;
+        self.lru.insert(clock, key);
+    }
+
+    fn evict(&mut self, capacity: usize) {
+        while self.entries.len() > capacity {
+            let (_, key) = self.lru.pop_first().unwrap();
+            self.entries.remove(&key);
+            self.stats.evictions += 1;
+        }
+    }
+}
+
+/// Caches registrations of caller-owned memory, so repeatedly sent buffers are registered once.
+/// A lookup reuses any cached region containing the requested range on the device, and the least
+/// recently used regions are evicted beyond `capacity`. Regions are registered outside the lock, so
+/// concurrent misses of the same range may both register it and keep the first one.
+///
+/// With local access, regions are widened to whole pages so that neighbouring buffers share them.
+/// With remote access, exactly the requested range is registered, so peers can not reach the data
+/// around it, and the whole address space is never registered.
+///
+/// # Invalidation
+///
+/// Freed or unmapped memory never leaves a stale registration behind:
+/// - With on-demand access, the device follows changes of the address space through the kernel,
+///   so regions stay cached until evicted and always access the pages currently mapped. Devices
+///   supporting implicit on-demand paging register the whole address space once instead, unless
+///   the access is remote.
+/// - Devices without on-demand paging pin the pages at registration, so their regions are only
+///   reused while a `CachedRegion` borrowing the memory is alive, and are deregistered with the
+///   last one. The memory can not be freed before, so the pinned pages are the mapped ones.
+pub struct MemoryRegionCache {
+    devices: Devices,
+    capacity: usize,
+    access: AccessFlags,
+    // the access each device registers with, and whether it registers the whole address space.
+    device_access: Vec<(AccessFlags, bool)>,
+    state: Mutex<CacheState>,
+}
+
+impl MemoryRegionCache {
+    pub fn new(devices: &Devices, capacity: usize, access: AccessFlags) -> Self {
+        let device_access = devices
+            .iter()
+            .map(|device| {
+                let info = device.info();
+                let access = access.supported_by(&info);
+                (
+                    access,
+                    access.is_on_demand()
+                        && !access.is_remote()
+                        && info.odp_caps.is_implicit_supported(),
+                )
+            })
+            .collect();
+        Self {
+            devices: devices.clone(),
+            capacity: capacity.max(1),
+            access,
+            device_access,
+            state: Default::default(),
+        }
+    }
+
+    /// Returns the access the regions of the device are registered with.
+    pub fn access(&self, device_index: usize) -> AccessFlags {
+        self.device_access[device_index].0
+    }
+
+    /// Whether the device registers the whole address space with implicit on-demand paging.
+    pub fn is_implicit(&self, device_index: usize) -> bool {
+        self.device_access[device_index].1
+    }
+
+    /// Returns a region on the device containing `data`, registering its pages on a miss.
+    pub fn get<'a>(&self, device_index: usize, data: &'a [u8]) -> Result<CachedRegion<'a>> {
+        let region = self.get_impl(device_index, data.as_ptr() as usize, data.len())?;
+        Ok(CachedRegion {
+            region,
+            _data: PhantomData,
+        })
+    }
+
+    fn get_impl(
+        &self,
+        device_index: usize,
+        addr: usize,
+        len: usize,
+    ) -> Result<Arc<UserMemoryRegion>> {
+        if self.is_implicit(device_index) {
+            let mut state = self.state.lock().unwrap();
+            if let Some(region) = state.implicit.get(&device_index).cloned() {
+                state.stats.hits += 1;
+                return Ok(region);
+            }
+            state.stats.misses += 1;
+            drop(state);
+
+            let region = Arc::new(self.register(device_index, 0, usize::MAX)?);
+            let mut state = self.state.lock().unwrap();
+            return Ok(state.implicit.entry(device_index).or_insert(region).clone());
+        }
+
+        let mut state = self.state.lock().unwrap();
+        if let Some(region) = Self::lookup(&mut state, device_index, addr, len) {
+            state.stats.hits += 1;
+            return Ok(region);
+        }
+        state.stats.misses += 1;
+        drop(state);
+
+        // registering pins the pages, which may take long, so other lookups go on meanwhile.
+        let end = addr + len.max(1);
+        let (start, end) = if self.access(device_index).is_remote() {
+            (addr, end)
+        } else {
+            (
+                addr / ALIGN_SIZE * ALIGN_SIZE,
+                end.next_multiple_of(ALIGN_SIZE),
+            )
+        };
+        let region = Arc::new(self.register(device_index, start, end - start)?);
+        let mut state = self.state.lock().unwrap();
+        if let Some(cached) = Self::lookup(&mut state, device_index, addr, len) {
+            return Ok(cached);
+        }
+        let cached = if self.access(device_index).is_on_demand() {
+            Cached::OnDemand(region.clone())
+        } else {
+            Cached::Pinned(Arc::downgrade(&region))
+        };
+        state.insert((device_index, region.addr, region.len), cached);
+        state.evict(self.capacity);
+        Ok(region)
+    }
+
+    /// Returns a cached region on the device containing the range, marking it as used.
+    /// Only the `MAX_LOOKUP` closest regions starting at or below the address are checked, and
+    /// pinned regions no longer in use are dropped on the way.
+    fn lookup(
+        state: &mut CacheState,
+        device_index: usize,
+        addr: usize,
+        len: usize,
+    ) -> Option<Arc<UserMemoryRegion>> {
+        let mut unused = Vec::new();
+        let found = state
+            .entries
+            .range((
+                Bound::Included((device_index, addr.saturating_sub(state.max_len), 0)),
+                Bound::Included((device_index, addr, usize::MAX)),
+            ))
+            .rev()
+            .take(MAX_LOOKUP)
+            .filter(|(&(_, start, size), _)| addr + len <= start + size)
+            .find_map(|(&key, entry)| match entry.region() {
+                Some(region) => Some((key, region)),
+                None => {
+                    unused.push(key);
+                    None
+                }
+            });
+        for key in &unused {
+            state.remove(key);
+        }
+
+        let (key, region) = found?;
+        state.touch(key);
+        Some(region)
+    }
+
+    /// Registers the range, or the whole address space if it starts at 0 and has the maximum length.
+    fn register(&self, device_index: usize, start: usize, len: usize) -> Result<UserMemoryRegion> {
+        let device = &self.devices[device_index];
+        if len != usize::MAX {
+            device.info().check_mr_size(len)?;
+        }
+
+        let access = self.access(device_index);
+        let mr = unsafe {
+            device
+                .backend()
+                .reg_mr(device.pd_ptr(), start as _, len, access.bits() as _)
+        };
+        if mr.is_null() {
+            return Err(Error::new(
+                ErrorKind::IBRegMemoryRegionFail,
+                format!(
+                    "{} range {:#x} of {} bytes: {}",
+                    device.info().name,
+                    start,
+                    len,
+                    std::io::Error::last_os_error()
+                ),
+            ));
+        }
+        Ok(UserMemoryRegion {
+            mr: RawMemoryRegion(mr, device.backend().clone()),
+            addr: start,
+            len,
+            device_index,
+        })
+    }
+
+    pub fn clear(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.entries.clear();
+        state.lru.clear();
+        state.max_len = 0;
+        state.implicit.clear();
+    }
+
+    pub fn stats(&self) -> MemoryRegionCacheStats {
+        let state = self.state.lock().unwrap();
+        MemoryRegionCacheStats {
+            entries: state.entries.len() + state.implicit.len(),
+            ..state.stats
+        }
+    }
+}
+
+impl std::fmt::Debug for MemoryRegionCache {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("MemoryRegionCache")
+            .field("capacity", &self.capacity)
+            .field("access", &self.access)
+            .field("stats", &self.stats())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mr_cache() {
+        let backend = MockVerbs::new(2).with_mr_limits(1 << 20, 3);
+        let devices =
+            Devices::open_with_backend(&DeviceConfig::default(), Arc::new(backend)).unwrap();
+        let cache = MemoryRegionCache::new(&devices, 2, AccessFlags::local());
+
+        let data = AlignedBuffer::new(4 * ALIGN_SIZE).unwrap();
+        let region = cache.get(0, &data[100..200]).unwrap();
+        assert_eq!(region.addr(), data.as_ptr() as usize);
+        assert_eq!(region.len(), ALIGN_SIZE);
+        assert!(region.contains(data[100..200].as_ptr() as usize, 100));
+
+        // ranges contained in a region in use on the same device hit.
+        let hit = cache.get(0, &data[..ALIGN_SIZE]).unwrap();
+        assert!(std::ptr::eq(&*region, &*hit));
+        let other = cache.get(1, &data[..ALIGN_SIZE]).unwrap();
+        assert!(!std::ptr::eq(&*region, &*other));
+        println!("{:?}", cache);
+
+        // pinned regions are deregistered with their last user, so freed memory is never hit.
+        drop((region, hit, other));
+        let region = cache.get(0, &data[..ALIGN_SIZE]).unwrap();
+        let stats = cache.stats();
+        assert_eq!(stats.hits, 1);
+        assert_eq!(stats.misses, 3);
+
+        // regions in use stay registered, reaching the limit of 3 per device.
+        let others = [
+            AlignedBuffer::new(ALIGN_SIZE).unwrap(),
+            AlignedBuffer::new(ALIGN_SIZE).unwrap(),
+            AlignedBuffer::new(ALIGN_SIZE).unwrap(),
+        ];
+        let second = cache.get(0, &others[0]).unwrap();
+        assert_eq!(cache.stats().evictions, 1);
+        let third = cache.get(0, &others[1]).unwrap();
+        let err = cache.get(0, &others[2]).unwrap_err();
+        assert_eq!(err.kind, ErrorKind::IBRegMemoryRegionFail);
+        drop((region, second, third));
+        let region = cache.get(0, &others[2]).unwrap();
+        assert_eq!(region.device_index(), 0);
+    }
+
+    #[test]
+    fn test_mr_cache_remote() {
+        let devices = MockVerbs::devices(1).unwrap();
+        let cache = MemoryRegionCache::new(&devices, 2, AccessFlags::default());
+        let data = AlignedBuffer::new(ALIGN_SIZE).unwrap();
+
+        // peers get access to the requested range only.
+        let region = cache.get(0, &data[100..200]).unwrap();
+        assert_eq!(region.addr(), data[100..].as_ptr() as usize);
+        assert_eq!(region.len(), 100);
+        let hit = cache.get(0, &data[150..200]).unwrap();
+        assert!(std::ptr::eq(&*region, &*hit));
+        let miss = cache.get(0, &data[..200]).unwrap();
+        assert_eq!(miss.len(), 200);
+
+        // nor to the whole address space.
+        let backend = MockVerbs::new(1).with_odp(true, true);
+        let devices =
+            Devices::open_with_backend(&DeviceConfig::default(), Arc::new(backend)).unwrap();
+        let access = AccessFlags::default().on_demand(true);
+        let cache = MemoryRegionCache::new(&devices, 2, access);
+        assert!(!cache.is_implicit(0));
+        let region = cache.get(0, &data[100..200]).unwrap();
+        assert_eq!(region.len(), 100);
+    }
+
+    #[test]
+    fn test_mr_cache_on_demand() {
+        let access = AccessFlags::local().on_demand(true);
+        let data = AlignedBuffer::new(4 * ALIGN_SIZE).unwrap();
+
+        // the whole address space is registered once.
+        let backend = MockVerbs::new(1).with_odp(true, true);
+        let devices =
+            Devices::open_with_backend(&DeviceConfig::default(), Arc::new(backend)).unwrap();
+        assert!(devices[0].info().odp_caps.is_implicit_supported());
+        let cache = MemoryRegionCache::new(&devices, 2, access);
+        assert!(cache.is_implicit(0));
+        let region = cache.get(0, &data[..ALIGN_SIZE]).unwrap();
+        assert_eq!((region.addr(), region.len()), (0, usize::MAX));
+        let other = AlignedBuffer::new(ALIGN_SIZE).unwrap();
+        let hit = cache.get(0, &other).unwrap();
+        assert!(std::ptr::eq(&*region, &*hit));
+        assert_eq!(cache.stats().entries, 1);
+
+        // explicit on-demand regions stay cached after use, as the device follows unmaps.
+        let backend = MockVerbs::new(1).with_odp(true, false);
+        let devices =
+            Devices::open_with_backend(&DeviceConfig::default(), Arc::new(backend)).unwrap();
+        let cache = MemoryRegionCache::new(&devices, 2, access);
+        assert!(!cache.is_implicit(0));
+        assert!(cache.access(0).is_on_demand());
+        let region = cache.get(0, &data[..]).unwrap();
+        assert_eq!(region.len(), data.len());
+        let addr = region.addr();
+        drop(region);
+        let region = cache.get(0, &data[ALIGN_SIZE..]).unwrap();
+        assert_eq!(region.addr(), addr);
+        assert_eq!(cache.stats().hits, 1);
+
+        // devices without on-demand paging pin the pages.
+        let devices = MockVerbs::devices(1).unwrap();
+        let cache = MemoryRegionCache::new(&devices, 2, access);
+        assert_eq!(cache.access(0), AccessFlags::local());
+        let region = cache.get(0, &data[..]).unwrap();
+        assert_eq!(region.len(), data.len());
+    }
+}
@@ -1,6 +1,11 @@
+use super::aligned_buffer::ALIGN_SIZE;
 use crate::*;
+use std::sync::Arc;
 
-struct RawMemoryRegion(*mut verbs::ibv_mr);
+pub(super) struct RawMemoryRegion(
+    pub(super) *mut verbs::ibv_mr,
+    pub(super) Arc<dyn VerbsBackend>,
+);
 impl std::ops::Deref for RawMemoryRegion {
     type Target = verbs::ibv_mr;
 
@@ -10,47 +15,163 @@ impl std::ops::Deref for RawMemoryRegion {
 }
 impl Drop for RawMemoryRegion {
     fn drop(&mut self) {
-        let _ = unsafe { verbs::ibv_dereg_mr(self.0) };
+        let _ = unsafe { self.1.dereg_mr(self.0) };
     }
 }
 unsafe impl Send for RawMemoryRegion {}
 unsafe impl Sync for RawMemoryRegion {}
 
 /// A registered buffer that can be used for RDMA operations.
+/// Buffers larger than the `max_mr_size` of a device are registered in chunks of `chunk_size`.
 pub struct RegisteredBuffer {
-    memory_regions: Vec<RawMemoryRegion>,
+    // memory regions of each device, one per chunk.
+    memory_regions: Vec<Vec<RawMemoryRegion>>,
+    access: Vec<AccessFlags>,
+    chunk_size: usize,
     aligned_buffer: AlignedBuffer,
     _devices: Devices,
 }
 
 impl RegisteredBuffer {
     pub fn create(devices: &Devices, size: usize) -> Result<Self> {
-        let mut buf = AlignedBuffer::new(size)?;
+        Self::create_with_access(devices, size, AccessFlags::default())
+    }
+
+    /// Registers the buffer with `access` on every device.
+    /// Relaxed ordering is dropped on devices which reject it, see `access`.
+    pub fn create_with_access(devices: &Devices, size: usize, access: AccessFlags) -> Result<Self> {
+        Self::create_impl(devices, size, access, ALIGN_SIZE)
+    }
+
+    /// Registers the buffer, splitting it into chunks of a multiple of `granularity` if it exceeds
+    /// the `max_mr_size` of a device, so that no block of `granularity` spans two chunks.
+    /// On failure, the regions registered so far are deregistered.
+    pub(crate) fn create_impl(
+        devices: &Devices,
+        size: usize,
+        access: AccessFlags,
+        granularity: usize,
+    ) -> Result<Self> {
+        let mut chunk_size = usize::MAX;
+        for device in devices {
+            device.info().check_mr_size(granularity)?;
+            let max_mr_size = device.info().device_attr.max_mr_size;
+            let max_mr_size = usize::try_from(max_mr_size).unwrap_or(usize::MAX);
+            chunk_size = chunk_size.min(max_mr_size / granularity * granularity);
+        }
+
+        let buf = AlignedBuffer::new(size)?;
         let mut memory_regions = Vec::with_capacity(devices.len());
+        let mut accesses = Vec::with_capacity(devices.len());
         for device in devices {
-            let mr = unsafe {
-                verbs::ibv_reg_mr(
-                    device.pd_ptr(),
-                    buf.as_mut_ptr() as _,
-                    buf.len(),
-                    verbs::ACCESS_FLAGS as _,
-                )
-            };
-            memory_regions.push(RawMemoryRegion(mr));
+            let (regions, access) = Self::register(device, &buf, chunk_size, access)?;
+            memory_regions.push(regions);
+            accesses.push(access);
         }
         Ok(Self {
             memory_regions,
+            access: accesses,
+            chunk_size,
             aligned_buffer: buf,
             _devices: devices.clone(),
         })
     }
 
+    fn register(
+        device: &Device,
+        buf: &AlignedBuffer,
+        chunk_size: usize,
+        access: AccessFlags,
+    ) -> Result<(Vec<RawMemoryRegion>, AccessFlags)> {
+        let reg_mr = |offset: usize, access: AccessFlags| unsafe {
+            let len = chunk_size.min(buf.len() - offset);
+            device.backend().reg_mr(
+                device.pd_ptr(),
+                buf.as_ptr().add(offset) as _,
+                len,
+                access.bits() as _,
+            )
+        };
+
+        if access.is_remote_atomic() {
+            device.info().check_atomic()?;
+        }
+        let mut access = access.supported_by(&device.info());
+        let mut regions = Vec::with_capacity(buf.len().div_ceil(chunk_size));
+        for offset in (0..buf.len()).step_by(chunk_size) {
+            let mut mr = reg_mr(offset, access);
+            let mut err = std::io::Error::last_os_error();
+            // devices without relaxed ordering reject the flag, other failures are not retried.
+            let rejected = matches!(
+                err.raw_os_error(),
+                Some(libc::EINVAL) | Some(libc::EOPNOTSUPP)
+            );
+            if mr.is_null() && offset == 0 && access.is_relaxed_ordering() && rejected {
+                tracing::warn!(
+                    "{} rejects relaxed ordering, registering without it: {}",
+                    device.info().name,
+                    err
+                );
+                access = access.relaxed_ordering(false);
+                mr = reg_mr(offset, access);
+                err = std::io::Error::last_os_error();
+            }
+            if mr.is_null() {
+                return Err(Error::new(
+                    ErrorKind::IBRegMemoryRegionFail,
+                    format!(
+                        "{} chunk at offset {offset} of {} bytes: {}",
+                        device.info().name,
+                        buf.len(),
+                        err
+                    ),
+                ));
+            }
+            regions.push(RawMemoryRegion(mr, device.backend().clone()));
+        }
+        Ok((regions, access))
+    }
+
+    /// Returns the size of the chunks the buffer is registered in.
+    pub fn chunk_size(&self) -> usize {
+        self.chunk_size
+    }
+
+    pub fn num_chunks(&self) -> usize {
+        self.memory_regions.first().map_or(0, Vec::len)
+    }
+
+    fn region(&self, index: usize, offset: usize) -> &RawMemoryRegion {
+        &self.memory_regions[index][offset / self.chunk_size]
+    }
+
+    /// Returns the lkey of the first chunk on the device.
     pub fn lkey(&self, index: usize) -> u32 {
-        self.memory_regions[index].lkey
+        self.lkey_at(index, 0)
     }
 
+    /// Returns the rkey of the first chunk on the device.
     pub fn rkey(&self, index: usize) -> u32 {
-        self.memory_regions[index].lkey
+        self.rkey_at(index, 0)
+    }
+
+    /// Returns the lkey of the chunk containing `offset` on the device.
+    pub fn lkey_at(&self, index: usize, offset: usize) -> u32 {
+        self.region(index, offset).lkey
+    }
+
+    /// Returns the rkey of the chunk containing `offset` on the device.
+    pub fn rkey_at(&self, index: usize, offset: usize) -> u32 {
+        self.region(index, offset).rkey
+    }
+
+    /// Returns the access the buffer is registered with on the device.
+    pub fn access(&self, index: usize) -> AccessFlags {
+        self.access[index]
+    }
+
+    pub(crate) fn memory_region(&self, index: usize, offset: usize) -> *mut verbs::ibv_mr {
+        self.region(index, offset).0
     }
 }
 
@@ -68,7 +189,9 @@ impl std::fmt::Debug for RegisteredBuffer {
         f.debug_struct("RegisteredBuffer")
             .field("addr", &self.aligned_buffer.as_ptr())
             .field("len", &self.aligned_buffer.len())
-            .field("num_mrs", &self.memory_regions.len())
+            .field("num_devices", &self.memory_regions.len())
+            .field("chunk_size", &self.chunk_size)
+            .field("num_chunks", &self.num_chunks())
             .finish()
     }
 }
@@ -85,4 +208,46 @@ mod tests {
         assert_eq!(registered_buffer.len(), size);
         println!("{:#?}", registered_buffer);
     }
+
+    #[test]
+    fn test_memory_region_chunks() {
+        let backend = MockVerbs::new(2).with_mr_limits(8192, 3);
+        let devices =
+            Devices::open_with_backend(&DeviceConfig::default(), Arc::new(backend)).unwrap();
+
+        let registered_buffer = RegisteredBuffer::create(&devices, 20480).unwrap();
+        assert_eq!(registered_buffer.chunk_size(), 8192);
+        assert_eq!(registered_buffer.num_chunks(), 3);
+        assert_ne!(
+            registered_buffer.lkey_at(0, 0),
+            registered_buffer.lkey_at(0, 8192)
+        );
+        assert_eq!(
+            registered_buffer.lkey_at(1, 16384),
+            registered_buffer.lkey_at(1, 20479)
+        );
+
+        drop(registered_buffer);
+
+        // the fourth chunk exceeds the limit of memory regions, and the first three are released.
+        let err = RegisteredBuffer::create(&devices, 32768).unwrap_err();
+        assert_eq!(err.kind, ErrorKind::IBRegMemoryRegionFail);
+        let registered_buffer = RegisteredBuffer::create(&devices, 20480).unwrap();
+        assert_eq!(registered_buffer.num_chunks(), 3);
+
+        // blocks of a pool never span two chunks.
+        drop(registered_buffer);
+        let buffer_pool = BufferPool::create(3072, 4, &devices).unwrap();
+        let buffers = (0..4)
+            .map(|_| buffer_pool.allocate().unwrap())
+            .collect::<Vec<_>>();
+        let lkeys = buffers
+            .iter()
+            .map(|buf| buf.lkey(&devices[0]))
+            .collect::<std::collections::HashSet<_>>();
+        assert_eq!(lkeys.len(), 2);
+
+        let err = BufferPool::create(16384, 1, &devices).unwrap_err();
+        assert!(matches!(err.kind, ErrorKind::IBExceedDeviceLimit { .. }));
+    }
 }
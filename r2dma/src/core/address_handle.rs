@@ -0,0 +1,72 @@
+use super::{Devices, Endpoint};
+use crate::{verbs, ErrorKind, Result, VerbsBackend};
+use std::sync::Arc;
+
+struct RawAddressHandle(*mut verbs::ibv_ah, Arc<dyn VerbsBackend>);
+impl Drop for RawAddressHandle {
+    fn drop(&mut self) {
+        let _ = unsafe { self.1.destroy_ah(self.0) };
+    }
+}
+unsafe impl Send for RawAddressHandle {}
+unsafe impl Sync for RawAddressHandle {}
+
+/// Represents an address handle, which describes the path to a remote port for unreliable datagram sends.
+pub struct AddressHandle {
+    address_handle: RawAddressHandle,
+    device_index: usize,
+    _devices: Devices,
+}
+
+impl AddressHandle {
+    pub fn create(
+        devices: &Devices,
+        device_index: usize,
+        port_num: u8,
+        sgid_index: u8,
+        remote: &Endpoint,
+    ) -> Result<Self> {
+        let mut attr = verbs::ibv_ah_attr {
+            grh: verbs::ibv_global_route {
+                dgid: remote.gid,
+                flow_label: 0,
+                sgid_index,
+                hop_limit: 0xff,
+                traffic_class: 0,
+            },
+            dlid: remote.lid,
+            sl: 0,
+            src_path_bits: 0,
+            static_rate: 0,
+            is_global: 1,
+            port_num,
+        };
+        let device = &devices[device_index];
+        let ptr = unsafe { device.backend().create_ah(device.pd_ptr(), &mut attr) };
+        if ptr.is_null() {
+            return Err(ErrorKind::IBCreateAddressHandleFail.with_errno());
+        }
+        Ok(Self {
+            address_handle: RawAddressHandle(ptr, device.backend().clone()),
+            device_index,
+            _devices: devices.clone(),
+        })
+    }
+
+    pub(crate) fn ah_ptr(&self) -> *mut verbs::ibv_ah {
+        self.address_handle.0
+    }
+
+    pub fn device_index(&self) -> usize {
+        self.device_index
+    }
+}
+
+impl std::fmt::Debug for AddressHandle {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("AddressHandle")
+            .field("handle", &unsafe { (*self.address_handle.0).handle })
+            .field("device_index", &self.device_index)
+            .finish()
+    }
+}
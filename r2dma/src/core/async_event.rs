@@ -0,0 +1,302 @@
+use super::Devices;
+use crate::{verbs, ErrorKind, Result};
+use serde::Serialize;
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+
+/// The kind of an asynchronous device event.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum AsyncEventKind {
+    CompQueueError,
+    QueuePairFatal,
+    QueuePairRequestError,
+    QueuePairAccessError,
+    CommunicationEstablished,
+    SendQueueDrained,
+    PathMigrated,
+    PathMigrationError,
+    DeviceFatal,
+    PortActive,
+    PortError,
+    LidChange,
+    PkeyChange,
+    SmChange,
+    SharedRecvQueueError,
+    /// The low watermark of a shared receive queue, see `SharedRecvQueue::on_async_event`.
+    SharedRecvQueueLimitReached,
+    QueuePairLastWqeReached,
+    ClientReregister,
+    GidChange,
+    WorkQueueFatal,
+    Unknown(u32),
+}
+
+impl From<verbs::ibv_event_type> for AsyncEventKind {
+    fn from(event_type: verbs::ibv_event_type) -> Self {
+        use verbs::ibv_event_type;
+        match event_type {
+            ibv_event_type::IBV_EVENT_CQ_ERR => Self::CompQueueError,
+            ibv_event_type::IBV_EVENT_QP_FATAL => Self::QueuePairFatal,
+            ibv_event_type::IBV_EVENT_QP_REQ_ERR => Self::QueuePairRequestError,
+            ibv_event_type::IBV_EVENT_QP_ACCESS_ERR => Self::QueuePairAccessError,
+            ibv_event_type::IBV_EVENT_COMM_EST => Self::CommunicationEstablished,
+            ibv_event_type::IBV_EVENT_SQ_DRAINED => Self::SendQueueDrained,
+            ibv_event_type::IBV_EVENT_PATH_MIG => Self::PathMigrated,
+            ibv_event_type::IBV_EVENT_PATH_MIG_ERR => Self::PathMigrationError,
+            ibv_event_type::IBV_EVENT_DEVICE_FATAL => Self::DeviceFatal,
+            ibv_event_type::IBV_EVENT_PORT_ACTIVE => Self::PortActive,
+            ibv_event_type::IBV_EVENT_PORT_ERR => Self::PortError,
+            ibv_event_type::IBV_EVENT_LID_CHANGE => Self::LidChange,
+            ibv_event_type::IBV_EVENT_PKEY_CHANGE => Self::PkeyChange,
+            ibv_event_type::IBV_EVENT_SM_CHANGE => Self::SmChange,
+            ibv_event_type::IBV_EVENT_SRQ_ERR => Self::SharedRecvQueueError,
+            ibv_event_type::IBV_EVENT_SRQ_LIMIT_REACHED => Self::SharedRecvQueueLimitReached,
+            ibv_event_type::IBV_EVENT_QP_LAST_WQE_REACHED => Self::QueuePairLastWqeReached,
+            ibv_event_type::IBV_EVENT_CLIENT_REREGISTER => Self::ClientReregister,
+            ibv_event_type::IBV_EVENT_GID_CHANGE => Self::GidChange,
+            ibv_event_type::IBV_EVENT_WQ_FATAL => Self::WorkQueueFatal,
+            _ => Self::Unknown(event_type.0),
+        }
+    }
+}
+
+/// The resource affected by an asynchronous device event.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum AsyncEventTarget {
+    QueuePair { qp_num: u32 },
+    CompQueue { handle: u32 },
+    SharedRecvQueue { handle: u32 },
+    Port { port_num: u8 },
+    Device,
+}
+
+/// An asynchronous event reported by an RDMA device.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct AsyncEvent {
+    pub device_index: usize,
+    pub device_name: String,
+    pub kind: AsyncEventKind,
+    pub target: AsyncEventTarget,
+}
+
+impl AsyncEventKind {
+    /// Returns true if the affected resource is no longer usable and should be torn down.
+    pub fn is_fatal(&self) -> bool {
+        matches!(
+            self,
+            Self::CompQueueError
+                | Self::QueuePairFatal
+                | Self::QueuePairRequestError
+                | Self::QueuePairAccessError
+                | Self::DeviceFatal
+                | Self::SharedRecvQueueError
+                | Self::WorkQueueFatal
+        )
+    }
+
+    fn target(&self, event: &verbs::ibv_async_event) -> AsyncEventTarget {
+        match self {
+            Self::CompQueueError => AsyncEventTarget::CompQueue {
+                handle: unsafe { (*event.element.cq).handle },
+            },
+            Self::QueuePairFatal
+            | Self::QueuePairRequestError
+            | Self::QueuePairAccessError
+            | Self::CommunicationEstablished
+            | Self::SendQueueDrained
+            | Self::PathMigrated
+            | Self::PathMigrationError
+            | Self::QueuePairLastWqeReached => AsyncEventTarget::QueuePair {
+                qp_num: unsafe { (*event.element.qp).qp_num },
+            },
+            Self::SharedRecvQueueError | Self::SharedRecvQueueLimitReached => {
+                AsyncEventTarget::SharedRecvQueue {
+                    handle: unsafe { (*event.element.srq).handle },
+                }
+            }
+            Self::PortActive
+            | Self::PortError
+            | Self::LidChange
+            | Self::PkeyChange
+            | Self::SmChange
+            | Self::ClientReregister
+            | Self::GidChange => AsyncEventTarget::Port {
+                port_num: unsafe { event.element.port_num } as u8,
+            },
+            Self::DeviceFatal | Self::WorkQueueFatal | Self::Unknown(_) => AsyncEventTarget::Device,
+        }
+    }
+}
+
+struct AsyncEventMonitorState {
+    stopping: AtomicBool,
+    devices: Devices,
+}
+
+/// Monitors asynchronous events of RDMA devices in a separate thread.
+/// Each event is acknowledged before it is delivered to the callback.
+pub struct AsyncEventMonitor {
+    state: Arc<AsyncEventMonitorState>,
+    handle: Option<std::thread::JoinHandle<()>>,
+}
+
+impl AsyncEventMonitor {
+    const POLL_TIMEOUT_MS: i32 = 100;
+
+    pub fn start<F>(devices: &Devices, callback: F) -> Result<Self>
+    where
+        F: Fn(AsyncEvent) + Send + 'static,
+    {
+        for device in devices {
+            let fd = unsafe { (*device.context_ptr()).async_fd };
+            let flags = unsafe { libc::fcntl(fd, libc::F_GETFL) };
+            let ret = unsafe { libc::fcntl(fd, libc::F_SETFL, flags | libc::O_NONBLOCK) };
+            if flags < 0 || ret < 0 {
+                return Err(ErrorKind::IBSetAsyncFdNonBlockFail.with_errno());
+            }
+        }
+
+        let state = Arc::new(AsyncEventMonitorState {
+            stopping: AtomicBool::new(false),
+            devices: devices.clone(),
+        });
+
+        let handle = std::thread::spawn({
+            let state = state.clone();
+            move || AsyncEventMonitor::run(state, callback)
+        });
+
+        Ok(AsyncEventMonitor {
+            state,
+            handle: Some(handle),
+        })
+    }
+
+    pub fn stop_and_join(&mut self) {
+        self.state.stopping.store(true, Ordering::Release);
+        if let Some(handle) = self.handle.take() {
+            handle.join().unwrap();
+        }
+    }
+
+    fn run<F: Fn(AsyncEvent)>(state: Arc<AsyncEventMonitorState>, callback: F) {
+        let devices = &state.devices;
+        let mut fds = devices
+            .iter()
+            .map(|device| libc::pollfd {
+                fd: unsafe { (*device.context_ptr()).async_fd },
+                events: libc::POLLIN,
+                revents: 0,
+            })
+            .collect::<Vec<_>>();
+
+        while !state.stopping.load(Ordering::Acquire) {
+            let ret =
+                unsafe { libc::poll(fds.as_mut_ptr(), fds.len() as _, Self::POLL_TIMEOUT_MS) };
+            if ret < 0 {
+                let err = std::io::Error::last_os_error();
+                if err.kind() != std::io::ErrorKind::Interrupted {
+                    tracing::error!("poll async event fd failed: {err}");
+                    std::thread::sleep(std::time::Duration::from_millis(1));
+                }
+                continue;
+            }
+
+            for (device, fd) in devices.iter().zip(&fds) {
+                if fd.revents & libc::POLLIN == 0 {
+                    continue;
+                }
+                loop {
+                    let mut event = std::mem::MaybeUninit::<verbs::ibv_async_event>::uninit();
+                    let ret = unsafe {
+                        device
+                            .backend()
+                            .get_async_event(device.context_ptr(), event.as_mut_ptr())
+                    };
+                    if ret != 0 {
+                        break;
+                    }
+                    let mut event = unsafe { event.assume_init() };
+                    let kind = AsyncEventKind::from(event.event_type);
+                    let event_info = AsyncEvent {
+                        device_index: device.index(),
+                        device_name: device.info().name.clone(),
+                        kind,
+                        target: kind.target(&event),
+                    };
+                    unsafe { device.backend().ack_async_event(&mut event) };
+
+                    tracing::debug!("async event: {:?}", event_info);
+                    callback(event_info);
+                }
+            }
+        }
+    }
+}
+
+impl Drop for AsyncEventMonitor {
+    fn drop(&mut self) {
+        self.stop_and_join();
+    }
+}
+
+impl Devices {
+    /// Starts monitoring asynchronous events of the devices, delivering each event to the callback.
+    pub fn monitor_events<F>(&self, callback: F) -> Result<AsyncEventMonitor>
+    where
+        F: Fn(AsyncEvent) + Send + 'static,
+    {
+        AsyncEventMonitor::start(self, callback)
+    }
+
+    /// Subscribes to asynchronous events of the devices as a tokio channel.
+    /// Events are delivered until the returned monitor is dropped.
+    pub fn subscribe_events(
+        &self,
+    ) -> Result<(
+        AsyncEventMonitor,
+        tokio::sync::mpsc::UnboundedReceiver<AsyncEvent>,
+    )> {
+        let (sender, receiver) = tokio::sync::mpsc::unbounded_channel();
+        let monitor = self.monitor_events(move |event| {
+            let _ = sender.send(event);
+        })?;
+        Ok((monitor, receiver))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_async_event_kind() {
+        let kind = AsyncEventKind::from(verbs::ibv_event_type::IBV_EVENT_PORT_ERR);
+        assert_eq!(kind, AsyncEventKind::PortError);
+        assert!(!kind.is_fatal());
+
+        let kind = AsyncEventKind::from(verbs::ibv_event_type::IBV_EVENT_QP_FATAL);
+        assert_eq!(kind, AsyncEventKind::QueuePairFatal);
+        assert!(kind.is_fatal());
+
+        let kind = AsyncEventKind::from(verbs::ibv_event_type::IBV_EVENT_SRQ_LIMIT_REACHED);
+        assert_eq!(kind, AsyncEventKind::SharedRecvQueueLimitReached);
+
+        let kind = AsyncEventKind::from(verbs::ibv_event_type(100));
+        assert_eq!(kind, AsyncEventKind::Unknown(100));
+        assert!(!kind.is_fatal());
+    }
+
+    #[tokio::test]
+    async fn test_subscribe_events() {
+        let devices = Devices::availables().unwrap();
+        let (monitor, mut receiver) = devices.subscribe_events().unwrap();
+        tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+        drop(monitor);
+        while let Some(event) = receiver.recv().await {
+            println!("{:?}", event);
+        }
+    }
+}
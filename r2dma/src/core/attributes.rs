@@ -0,0 +1,383 @@
+use crate::{verbs, AccessFlags};
+use serde::{Deserialize, Serialize, Serializer};
+use std::ffi::CStr;
+
+/// The logical state of a port.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum PortState {
+    Nop,
+    Down,
+    Init,
+    Armed,
+    Active,
+    ActiveDefer,
+    Unknown(u32),
+}
+
+impl From<verbs::ibv_port_state> for PortState {
+    fn from(state: verbs::ibv_port_state) -> Self {
+        match state {
+            verbs::ibv_port_state::IBV_PORT_NOP => Self::Nop,
+            verbs::ibv_port_state::IBV_PORT_DOWN => Self::Down,
+            verbs::ibv_port_state::IBV_PORT_INIT => Self::Init,
+            verbs::ibv_port_state::IBV_PORT_ARMED => Self::Armed,
+            verbs::ibv_port_state::IBV_PORT_ACTIVE => Self::Active,
+            verbs::ibv_port_state::IBV_PORT_ACTIVE_DEFER => Self::ActiveDefer,
+            state => Self::Unknown(state.0),
+        }
+    }
+}
+
+/// The maximum transmission unit of a port or path.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum Mtu {
+    Mtu256,
+    Mtu512,
+    Mtu1024,
+    Mtu2048,
+    Mtu4096,
+    Unknown(u32),
+}
+
+impl Mtu {
+    /// Returns the MTU in bytes, or 0 if the value is unknown.
+    pub fn bytes(&self) -> u32 {
+        match self {
+            Self::Mtu256 => 256,
+            Self::Mtu512 => 512,
+            Self::Mtu1024 => 1024,
+            Self::Mtu2048 => 2048,
+            Self::Mtu4096 => 4096,
+            Self::Unknown(_) => 0,
+        }
+    }
+}
+
+impl From<verbs::ibv_mtu> for Mtu {
+    fn from(mtu: verbs::ibv_mtu) -> Self {
+        match mtu {
+            verbs::ibv_mtu::IBV_MTU_256 => Self::Mtu256,
+            verbs::ibv_mtu::IBV_MTU_512 => Self::Mtu512,
+            verbs::ibv_mtu::IBV_MTU_1024 => Self::Mtu1024,
+            verbs::ibv_mtu::IBV_MTU_2048 => Self::Mtu2048,
+            verbs::ibv_mtu::IBV_MTU_4096 => Self::Mtu4096,
+            mtu => Self::Unknown(mtu.0),
+        }
+    }
+}
+
+impl From<Mtu> for verbs::ibv_mtu {
+    fn from(mtu: Mtu) -> Self {
+        match mtu {
+            Mtu::Mtu256 => verbs::ibv_mtu::IBV_MTU_256,
+            Mtu::Mtu512 => verbs::ibv_mtu::IBV_MTU_512,
+            Mtu::Mtu1024 => verbs::ibv_mtu::IBV_MTU_1024,
+            Mtu::Mtu2048 => verbs::ibv_mtu::IBV_MTU_2048,
+            Mtu::Mtu4096 => verbs::ibv_mtu::IBV_MTU_4096,
+            Mtu::Unknown(mtu) => verbs::ibv_mtu(mtu),
+        }
+    }
+}
+
+/// The link layer of a port.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum LinkLayer {
+    Unspecified,
+    InfiniBand,
+    Ethernet,
+    Unknown(u8),
+}
+
+impl From<u8> for LinkLayer {
+    fn from(link_layer: u8) -> Self {
+        match link_layer {
+            x if x == verbs::IBV_LINK_LAYER::UNSPECIFIED as u8 => Self::Unspecified,
+            x if x == verbs::IBV_LINK_LAYER::INFINIBAND as u8 => Self::InfiniBand,
+            x if x == verbs::IBV_LINK_LAYER::ETHERNET as u8 => Self::Ethernet,
+            x => Self::Unknown(x),
+        }
+    }
+}
+
+/// The atomic operation capability of a device.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum AtomicCap {
+    None,
+    Hca,
+    Global,
+    Unknown(u32),
+}
+
+impl From<verbs::ibv_atomic_cap> for AtomicCap {
+    fn from(cap: verbs::ibv_atomic_cap) -> Self {
+        match cap {
+            verbs::ibv_atomic_cap::IBV_ATOMIC_NONE => Self::None,
+            verbs::ibv_atomic_cap::IBV_ATOMIC_HCA => Self::Hca,
+            verbs::ibv_atomic_cap::IBV_ATOMIC_GLOB => Self::Global,
+            cap => Self::Unknown(cap.0),
+        }
+    }
+}
+
+/// Returns the number of lanes of the encoded `active_width`.
+pub fn link_width_lanes(active_width: u8) -> Option<u32> {
+    match active_width {
+        1 => Some(1),
+        2 => Some(4),
+        4 => Some(8),
+        8 => Some(12),
+        16 => Some(2),
+        _ => None,
+    }
+}
+
+/// Returns the per-lane speed in Gbps of the encoded `active_speed`.
+pub fn link_speed_gbps(active_speed: u8) -> Option<f64> {
+    match active_speed {
+        1 => Some(2.5),
+        2 => Some(5.0),
+        4 | 8 => Some(10.0),
+        16 => Some(14.0),
+        32 => Some(25.0),
+        64 => Some(50.0),
+        128 => Some(100.0),
+        _ => None,
+    }
+}
+
+/// A typed view of `ibv_port_attr`.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct PortAttr {
+    pub state: PortState,
+    pub max_mtu: Mtu,
+    pub active_mtu: Mtu,
+    pub link_layer: LinkLayer,
+    pub lid: u16,
+    pub sm_lid: u16,
+    pub lmc: u8,
+    pub gid_tbl_len: i32,
+    pub pkey_tbl_len: u16,
+    pub max_msg_sz: u32,
+    pub port_cap_flags: u32,
+    pub active_width: u8,
+    pub active_speed: u8,
+    pub phys_state: u8,
+    /// The link width in lanes, if known.
+    pub lanes: Option<u32>,
+    /// The link bandwidth in Gbps, computed from the width and speed.
+    pub bandwidth_gbps: Option<f64>,
+}
+
+impl From<&verbs::ibv_port_attr> for PortAttr {
+    fn from(attr: &verbs::ibv_port_attr) -> Self {
+        let lanes = link_width_lanes(attr.active_width);
+        let bandwidth_gbps = lanes
+            .zip(link_speed_gbps(attr.active_speed))
+            .map(|(lanes, speed)| lanes as f64 * speed);
+        Self {
+            state: attr.state.into(),
+            max_mtu: attr.max_mtu.into(),
+            active_mtu: attr.active_mtu.into(),
+            link_layer: attr.link_layer.into(),
+            lid: attr.lid,
+            sm_lid: attr.sm_lid,
+            lmc: attr.lmc,
+            gid_tbl_len: attr.gid_tbl_len,
+            pkey_tbl_len: attr.pkey_tbl_len,
+            max_msg_sz: attr.max_msg_sz,
+            port_cap_flags: attr.port_cap_flags,
+            active_width: attr.active_width,
+            active_speed: attr.active_speed,
+            phys_state: attr.phys_state,
+            lanes,
+            bandwidth_gbps,
+        }
+    }
+}
+
+impl PortAttr {
+    pub(crate) fn serialize_raw<S: Serializer>(
+        attr: &verbs::ibv_port_attr,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        PortAttr::from(attr).serialize(serializer)
+    }
+}
+
+/// A typed view of `ibv_device_attr`.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct DeviceAttr {
+    pub fw_ver: String,
+    pub node_guid: u64,
+    pub sys_image_guid: u64,
+    pub vendor_id: u32,
+    pub vendor_part_id: u32,
+    pub hw_ver: u32,
+    pub max_mr_size: u64,
+    pub page_size_cap: u64,
+    pub max_qp: i32,
+    pub max_qp_wr: i32,
+    pub max_sge: i32,
+    pub max_sge_rd: i32,
+    pub max_cq: i32,
+    pub max_cqe: i32,
+    pub max_mr: i32,
+    pub max_pd: i32,
+    pub max_qp_rd_atom: i32,
+    pub max_qp_init_rd_atom: i32,
+    pub max_srq: i32,
+    pub max_srq_wr: i32,
+    pub max_srq_sge: i32,
+    pub max_pkeys: u16,
+    pub atomic_cap: AtomicCap,
+    pub device_cap_flags: u32,
+    pub phys_port_cnt: u8,
+}
+
+impl From<&verbs::ibv_device_attr> for DeviceAttr {
+    fn from(attr: &verbs::ibv_device_attr) -> Self {
+        let fw_ver = unsafe { CStr::from_ptr(attr.fw_ver.as_ptr()) }
+            .to_string_lossy()
+            .to_string();
+        Self {
+            fw_ver,
+            node_guid: u64::from_be(attr.node_guid),
+            sys_image_guid: u64::from_be(attr.sys_image_guid),
+            vendor_id: attr.vendor_id,
+            vendor_part_id: attr.vendor_part_id,
+            hw_ver: attr.hw_ver,
+            max_mr_size: attr.max_mr_size,
+            page_size_cap: attr.page_size_cap,
+            max_qp: attr.max_qp,
+            max_qp_wr: attr.max_qp_wr,
+            max_sge: attr.max_sge,
+            max_sge_rd: attr.max_sge_rd,
+            max_cq: attr.max_cq,
+            max_cqe: attr.max_cqe,
+            max_mr: attr.max_mr,
+            max_pd: attr.max_pd,
+            max_qp_rd_atom: attr.max_qp_rd_atom,
+            max_qp_init_rd_atom: attr.max_qp_init_rd_atom,
+            max_srq: attr.max_srq,
+            max_srq_wr: attr.max_srq_wr,
+            max_srq_sge: attr.max_srq_sge,
+            max_pkeys: attr.max_pkeys,
+            atomic_cap: attr.atomic_cap.into(),
+            device_cap_flags: attr.device_cap_flags,
+            phys_port_cnt: attr.phys_port_cnt,
+        }
+    }
+}
+
+impl DeviceAttr {
+    pub(crate) fn serialize_raw<S: Serializer>(
+        attr: &verbs::ibv_device_attr,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        DeviceAttr::from(attr).serialize(serializer)
+    }
+}
+
+/// The on-demand paging capabilities of a device, from `ibv_device_attr_ex`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct OdpCaps {
+    pub general_caps: u64,
+    pub rc_odp_caps: u32,
+    pub ud_odp_caps: u32,
+}
+
+impl From<&verbs::ibv_odp_caps> for OdpCaps {
+    fn from(caps: &verbs::ibv_odp_caps) -> Self {
+        Self {
+            general_caps: caps.general_caps,
+            rc_odp_caps: caps.per_transport_caps.rc_odp_caps,
+            ud_odp_caps: caps.per_transport_caps.ud_odp_caps,
+        }
+    }
+}
+
+impl OdpCaps {
+    pub fn is_supported(&self) -> bool {
+        self.general_caps & verbs::ibv_odp_general_caps::IBV_ODP_SUPPORT.0 as u64 != 0
+    }
+
+    /// Whether the whole address space can be registered at once.
+    pub fn is_implicit_supported(&self) -> bool {
+        self.is_supported()
+            && self.general_caps & verbs::ibv_odp_general_caps::IBV_ODP_SUPPORT_IMPLICIT.0 as u64
+                != 0
+    }
+
+    /// Whether RC queue pairs can use on-demand regions registered with the access flags.
+    pub fn supports_rc(&self, access: AccessFlags) -> bool {
+        use verbs::{ibv_access_flags::*, ibv_odp_transport_cap_bits::*};
+
+        let mut required = IBV_ODP_SUPPORT_SEND | IBV_ODP_SUPPORT_RECV;
+        if access.contains(IBV_ACCESS_REMOTE_READ) {
+            required |= IBV_ODP_SUPPORT_READ;
+        }
+        if access.contains(IBV_ACCESS_REMOTE_WRITE) {
+            required |= IBV_ODP_SUPPORT_WRITE;
+        }
+        if access.contains(IBV_ACCESS_REMOTE_ATOMIC) {
+            required |= IBV_ODP_SUPPORT_ATOMIC;
+        }
+        self.is_supported() && self.rc_odp_caps & required.0 == required.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_port_attr() {
+        let attr = verbs::ibv_port_attr {
+            state: verbs::ibv_port_state::IBV_PORT_ACTIVE,
+            max_mtu: verbs::ibv_mtu::IBV_MTU_4096,
+            active_mtu: verbs::ibv_mtu::IBV_MTU_1024,
+            link_layer: verbs::IBV_LINK_LAYER::ETHERNET as u8,
+            active_width: 2,
+            active_speed: 32,
+            ..Default::default()
+        };
+        let attr = PortAttr::from(&attr);
+        assert_eq!(attr.state, PortState::Active);
+        assert_eq!(attr.max_mtu.bytes(), 4096);
+        assert_eq!(attr.active_mtu, Mtu::Mtu1024);
+        assert_eq!(attr.link_layer, LinkLayer::Ethernet);
+        assert_eq!(attr.lanes, Some(4));
+        assert_eq!(attr.bandwidth_gbps, Some(100.0));
+
+        let json = serde_json::to_value(&attr).unwrap();
+        assert_eq!(json["state"], "Active");
+        assert_eq!(json["active_mtu"], "Mtu1024");
+        assert_eq!(json["link_layer"], "Ethernet");
+    }
+
+    #[test]
+    fn test_unknown_values() {
+        // values newer than the headers are kept instead of being undefined behavior.
+        assert_eq!(
+            PortState::from(verbs::ibv_port_state(100)),
+            PortState::Unknown(100)
+        );
+        assert_eq!(Mtu::from(verbs::ibv_mtu(100)), Mtu::Unknown(100));
+        assert_eq!(Mtu::Unknown(100).bytes(), 0);
+        assert_eq!(
+            AtomicCap::from(verbs::ibv_atomic_cap(100)),
+            AtomicCap::Unknown(100)
+        );
+    }
+
+    #[test]
+    fn test_link_decoding() {
+        assert_eq!(link_width_lanes(1), Some(1));
+        assert_eq!(link_width_lanes(8), Some(12));
+        assert_eq!(link_width_lanes(3), None);
+        assert_eq!(link_speed_gbps(64), Some(50.0));
+        assert_eq!(link_speed_gbps(0), None);
+        assert_eq!(LinkLayer::from(7), LinkLayer::Unknown(7));
+        assert!(Mtu::Mtu512 < Mtu::Mtu2048);
+    }
+}
@@ -1,8 +1,13 @@
-use super::Devices;
-use crate::{verbs, ErrorKind, Result};
-use std::sync::Arc;
+use super::{socket::OutstandingWork, Device, Devices};
+use crate::{verbs, Buffer, ErrorKind, Result, VerbsBackend};
+use std::{
+    collections::HashMap,
+    ops::{Deref, DerefMut},
+    sync::{Arc, RwLock, Weak},
+    time::Duration,
+};
 
-struct RawCompQueue(*mut verbs::ibv_cq);
+struct RawCompQueue(*mut verbs::ibv_cq, Arc<dyn VerbsBackend>);
 impl std::ops::Deref for RawCompQueue {
     type Target = verbs::ibv_cq;
 
@@ -12,47 +17,97 @@ impl std::ops::Deref for RawCompQueue {
 }
 impl Drop for RawCompQueue {
     fn drop(&mut self) {
-        let _ = unsafe { verbs::ibv_destroy_cq(self.0) };
+        let _ = unsafe { self.1.destroy_cq(self.0) };
     }
 }
 unsafe impl Send for RawCompQueue {}
 unsafe impl Sync for RawCompQueue {}
 
+struct RawCompChannel(*mut verbs::ibv_comp_channel, Arc<dyn VerbsBackend>);
+impl Drop for RawCompChannel {
+    fn drop(&mut self) {
+        let _ = unsafe { self.1.destroy_comp_channel(self.0) };
+    }
+}
+unsafe impl Send for RawCompChannel {}
+unsafe impl Sync for RawCompChannel {}
+
 /// Represents a collection of completion queues for RDMA devices.
 pub struct CompQueues {
     comp_queues: Vec<RawCompQueue>,
+    // dropped after the completion queues attached to them.
+    channels: Vec<RawCompChannel>,
     pub cqe: usize,
+    // keyed by device index and queue pair number, which is only unique within a device.
+    outstanding: RwLock<HashMap<(usize, u32), Weak<OutstandingWork>>>,
     _devices: Devices,
 }
 
 impl CompQueues {
     pub fn create(devices: &Devices, max_cqe: u32) -> Result<Arc<Self>> {
+        Self::create_impl(devices, max_cqe, false)
+    }
+
+    /// Creates completion queues attached to a completion channel per device, which allows blocking on `wait`.
+    pub fn create_with_channel(devices: &Devices, max_cqe: u32) -> Result<Arc<Self>> {
+        Self::create_impl(devices, max_cqe, true)
+    }
+
+    fn create_impl(devices: &Devices, max_cqe: u32, with_channel: bool) -> Result<Arc<Self>> {
+        let mut channels = Vec::new();
         let mut comp_queues = Vec::with_capacity(devices.len());
         for device in devices {
+            device.info().check_cqe(max_cqe)?;
+            let channel = if with_channel {
+                let channel = Self::create_channel(device)?;
+                let ptr = channel.0;
+                channels.push(channel);
+                ptr
+            } else {
+                std::ptr::null_mut()
+            };
             let ptr = unsafe {
-                verbs::ibv_create_cq(
+                device.backend().create_cq(
                     device.context_ptr(),
                     max_cqe as _,
                     std::ptr::null_mut(),
-                    std::ptr::null_mut(),
+                    channel,
                     0,
                 )
             };
             if ptr.is_null() {
                 return Err(ErrorKind::IBCreateCompQueueFail.with_errno());
             }
-            comp_queues.push(RawCompQueue(ptr));
+            comp_queues.push(RawCompQueue(ptr, device.backend().clone()));
         }
         let cqe = comp_queues.first().unwrap().cqe as usize;
 
         let this = Self {
             comp_queues,
+            channels,
             cqe,
+            outstanding: Default::default(),
             _devices: devices.clone(),
         };
         Ok(Arc::new(this))
     }
 
+    fn create_channel(device: &Device) -> Result<RawCompChannel> {
+        let ptr = unsafe { device.backend().create_comp_channel(device.context_ptr()) };
+        if ptr.is_null() {
+            return Err(ErrorKind::IBCreateCompChannelFail.with_errno());
+        }
+        let channel = RawCompChannel(ptr, device.backend().clone());
+
+        let fd = unsafe { (*ptr).fd };
+        let flags = unsafe { libc::fcntl(fd, libc::F_GETFL) };
+        let ret = unsafe { libc::fcntl(fd, libc::F_SETFL, flags | libc::O_NONBLOCK) };
+        if flags < 0 || ret < 0 {
+            return Err(ErrorKind::IBSetCompChannelNonBlockFail.with_errno());
+        }
+        Ok(channel)
+    }
+
     pub(crate) fn comp_queue_ptr(&self, device_index: usize) -> *mut verbs::ibv_cq {
         self.comp_queues[device_index].0
     }
@@ -61,24 +116,155 @@ impl CompQueues {
         self.comp_queues.len() * self.cqe
     }
 
-    pub fn poll_cq<'a>(&self, wcs: &'a mut [verbs::ibv_wc]) -> Result<&'a mut [verbs::ibv_wc]> {
+    /// Polls the completion queue of each device into `wcs`.
+    /// The returned completions remember the device each came from, see `Completions::device_index`.
+    pub fn poll_cq<'a>(&self, wcs: &'a mut [verbs::ibv_wc]) -> Result<Completions<'a>> {
         assert!(wcs.len() >= self.comp_queues.len());
         let mut offset = 0usize;
+        let mut ends = Vec::new();
         let num_entries = (wcs.len() / self.comp_queues.len()) as i32;
-        for comp_queue in &self.comp_queues {
+        for (device_index, comp_queue) in self.comp_queues.iter().enumerate() {
             let num = unsafe {
-                verbs::ibv_poll_cq(comp_queue.0, num_entries, wcs.as_mut_ptr().add(offset) as _)
+                comp_queue
+                    .1
+                    .poll_cq(comp_queue.0, num_entries, wcs.as_mut_ptr().add(offset) as _)
             };
-            if num >= 0 {
+            if num > 0 {
                 offset += num as usize;
-            } else {
+                ends.push((offset, device_index));
+            } else if num < 0 {
                 tracing::error!(
                     "poll comp queue failed: {}",
                     std::io::Error::last_os_error()
                 );
             }
         }
-        Ok(&mut wcs[..offset])
+        Ok(Completions {
+            wcs: &mut wcs[..offset],
+            ends,
+        })
+    }
+
+    pub(crate) fn register(
+        &self,
+        device_index: usize,
+        qp_num: u32,
+        outstanding: &Arc<OutstandingWork>,
+    ) {
+        let mut map = self.outstanding.write().unwrap();
+        map.insert((device_index, qp_num), Arc::downgrade(outstanding));
+    }
+
+    pub(crate) fn unregister(&self, device_index: usize, qp_num: u32) {
+        let mut map = self.outstanding.write().unwrap();
+        map.remove(&(device_index, qp_num));
+    }
+
+    /// Takes the buffer of the completed work request from the socket which posted it, given the
+    /// device the completion came from. Receives of a queue pair attached to a shared receive queue
+    /// are taken from that queue. Dropping the returned buffer releases it to its pool.
+    pub fn take_buffer(&self, device_index: usize, wc: &verbs::ibv_wc) -> Option<Buffer> {
+        let outstanding = self
+            .outstanding
+            .read()
+            .unwrap()
+            .get(&(device_index, wc.qp_num))?
+            .upgrade()?;
+        outstanding.complete(wc)
+    }
+
+    pub fn has_channel(&self) -> bool {
+        !self.channels.is_empty()
+    }
+
+    /// Requests a notification on the completion channel for the next completion of each queue.
+    pub fn req_notify(&self) -> Result<()> {
+        for comp_queue in &self.comp_queues {
+            let ret = unsafe { comp_queue.1.req_notify_cq(comp_queue.0, 0) };
+            if ret != 0 {
+                return Err(ErrorKind::IBReqNotifyCompQueueFail.with_errno());
+            }
+        }
+        Ok(())
+    }
+
+    /// Blocks until a requested notification arrives or the timeout elapses.
+    /// Returns the number of notifications consumed, which must be re-requested before waiting again.
+    pub fn wait(&self, timeout: Duration) -> Result<usize> {
+        let mut fds = self
+            .channels
+            .iter()
+            .map(|channel| libc::pollfd {
+                fd: unsafe { (*channel.0).fd },
+                events: libc::POLLIN,
+                revents: 0,
+            })
+            .collect::<Vec<_>>();
+        let ret = unsafe { libc::poll(fds.as_mut_ptr(), fds.len() as _, timeout.as_millis() as _) };
+        if ret < 0 {
+            let err = std::io::Error::last_os_error();
+            if err.kind() == std::io::ErrorKind::Interrupted {
+                return Ok(0);
+            }
+            return Err(ErrorKind::IBGetCompQueueEventFail.with_errno());
+        }
+
+        let mut count = 0;
+        for (channel, fd) in self.channels.iter().zip(&fds) {
+            if fd.revents & libc::POLLIN == 0 {
+                continue;
+            }
+            loop {
+                let mut cq = std::ptr::null_mut();
+                let mut cq_context = std::ptr::null_mut();
+                let ret = unsafe { channel.1.get_cq_event(channel.0, &mut cq, &mut cq_context) };
+                if ret != 0 {
+                    break;
+                }
+                unsafe { channel.1.ack_cq_events(cq, 1) };
+                count += 1;
+            }
+        }
+        Ok(count)
+    }
+}
+
+/// Work completions polled by `CompQueues::poll_cq`, grouped by the device they came from.
+pub struct Completions<'a> {
+    wcs: &'a mut [verbs::ibv_wc],
+    // the end offset of the completions of each device which has any.
+    ends: Vec<(usize, usize)>,
+}
+
+impl Completions<'_> {
+    /// Returns the index of the device whose completion queue produced the `i`-th completion.
+    pub fn device_index(&self, i: usize) -> usize {
+        let group = self.ends.partition_point(|&(end, _)| end <= i);
+        self.ends[group].1
+    }
+
+    /// Iterates over the completions along with the index of the device each came from.
+    pub fn iter_with_device(&self) -> impl Iterator<Item = (usize, &verbs::ibv_wc)> {
+        let mut start = 0;
+        self.ends.iter().flat_map(move |&(end, device_index)| {
+            let wcs = &self.wcs[start..end];
+            start = end;
+            wcs.iter().map(move |wc| (device_index, wc))
+        })
+    }
+}
+
+impl Deref for Completions<'_> {
+    type Target = [verbs::ibv_wc];
+
+    fn deref(&self) -> &Self::Target {
+        self.wcs
+    }
+}
+
+impl DerefMut for Completions<'_> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        self.wcs
     }
 }
 
@@ -87,6 +273,7 @@ impl std::fmt::Debug for CompQueues {
         f.debug_struct("CompQueue")
             .field("cqe", &self.cqe)
             .field("num_cqs", &self.comp_queues.len())
+            .field("has_channel", &self.has_channel())
             .finish()
     }
 }
@@ -94,6 +281,7 @@ impl std::fmt::Debug for CompQueues {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::testing::{cap, connect, socket_pair};
 
     #[test]
     fn test_comp_queue() {
@@ -102,4 +290,67 @@ mod tests {
         let comp_queues = CompQueues::create(&devices, max_cqe).unwrap();
         println!("{:#?}", comp_queues);
     }
+
+    #[test]
+    fn test_comp_channel() {
+        use crate::*;
+
+        let devices = MockVerbs::devices(1).unwrap();
+        let comp_queues = CompQueues::create_with_channel(&devices, 128).unwrap();
+        assert!(comp_queues.has_channel());
+        assert_eq!(comp_queues.wait(Duration::from_millis(1)).unwrap(), 0);
+
+        let (socket_a, socket_b) =
+            socket_pair(&devices, &comp_queues, &comp_queues, Socket::create);
+        connect(&socket_a, &socket_b);
+
+        let buffer_pool = BufferPool::create(4096, 4, &devices).unwrap();
+        comp_queues.req_notify().unwrap();
+        socket_b
+            .post_recv(1, buffer_pool.allocate().unwrap())
+            .unwrap();
+        socket_a
+            .post_send(2, buffer_pool.allocate().unwrap())
+            .unwrap();
+
+        // one notification per request, no matter how many completions arrived.
+        assert_eq!(comp_queues.wait(Duration::from_millis(100)).unwrap(), 1);
+        assert_eq!(comp_queues.wait(Duration::from_millis(1)).unwrap(), 0);
+        let mut wcs = vec![verbs::ibv_wc::default(); 128];
+        assert_eq!(comp_queues.poll_cq(&mut wcs).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_same_qp_num_on_two_devices() {
+        use crate::*;
+
+        let devices = MockVerbs::devices(2).unwrap();
+        let comp_queues = CompQueues::create(&devices, 128).unwrap();
+        let queue_pair_a = QueuePair::create(&devices, 0, &comp_queues, cap()).unwrap();
+        let socket_a = Socket::create(Arc::new(queue_pair_a));
+        let queue_pair_b = QueuePair::create(&devices, 1, &comp_queues, cap()).unwrap();
+        let socket_b = Socket::create(Arc::new(queue_pair_b));
+        assert_eq!(socket_a.qp_num(), socket_b.qp_num());
+        connect(&socket_a, &socket_b);
+
+        let buffer_pool = BufferPool::create(4096, 4, &devices).unwrap();
+        socket_b
+            .post_recv(1, buffer_pool.allocate().unwrap())
+            .unwrap();
+        socket_a
+            .post_send(1, buffer_pool.allocate().unwrap())
+            .unwrap();
+
+        let mut wcs = vec![verbs::ibv_wc::default(); 128];
+        let comp = comp_queues.poll_cq(&mut wcs).unwrap();
+        assert_eq!(comp.len(), 2);
+        assert_eq!(comp.device_index(0), 0);
+        assert_eq!(comp.device_index(1), 1);
+        for (device_index, wc) in comp.iter_with_device() {
+            assert_eq!(wc.is_recv(), device_index == 1);
+            assert!(comp_queues.take_buffer(device_index, wc).is_some());
+        }
+        assert_eq!(socket_a.outstanding() + socket_b.outstanding(), 0);
+        assert_eq!(buffer_pool.stats().in_use, 0);
+    }
 }
@@ -1,6 +1,7 @@
-use std::collections::HashSet;
+use serde::Serialize;
+use std::{collections::HashSet, time::Duration};
 
-#[derive(Debug, PartialEq, Eq, Hash)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize)]
 pub enum GidType {
     IB,
     RoCEv1,
@@ -11,12 +12,91 @@ pub enum GidType {
 #[derive(Debug, Default)]
 pub struct Config {
     pub device: DeviceConfig,
+    pub event_loop: EventLoopConfig,
 }
 
-#[derive(Debug, Default)]
+#[derive(Debug, Clone, Default)]
 pub struct DeviceConfig {
     pub device_filter: HashSet<String>,
     pub gid_type_filter: HashSet<GidType>,
     pub skip_inactive_port: bool,
     pub roce_v2_skip_link_local_addr: bool,
 }
+
+/// How new queue pairs are assigned to the loops of an event loop group.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub enum LoopAssignment {
+    #[default]
+    RoundRobin,
+    /// Assigns to the loop pinned to the CPU of the calling thread, falling back to round-robin.
+    CpuAffinity,
+}
+
+/// How an event loop waits when a poll returns no completions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum PollStrategy {
+    /// Polls continuously without giving up the CPU, for the lowest latency.
+    BusyPoll,
+    /// Spins for `spin_polls` empty polls, then yields for `yield_polls` empty polls, then parks for `park` between polls.
+    Backoff {
+        spin_polls: u32,
+        yield_polls: u32,
+        park: Duration,
+    },
+    /// Blocks on a completion channel until a completion arrives, waking up at least every `timeout`.
+    Blocking { timeout: Duration },
+}
+
+impl Default for PollStrategy {
+    fn default() -> Self {
+        Self::Backoff {
+            spin_polls: 0,
+            yield_polls: 0,
+            park: Duration::from_millis(1),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct EventLoopConfig {
+    pub num_loops: usize,
+    pub max_cqe: u32,
+    pub poll_strategy: PollStrategy,
+    pub assignment: LoopAssignment,
+    /// CPUs to pin the loops to, used with `LoopAssignment::CpuAffinity`. Defaults to `0..num_loops`.
+    pub cpus: Vec<usize>,
+}
+
+impl Default for EventLoopConfig {
+    fn default() -> Self {
+        Self {
+            num_loops: 1,
+            max_cqe: 1024,
+            poll_strategy: PollStrategy::default(),
+            assignment: LoopAssignment::default(),
+            cpus: vec![],
+        }
+    }
+}
+
+/// Credit-based flow control of a socket, see `Socket::create_with_flow_control`.
+#[derive(Debug, Clone, Copy)]
+pub struct FlowControlConfig {
+    /// The receives each side posts before the first send, which the peer may consume unadvertised.
+    pub initial_credits: u32,
+    /// Advertises freshly posted receives in a standalone update once this many are pending,
+    /// instead of waiting for the next send to piggyback them.
+    pub update_threshold: u32,
+    /// How long a send waits for credits.
+    pub send_timeout: Duration,
+}
+
+impl Default for FlowControlConfig {
+    fn default() -> Self {
+        Self {
+            initial_credits: 64,
+            update_threshold: 16,
+            send_timeout: Duration::from_secs(1),
+        }
+    }
+}
@@ -0,0 +1,327 @@
+use super::Device;
+use crate::{Error, ErrorKind, Result};
+use serde::Serialize;
+use std::{
+    collections::BTreeMap,
+    time::{Duration, Instant, SystemTime},
+};
+
+/// The counters of a port, read from `counters/` and `hw_counters/` under the port in sysfs.
+/// Counters the device does not provide are `None`, and unknown ones are kept in `other`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct PortCounters {
+    pub port_num: u8,
+    /// Transmitted data in 4-byte words, see `xmit_bytes`.
+    pub port_xmit_data: Option<u64>,
+    /// Received data in 4-byte words, see `rcv_bytes`.
+    pub port_rcv_data: Option<u64>,
+    pub port_xmit_packets: Option<u64>,
+    pub port_rcv_packets: Option<u64>,
+    pub port_xmit_discards: Option<u64>,
+    pub port_xmit_wait: Option<u64>,
+    pub port_rcv_errors: Option<u64>,
+    pub symbol_error: Option<u64>,
+    pub link_downed: Option<u64>,
+    pub link_error_recovery: Option<u64>,
+    pub out_of_sequence: Option<u64>,
+    pub packet_seq_err: Option<u64>,
+    pub duplicate_request: Option<u64>,
+    pub implied_nak_seq_err: Option<u64>,
+    pub local_ack_timeout_err: Option<u64>,
+    pub rnr_nak_retry_err: Option<u64>,
+    pub np_cnp_sent: Option<u64>,
+    pub np_ecn_marked_roce_packets: Option<u64>,
+    pub rp_cnp_handled: Option<u64>,
+    pub rp_cnp_ignored: Option<u64>,
+    pub other: BTreeMap<String, u64>,
+}
+
+impl PortCounters {
+    fn field_mut(&mut self, name: &str) -> Option<&mut Option<u64>> {
+        Some(match name {
+            "port_xmit_data" => &mut self.port_xmit_data,
+            "port_rcv_data" => &mut self.port_rcv_data,
+            "port_xmit_packets" => &mut self.port_xmit_packets,
+            "port_rcv_packets" => &mut self.port_rcv_packets,
+            "port_xmit_discards" => &mut self.port_xmit_discards,
+            "port_xmit_wait" => &mut self.port_xmit_wait,
+            "port_rcv_errors" => &mut self.port_rcv_errors,
+            "symbol_error" => &mut self.symbol_error,
+            "link_downed" => &mut self.link_downed,
+            "link_error_recovery" => &mut self.link_error_recovery,
+            "out_of_sequence" => &mut self.out_of_sequence,
+            "packet_seq_err" => &mut self.packet_seq_err,
+            "duplicate_request" => &mut self.duplicate_request,
+            "implied_nak_seq_err" => &mut self.implied_nak_seq_err,
+            "local_ack_timeout_err" => &mut self.local_ack_timeout_err,
+            "rnr_nak_retry_err" => &mut self.rnr_nak_retry_err,
+            "np_cnp_sent" => &mut self.np_cnp_sent,
+            "np_ecn_marked_roce_packets" => &mut self.np_ecn_marked_roce_packets,
+            "rp_cnp_handled" => &mut self.rp_cnp_handled,
+            "rp_cnp_ignored" => &mut self.rp_cnp_ignored,
+            _ => return None,
+        })
+    }
+
+    /// Builds the counters of a port from `(name, value)` pairs as read from sysfs.
+    pub fn from_values(port_num: u8, values: impl IntoIterator<Item = (String, u64)>) -> Self {
+        let mut counters = Self {
+            port_num,
+            ..Default::default()
+        };
+        for (name, value) in values {
+            match counters.field_mut(&name) {
+                Some(field) => *field = Some(value),
+                None => {
+                    counters.other.insert(name, value);
+                }
+            }
+        }
+        counters
+    }
+
+    fn fields(&self) -> [(&'static str, Option<u64>); 20] {
+        [
+            ("port_xmit_data", self.port_xmit_data),
+            ("port_rcv_data", self.port_rcv_data),
+            ("port_xmit_packets", self.port_xmit_packets),
+            ("port_rcv_packets", self.port_rcv_packets),
+            ("port_xmit_discards", self.port_xmit_discards),
+            ("port_xmit_wait", self.port_xmit_wait),
+            ("port_rcv_errors", self.port_rcv_errors),
+            ("symbol_error", self.symbol_error),
+            ("link_downed", self.link_downed),
+            ("link_error_recovery", self.link_error_recovery),
+            ("out_of_sequence", self.out_of_sequence),
+            ("packet_seq_err", self.packet_seq_err),
+            ("duplicate_request", self.duplicate_request),
+            ("implied_nak_seq_err", self.implied_nak_seq_err),
+            ("local_ack_timeout_err", self.local_ack_timeout_err),
+            ("rnr_nak_retry_err", self.rnr_nak_retry_err),
+            ("np_cnp_sent", self.np_cnp_sent),
+            (
+                "np_ecn_marked_roce_packets",
+                self.np_ecn_marked_roce_packets,
+            ),
+            ("rp_cnp_handled", self.rp_cnp_handled),
+            ("rp_cnp_ignored", self.rp_cnp_ignored),
+        ]
+    }
+
+    /// Returns every counter the device provides by name.
+    pub fn values(&self) -> BTreeMap<String, u64> {
+        let mut values = self.other.clone();
+        for (name, value) in self.fields() {
+            if let Some(value) = value {
+                values.insert(name.to_string(), value);
+            }
+        }
+        values
+    }
+
+    pub fn xmit_bytes(&self) -> Option<u64> {
+        self.port_xmit_data.map(|words| words * 4)
+    }
+
+    pub fn rcv_bytes(&self) -> Option<u64> {
+        self.port_rcv_data.map(|words| words * 4)
+    }
+}
+
+/// A snapshot of the port counters of a device.
+#[derive(Debug, Clone, Serialize)]
+pub struct DeviceCounters {
+    pub device: String,
+    /// Milliseconds since the Unix epoch when the counters were read.
+    pub timestamp_ms: u64,
+    pub ports: Vec<PortCounters>,
+    #[serde(skip)]
+    read_at: Instant,
+}
+
+impl DeviceCounters {
+    pub fn new(device: String, ports: Vec<PortCounters>) -> Self {
+        let timestamp_ms = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis() as u64;
+        Self {
+            device,
+            timestamp_ms,
+            ports,
+            read_at: Instant::now(),
+        }
+    }
+
+    pub fn port(&self, port_num: u8) -> Option<&PortCounters> {
+        self.ports.iter().find(|port| port.port_num == port_num)
+    }
+
+    /// Returns the time between an earlier snapshot and this one.
+    pub fn elapsed_since(&self, earlier: &DeviceCounters) -> Duration {
+        self.read_at.saturating_duration_since(earlier.read_at)
+    }
+
+    /// Returns the per-second rates of the counters since an earlier snapshot of the same device.
+    /// Counters which went backwards, e.g. after a reset, are rated 0.
+    pub fn rates(&self, earlier: &DeviceCounters) -> DeviceCounterRates {
+        self.rates_over(earlier, self.elapsed_since(earlier))
+    }
+
+    fn rates_over(&self, earlier: &DeviceCounters, interval: Duration) -> DeviceCounterRates {
+        let secs = interval.as_secs_f64();
+        let per_sec = |now: u64, before: u64| {
+            if secs > 0.0 {
+                now.saturating_sub(before) as f64 / secs
+            } else {
+                0.0
+            }
+        };
+        let ports = self
+            .ports
+            .iter()
+            .filter_map(|port| {
+                let before = earlier.port(port.port_num)?.values();
+                let rates = port
+                    .values()
+                    .into_iter()
+                    .filter_map(|(name, now)| {
+                        let rate = per_sec(now, *before.get(&name)?);
+                        Some((name, rate))
+                    })
+                    .collect::<BTreeMap<_, _>>();
+                Some(PortCounterRates {
+                    port_num: port.port_num,
+                    xmit_bytes_per_sec: rates.get("port_xmit_data").map(|words| words * 4.0),
+                    rcv_bytes_per_sec: rates.get("port_rcv_data").map(|words| words * 4.0),
+                    rates,
+                })
+            })
+            .collect();
+        DeviceCounterRates {
+            device: self.device.clone(),
+            interval_secs: secs,
+            ports,
+        }
+    }
+}
+
+/// The per-second rates of the counters of a port.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct PortCounterRates {
+    pub port_num: u8,
+    pub xmit_bytes_per_sec: Option<f64>,
+    pub rcv_bytes_per_sec: Option<f64>,
+    /// The rates of every counter present in both snapshots, by name.
+    pub rates: BTreeMap<String, f64>,
+}
+
+impl PortCounterRates {
+    pub fn rate(&self, name: &str) -> Option<f64> {
+        self.rates.get(name).copied()
+    }
+}
+
+/// The per-second rates of the port counters of a device between two snapshots.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct DeviceCounterRates {
+    pub device: String,
+    pub interval_secs: f64,
+    pub ports: Vec<PortCounterRates>,
+}
+
+impl Device {
+    /// Reads the counters of every physical port of the device.
+    pub fn counters(&self) -> Result<DeviceCounters> {
+        let info = self.info();
+        let mut ports = Vec::with_capacity(info.device_attr.phys_port_cnt as usize);
+        for port_num in 1..=info.device_attr.phys_port_cnt {
+            let values = self
+                .backend()
+                .read_port_counters(&info.ibdev_path, port_num)
+                .map_err(|err| {
+                    Error::new(
+                        ErrorKind::IBReadCountersFail,
+                        format!("{} port {port_num}: {err}", info.name),
+                    )
+                })?;
+            ports.push(PortCounters::from_values(port_num, values));
+        }
+        Ok(DeviceCounters::new(info.name.clone(), ports))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{testing::connected_sockets, *};
+
+    #[test]
+    fn test_counter_rates() {
+        let values = |data: u64, rnr: u64| {
+            vec![
+                ("port_xmit_data".to_string(), data),
+                ("rnr_nak_retry_err".to_string(), rnr),
+                ("vendor_counter".to_string(), 7),
+            ]
+        };
+        let earlier = DeviceCounters::new(
+            "dev".into(),
+            vec![PortCounters::from_values(1, values(100, 5))],
+        );
+        let later = DeviceCounters::new(
+            "dev".into(),
+            vec![PortCounters::from_values(1, values(600, 3))],
+        );
+        let port = later.port(1).unwrap();
+        assert_eq!(port.xmit_bytes(), Some(2400));
+        assert_eq!(port.port_rcv_data, None);
+        assert_eq!(port.other["vendor_counter"], 7);
+        assert_eq!(port.values().len(), 3);
+
+        let rates = later.rates_over(&earlier, Duration::from_secs(2));
+        let port = &rates.ports[0];
+        assert_eq!(port.xmit_bytes_per_sec, Some(1000.0));
+        assert_eq!(port.rcv_bytes_per_sec, None);
+        assert_eq!(port.rate("port_xmit_data"), Some(250.0));
+        assert_eq!(port.rate("rnr_nak_retry_err"), Some(0.0));
+        assert_eq!(port.rate("vendor_counter"), Some(0.0));
+
+        let json = serde_json::to_value(&later).unwrap();
+        assert_eq!(json["ports"][0]["port_xmit_data"], 600);
+        assert!(json["ports"][0]["port_rcv_data"].is_null());
+    }
+
+    #[test]
+    fn test_device_counters() {
+        let (devices, comp_queues, socket_a, socket_b) = connected_sockets(1);
+
+        let device = &devices[0];
+        let earlier = device.counters().unwrap();
+        let port = earlier.port(1).unwrap();
+        assert_eq!(port.port_xmit_data, Some(0));
+        assert_eq!(port.rnr_nak_retry_err, Some(0));
+
+        let buffer_pool = BufferPool::create(4096, 4, &devices).unwrap();
+        socket_b
+            .post_recv(0, buffer_pool.allocate().unwrap())
+            .unwrap();
+        let mut buf = buffer_pool.allocate().unwrap();
+        buf.fill(1);
+        socket_a.post_send(1, buf).unwrap();
+        let counters = device.counters().unwrap();
+        let port = counters.port(1).unwrap();
+        assert_eq!(port.xmit_bytes(), Some(4096));
+        assert_eq!(port.rcv_bytes(), Some(4096));
+        assert_eq!(port.port_xmit_packets, Some(1));
+
+        // a send without a posted receive exhausts the RNR retries.
+        socket_a
+            .post_send(2, buffer_pool.allocate().unwrap())
+            .unwrap();
+        let port = device.counters().unwrap().ports.remove(0);
+        assert_eq!(port.rnr_nak_retry_err, Some(1));
+        let rates = counters.rates(&earlier);
+        assert_eq!(rates.ports[0].rate("rnr_nak_retry_err"), Some(0.0));
+    }
+}
@@ -0,0 +1,103 @@
+use super::FlowControlConfig;
+use crate::{verbs, Error, ErrorKind, Result};
+use std::sync::{Condvar, Mutex};
+
+/// Flags an immediate value which only carries credits, sent without a payload.
+pub(crate) const CREDIT_UPDATE: u32 = 1 << 31;
+
+#[derive(Debug, Default)]
+struct CreditState {
+    /// Receives the peer has advertised and this side has not consumed yet.
+    send: u32,
+    /// Receives posted by this side and not advertised to the peer yet.
+    unadvertised: u32,
+    /// Receives covered by the initial credits of the peer, which need no advertisement.
+    preadvertised: u32,
+}
+
+/// Credit-based flow control of a reliable connection: each send consumes a receive of the peer, and
+/// freshly posted receives are advertised in the immediate data of sends.
+#[derive(Debug)]
+pub(crate) struct Credits {
+    state: Mutex<CreditState>,
+    available: Condvar,
+    config: FlowControlConfig,
+}
+
+impl Credits {
+    pub(crate) fn new(config: FlowControlConfig) -> Self {
+        Self {
+            state: Mutex::new(CreditState {
+                send: config.initial_credits,
+                unadvertised: 0,
+                preadvertised: config.initial_credits,
+            }),
+            available: Condvar::new(),
+            config,
+        }
+    }
+
+    pub(crate) fn send_credits(&self) -> u32 {
+        self.state.lock().unwrap().send
+    }
+
+    /// Takes a credit for a send, waiting for one up to the timeout.
+    /// Returns the credits to piggyback on the send.
+    pub(crate) fn acquire(&self) -> Result<u32> {
+        let state = self.state.lock().unwrap();
+        // the last credit is kept for advertising receives, unless this send does it.
+        let (mut state, result) = self
+            .available
+            .wait_timeout_while(state, self.config.send_timeout, |state| {
+                state.send == 0 || (state.send == 1 && state.unadvertised == 0)
+            })
+            .unwrap();
+        if result.timed_out() {
+            return Err(Error::new(
+                ErrorKind::IBWaitSendCreditTimeout,
+                format!("{} credits available", state.send),
+            ));
+        }
+        state.send -= 1;
+        Ok(std::mem::take(&mut state.unadvertised))
+    }
+
+    /// Gives back a credit and the piggybacked credits of a send which failed to post.
+    pub(crate) fn release(&self, advertised: u32) {
+        let mut state = self.state.lock().unwrap();
+        state.send += 1;
+        state.unadvertised += advertised;
+        self.available.notify_all();
+    }
+
+    /// Counts a posted receive. Returns the credits to advertise in a standalone update, if one is due.
+    pub(crate) fn on_post_recv(&self) -> Option<u32> {
+        let mut state = self.state.lock().unwrap();
+        if state.preadvertised > 0 {
+            state.preadvertised -= 1;
+            return None;
+        }
+        state.unadvertised += 1;
+        self.available.notify_all();
+        if state.unadvertised >= self.config.update_threshold && state.send > 0 {
+            state.send -= 1;
+            Some(std::mem::take(&mut state.unadvertised))
+        } else {
+            None
+        }
+    }
+
+    /// Adds the credits advertised by the peer in a received message.
+    pub(crate) fn on_completion(&self, wc: &verbs::ibv_wc) {
+        if wc.opcode != verbs::ibv_wc_opcode::IBV_WC_RECV || !wc.wc_status().is_success() {
+            return;
+        }
+        if let Some(imm_data) = wc.imm_data() {
+            let credits = imm_data & !CREDIT_UPDATE;
+            if credits > 0 {
+                self.state.lock().unwrap().send += credits;
+                self.available.notify_all();
+            }
+        }
+    }
+}
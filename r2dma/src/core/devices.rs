@@ -1,22 +1,24 @@
-use super::{DeviceConfig, GidType};
-use crate::{verbs, Error, ErrorKind, Result};
+use super::{DeviceAttr, DeviceConfig, GidType, OdpCaps, PortAttr, PortState};
+use crate::{verbs, Error, ErrorKind, Result, VerbsBackend};
+use serde::{ser::SerializeSeq, Serialize, Serializer};
 use std::{
     ffi::{c_int, CStr, OsStr},
     ops::Deref,
     os::unix::ffi::OsStrExt,
     path::{Path, PathBuf},
-    sync::Arc,
+    sync::{Arc, RwLock},
 };
 
 struct RawDeviceList {
     ptr: *mut *mut verbs::ibv_device,
     num_devices: usize,
+    backend: Arc<dyn VerbsBackend>,
 }
 
 impl RawDeviceList {
-    fn available() -> Result<Self> {
+    fn available(backend: &Arc<dyn VerbsBackend>) -> Result<Self> {
         let mut num_devices: c_int = 0;
-        let ptr = unsafe { verbs::ibv_get_device_list(&mut num_devices) };
+        let ptr = unsafe { backend.get_device_list(&mut num_devices) };
         if ptr.is_null() {
             return Err(ErrorKind::IBGetDeviceListFail.with_errno());
         }
@@ -26,13 +28,14 @@ impl RawDeviceList {
         Ok(Self {
             ptr,
             num_devices: num_devices as usize,
+            backend: backend.clone(),
         })
     }
 }
 
 impl Drop for RawDeviceList {
     fn drop(&mut self) {
-        unsafe { verbs::ibv_free_device_list(self.ptr) };
+        unsafe { self.backend.free_device_list(self.ptr) };
     }
 }
 
@@ -47,16 +50,16 @@ impl Deref for RawDeviceList {
 unsafe impl Send for RawDeviceList {}
 unsafe impl Sync for RawDeviceList {}
 
-struct RawContext(*mut verbs::ibv_context);
+struct RawContext(*mut verbs::ibv_context, Arc<dyn VerbsBackend>);
 impl Drop for RawContext {
     fn drop(&mut self) {
-        let _ = unsafe { verbs::ibv_close_device(self.0) };
+        let _ = unsafe { self.1.close_device(self.0) };
     }
 }
 impl RawContext {
     fn query_device(&self) -> Result<verbs::ibv_device_attr> {
         let mut device_attr = verbs::ibv_device_attr::default();
-        let ret = unsafe { verbs::ibv_query_device(self.0, &mut device_attr) };
+        let ret = unsafe { self.1.query_device(self.0, &mut device_attr) };
         if ret != 0 {
             Err(ErrorKind::IBQueryDeviceFail.with_errno())
         } else {
@@ -64,9 +67,19 @@ impl RawContext {
         }
     }
 
+    fn query_odp_caps(&self) -> Result<OdpCaps> {
+        let mut device_attr = verbs::ibv_device_attr_ex::default();
+        let ret = unsafe { self.1.query_device_ex(self.0, &mut device_attr) };
+        if ret != 0 {
+            Err(ErrorKind::IBQueryDeviceFail.with_errno())
+        } else {
+            Ok(OdpCaps::from(&device_attr.odp_caps))
+        }
+    }
+
     fn query_port(&self, port_num: u8) -> Result<verbs::ibv_port_attr> {
         let mut port_attr = std::mem::MaybeUninit::<verbs::ibv_port_attr>::uninit();
-        let ret = unsafe { verbs::ibv_query_port(self.0, port_num, port_attr.as_mut_ptr() as _) };
+        let ret = unsafe { self.1.query_port(self.0, port_num, port_attr.as_mut_ptr()) };
         if ret == 0 {
             Ok(unsafe { port_attr.assume_init() })
         } else {
@@ -76,7 +89,7 @@ impl RawContext {
 
     fn query_gid(&self, port_num: u8, gid_index: u16) -> Result<verbs::ibv_gid> {
         let mut gid = verbs::ibv_gid::default();
-        let ret = unsafe { verbs::ibv_query_gid(self.0, port_num as _, gid_index as _, &mut gid) };
+        let ret = unsafe { self.1.query_gid(self.0, port_num, gid_index as _, &mut gid) };
         if ret == 0 && !gid.is_null() {
             Ok(gid)
         } else {
@@ -91,8 +104,7 @@ impl RawContext {
         ibdev_path: &Path,
         port_attr: &verbs::ibv_port_attr,
     ) -> Result<GidType> {
-        let path = ibdev_path.join(format!("ports/{port_num}/gid_attrs/types/{gid_index}"));
-        match std::fs::read_to_string(path) {
+        match self.1.read_gid_type(ibdev_path, port_num, gid_index) {
             Ok(content) => {
                 if content == "IB/RoCE v1\n" {
                     if port_attr.link_layer == verbs::IBV_LINK_LAYER::INFINIBAND as u8 {
@@ -113,26 +125,35 @@ impl RawContext {
 unsafe impl Send for RawContext {}
 unsafe impl Sync for RawContext {}
 
-pub struct RawProtectionDomain(*mut verbs::ibv_pd);
+pub struct RawProtectionDomain(*mut verbs::ibv_pd, Arc<dyn VerbsBackend>);
 impl Drop for RawProtectionDomain {
     fn drop(&mut self) {
-        let _ = unsafe { verbs::ibv_dealloc_pd(self.0) };
+        let _ = unsafe { self.1.dealloc_pd(self.0) };
     }
 }
 unsafe impl Send for RawProtectionDomain {}
 unsafe impl Sync for RawProtectionDomain {}
 
-#[derive(Debug, Default)]
+#[derive(Debug, Clone, Default, Serialize)]
 #[allow(unused)]
 pub struct DeviceInfo {
     pub index: usize,
     pub name: String,
     pub guid: u64,
     pub ibdev_path: PathBuf,
+    #[serde(serialize_with = "DeviceAttr::serialize_raw")]
     pub device_attr: verbs::ibv_device_attr,
+    pub odp_caps: OdpCaps,
     pub ports: Vec<Port>,
 }
 
+impl DeviceInfo {
+    /// Returns the typed view of the device attributes.
+    pub fn attr(&self) -> DeviceAttr {
+        DeviceAttr::from(&self.device_attr)
+    }
+}
+
 /// Represents an RDMA device.
 #[allow(unused)]
 pub struct Device {
@@ -140,21 +161,57 @@ pub struct Device {
     context: RawContext,
     device: *mut verbs::ibv_device,
     list: Arc<RawDeviceList>,
-    info: DeviceInfo,
+    index: usize,
+    info: RwLock<Arc<DeviceInfo>>,
 }
 
 unsafe impl Send for Device {}
 unsafe impl Sync for Device {}
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Serialize)]
 pub struct Port {
     pub port_num: u8,
     /// The attributes of the port.
+    #[serde(serialize_with = "PortAttr::serialize_raw")]
     pub port_attr: verbs::ibv_port_attr,
     /// The GID (Global Identifier) list of the port.
+    #[serde(serialize_with = "serialize_gids")]
     pub gids: Vec<(u16, verbs::ibv_gid, GidType)>,
 }
 
+impl Port {
+    /// Returns the typed view of the port attributes.
+    pub fn attr(&self) -> PortAttr {
+        PortAttr::from(&self.port_attr)
+    }
+
+    pub fn state(&self) -> PortState {
+        self.port_attr.state.into()
+    }
+}
+
+fn serialize_gids<S: Serializer>(
+    gids: &[(u16, verbs::ibv_gid, GidType)],
+    serializer: S,
+) -> std::result::Result<S::Ok, S::Error> {
+    #[derive(Serialize)]
+    struct GidEntry<'a> {
+        index: u16,
+        gid: String,
+        gid_type: &'a GidType,
+    }
+
+    let mut seq = serializer.serialize_seq(Some(gids.len()))?;
+    for (index, gid, gid_type) in gids {
+        seq.serialize_element(&GidEntry {
+            index: *index,
+            gid: format!("{gid:?}"),
+            gid_type,
+        })?;
+    }
+    seq.end()
+}
+
 #[allow(unused)]
 impl Device {
     fn open(
@@ -166,47 +223,58 @@ impl Device {
         let name = unsafe { CStr::from_ptr((*device).name.as_ptr()) }
             .to_string_lossy()
             .to_string();
-        let guid = u64::from_be(unsafe { verbs::ibv_get_device_guid(device) });
+        let backend = list.backend.clone();
+        let guid = u64::from_be(unsafe { backend.get_device_guid(device) });
         let str = unsafe { CStr::from_ptr((*device).ibdev_path.as_ptr()) };
         let ibdev_path = PathBuf::from(OsStr::from_bytes(str.to_bytes()));
 
-        let context = RawContext(unsafe {
-            let context = verbs::ibv_open_device(device);
-            if context.is_null() {
-                return Err(ErrorKind::IBOpenDeviceFail.with_errno());
-            }
-            context
-        });
-
-        let protection_domain = RawProtectionDomain(unsafe {
-            let protection_domain = verbs::ibv_alloc_pd(context.0);
-            if protection_domain.is_null() {
-                return Err(ErrorKind::IBAllocPDFail.with_errno());
-            }
-            protection_domain
-        });
+        let context = RawContext(
+            unsafe {
+                let context = backend.open_device(device);
+                if context.is_null() {
+                    return Err(ErrorKind::IBOpenDeviceFail.with_errno());
+                }
+                context
+            },
+            backend.clone(),
+        );
+
+        let protection_domain = RawProtectionDomain(
+            unsafe {
+                let protection_domain = backend.alloc_pd(context.0);
+                if protection_domain.is_null() {
+                    return Err(ErrorKind::IBAllocPDFail.with_errno());
+                }
+                protection_domain
+            },
+            backend,
+        );
 
-        let mut device = Self {
+        let device = Self {
             protection_domain,
             context,
             device,
             list,
-            info: DeviceInfo {
+            index,
+            info: RwLock::new(Arc::new(DeviceInfo {
                 index,
                 name,
                 guid,
                 ibdev_path,
                 ..Default::default()
-            },
+            })),
         };
         device.update_attr(config)?;
 
         Ok(device)
     }
 
-    fn update_attr(&mut self, config: &DeviceConfig) -> Result<()> {
+    /// Re-queries the device attributes and replaces the cached info, returning the previous one.
+    fn update_attr(&self, config: &DeviceConfig) -> Result<Arc<DeviceInfo>> {
+        let old_info = self.info();
         // 1. query device attr.
         let device_attr = self.context.query_device()?;
+        let odp_caps = self.context.query_odp_caps()?;
 
         let mut ports = vec![];
         for port_num in 1..=device_attr.phys_port_cnt {
@@ -223,7 +291,7 @@ impl Device {
                     let gid_type = self.context.query_gid_type(
                         port_num,
                         gid_index,
-                        &self.info.ibdev_path,
+                        &old_info.ibdev_path,
                         &port_attr,
                     )?;
                     if !config.gid_type_filter.is_empty()
@@ -250,10 +318,25 @@ impl Device {
             });
         }
 
-        self.info.device_attr = device_attr;
-        self.info.ports = ports;
+        let info = DeviceInfo {
+            device_attr,
+            odp_caps,
+            ports,
+            ..(*old_info).clone()
+        };
+        *self.info.write().unwrap() = Arc::new(info);
 
-        Ok(())
+        Ok(old_info)
+    }
+
+    /// Re-queries port states and GID tables, returning what has changed since the last query.
+    pub fn refresh(&self, config: &DeviceConfig) -> Result<DeviceDiff> {
+        let old_info = self.update_attr(config)?;
+        Ok(DeviceDiff::compare(&old_info, &self.info()))
+    }
+
+    pub(crate) fn backend(&self) -> &Arc<dyn VerbsBackend> {
+        &self.list.backend
     }
 
     pub(crate) fn device_ptr(&self) -> *mut verbs::ibv_device {
@@ -269,17 +352,134 @@ impl Device {
     }
 
     pub fn index(&self) -> usize {
-        self.info.index
+        self.index
     }
 
-    pub fn info(&self) -> &DeviceInfo {
-        &self.info
+    /// Returns a snapshot of the device info, which is replaced by `refresh`.
+    pub fn info(&self) -> Arc<DeviceInfo> {
+        self.info.read().unwrap().clone()
     }
 }
 
 impl std::fmt::Debug for Device {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        std::fmt::Debug::fmt(&self.info, f)
+        std::fmt::Debug::fmt(&self.info(), f)
+    }
+}
+
+/// A GID table entry which has been added or removed.
+#[derive(Debug, Clone)]
+pub struct GidChange {
+    pub port_num: u8,
+    pub gid_index: u16,
+    pub gid: verbs::ibv_gid,
+    pub gid_type: GidType,
+}
+
+/// A port whose state has changed. `None` means the port is absent, e.g. skipped as inactive.
+#[derive(Debug, Clone)]
+pub struct PortStateChange {
+    pub port_num: u8,
+    pub old_state: Option<PortState>,
+    pub new_state: Option<PortState>,
+}
+
+/// The changes of a device found by `Device::refresh`.
+#[derive(Debug, Clone, Default)]
+pub struct DeviceDiff {
+    pub index: usize,
+    pub name: String,
+    pub port_states: Vec<PortStateChange>,
+    pub gids_added: Vec<GidChange>,
+    pub gids_removed: Vec<GidChange>,
+}
+
+impl DeviceDiff {
+    fn compare(old: &DeviceInfo, new: &DeviceInfo) -> Self {
+        let mut diff = DeviceDiff {
+            index: new.index,
+            name: new.name.clone(),
+            ..Default::default()
+        };
+
+        let find_port = |info: &DeviceInfo, port_num: u8| -> Option<Port> {
+            info.ports.iter().find(|p| p.port_num == port_num).cloned()
+        };
+        let mut port_nums = old
+            .ports
+            .iter()
+            .chain(&new.ports)
+            .map(|p| p.port_num)
+            .collect::<Vec<_>>();
+        port_nums.sort();
+        port_nums.dedup();
+
+        for port_num in port_nums {
+            let old_port = find_port(old, port_num);
+            let new_port = find_port(new, port_num);
+            let old_state = old_port.as_ref().map(Port::state);
+            let new_state = new_port.as_ref().map(Port::state);
+            if old_state != new_state {
+                diff.port_states.push(PortStateChange {
+                    port_num,
+                    old_state,
+                    new_state,
+                });
+            }
+
+            let old_gids = old_port.map(|p| p.gids).unwrap_or_default();
+            let new_gids = new_port.map(|p| p.gids).unwrap_or_default();
+            let contains = |gids: &[(u16, verbs::ibv_gid, GidType)],
+                            entry: &(u16, verbs::ibv_gid, GidType)| {
+                gids.iter()
+                    .any(|g| g.0 == entry.0 && g.1.as_raw() == entry.1.as_raw() && g.2 == entry.2)
+            };
+            let to_change =
+                |(gid_index, gid, gid_type): &(u16, verbs::ibv_gid, GidType)| GidChange {
+                    port_num,
+                    gid_index: *gid_index,
+                    gid: *gid,
+                    gid_type: gid_type.clone(),
+                };
+            diff.gids_removed.extend(
+                old_gids
+                    .iter()
+                    .filter(|g| !contains(&new_gids, g))
+                    .map(to_change),
+            );
+            diff.gids_added.extend(
+                new_gids
+                    .iter()
+                    .filter(|g| !contains(&old_gids, g))
+                    .map(to_change),
+            );
+        }
+
+        diff
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.port_states.is_empty() && self.gids_added.is_empty() && self.gids_removed.is_empty()
+    }
+}
+
+/// The changes of a device set found by `Devices::refresh`.
+/// Devices which appear or disappear are reported by name, a new `Devices` must be opened to use them.
+#[derive(Debug, Clone, Default)]
+pub struct DevicesDiff {
+    pub changed: Vec<DeviceDiff>,
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+    /// Devices still in the device list which failed to be queried, e.g. transiently during a reset.
+    pub failed: Vec<(String, Error)>,
+}
+
+impl DevicesDiff {
+    pub fn is_empty(&self) -> bool {
+        self.changed.is_empty()
+            && self.added.is_empty()
+            && self.removed.is_empty()
+            && self.failed.is_empty()
     }
 }
 
@@ -295,18 +495,22 @@ impl Devices {
 
     /// Opens RDMA devices based on the provided configuration.
     pub fn open(config: &DeviceConfig) -> Result<Devices> {
-        let list = Arc::new(RawDeviceList::available()?);
+        Self::open_with_backend(config, crate::default_backend())
+    }
+
+    /// Opens RDMA devices through the given verbs backend, e.g. `MockVerbs` in tests.
+    pub fn open_with_backend(
+        config: &DeviceConfig,
+        backend: Arc<dyn VerbsBackend>,
+    ) -> Result<Devices> {
+        let list = Arc::new(RawDeviceList::available(&backend)?);
         let mut devices = Vec::with_capacity(list.len());
         for &device in list.iter() {
             let index = devices.len();
             let device = Device::open(list.clone(), device, index, config)?;
-            if !config.device_filter.is_empty() && !config.device_filter.contains(&device.info.name)
-            {
-                tracing::debug!(
-                    "skip device {} by filter: {:?}",
-                    device.info.name,
-                    config.device_filter
-                );
+            let name = device.info().name.clone();
+            if !config.device_filter.is_empty() && !config.device_filter.contains(&name) {
+                tracing::debug!("skip device {} by filter: {:?}", name, config.device_filter);
                 continue;
             }
 
@@ -320,6 +524,57 @@ impl Devices {
     }
 }
 
+impl Devices {
+    /// Re-queries port states and GID tables of the opened devices and rescans the device list.
+    /// Devices missing from the device list are reported as removed, and devices which are listed but
+    /// can not be queried are reported as failed.
+    pub fn refresh(&self, config: &DeviceConfig) -> Result<DevicesDiff> {
+        let mut diff = DevicesDiff::default();
+        for device in self.iter() {
+            match device.refresh(config) {
+                Ok(device_diff) => {
+                    if !device_diff.is_empty() {
+                        diff.changed.push(device_diff);
+                    }
+                }
+                Err(err) => {
+                    tracing::warn!("refresh device {} failed: {}", device.info().name, err);
+                    diff.failed.push((device.info().name.clone(), err));
+                }
+            }
+        }
+
+        let names: Vec<String> = match RawDeviceList::available(self[0].backend()) {
+            Ok(list) => list
+                .iter()
+                .map(|&device| {
+                    unsafe { CStr::from_ptr((*device).name.as_ptr()) }
+                        .to_string_lossy()
+                        .to_string()
+                })
+                .collect(),
+            Err(err) if err.kind == ErrorKind::IBDeviceNotFound => vec![],
+            Err(err) => return Err(err),
+        };
+        for device in self.iter() {
+            let name = device.info().name.clone();
+            if !names.contains(&name) {
+                diff.failed.retain(|(failed, _)| *failed != name);
+                diff.removed.push(name);
+            }
+        }
+        for name in names {
+            let filtered =
+                !config.device_filter.is_empty() && !config.device_filter.contains(&name);
+            if !filtered && !self.iter().any(|device| device.info().name == name) {
+                diff.added.push(name);
+            }
+        }
+
+        Ok(diff)
+    }
+}
+
 impl Deref for Devices {
     type Target = [Device];
 
@@ -349,4 +604,27 @@ mod tests {
             println!("{:#?}", device);
         }
     }
+
+    #[test]
+    fn serialize_devices() {
+        let devices = Devices::availables().unwrap();
+        for device in &devices {
+            let info = device.info();
+            let json = serde_json::to_value(&*info).unwrap();
+            assert_eq!(json["name"], info.name);
+            assert_eq!(json["ports"].as_array().unwrap().len(), info.ports.len());
+            assert!(json["device_attr"]["max_qp"].as_i64().unwrap() > 0);
+            println!("{}", serde_json::to_string_pretty(&json).unwrap());
+        }
+    }
+
+    #[test]
+    fn refresh_devices() {
+        let config = DeviceConfig::default();
+        let devices = Devices::open(&config).unwrap();
+        let info = devices[0].info();
+        let diff = devices.refresh(&config).unwrap();
+        assert!(diff.is_empty(), "{:#?}", diff);
+        assert_eq!(devices[0].info().ports.len(), info.ports.len());
+    }
 }
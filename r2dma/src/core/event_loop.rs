@@ -1,13 +1,53 @@
-use super::{CompQueues, Devices};
-use crate::{verbs, Result};
+use super::{CompQueues, Devices, EventLoopConfig, PollStrategy};
+use crate::{verbs, Error, ErrorKind, Result};
+use serde::Serialize;
 use std::sync::{
-    atomic::{AtomicBool, Ordering},
+    atomic::{AtomicBool, AtomicU64, Ordering},
     Arc,
 };
 
+/// Counters of an event loop, for tuning its polling strategy.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct EventLoopStats {
+    pub polls: u64,
+    pub empty_polls: u64,
+    pub completions: u64,
+    /// Times the loop was woken up by a completion channel notification.
+    pub wakeups: u64,
+}
+
+impl EventLoopStats {
+    /// Returns the average number of completions of polls which returned any.
+    pub fn completions_per_poll(&self) -> f64 {
+        match self.polls - self.empty_polls {
+            0 => 0.0,
+            polls => self.completions as f64 / polls as f64,
+        }
+    }
+
+    /// Returns the fraction of polls which returned no completions.
+    pub fn empty_poll_ratio(&self) -> f64 {
+        match self.polls {
+            0 => 0.0,
+            polls => self.empty_polls as f64 / polls as f64,
+        }
+    }
+}
+
+#[derive(Default)]
+struct EventLoopCounters {
+    polls: AtomicU64,
+    empty_polls: AtomicU64,
+    completions: AtomicU64,
+    wakeups: AtomicU64,
+}
+
 pub struct EventLoopState {
     stopping: AtomicBool,
     comp_queues: Arc<CompQueues>,
+    cpu: Option<usize>,
+    poll_strategy: PollStrategy,
+    counters: EventLoopCounters,
 }
 
 pub struct EventLoop {
@@ -19,10 +59,42 @@ pub struct EventLoop {
 /// It runs in a separate thread and processes completion events until stopped.
 impl EventLoop {
     pub fn create(devices: &Devices, max_cqe: u32) -> Result<Self> {
-        let comp_queues = CompQueues::create(devices, max_cqe)?;
+        Self::create_impl(devices, max_cqe, None, PollStrategy::default())
+    }
+
+    /// Creates an event loop whose polling thread is pinned to the CPU.
+    pub fn create_pinned(devices: &Devices, max_cqe: u32, cpu: usize) -> Result<Self> {
+        Self::create_impl(devices, max_cqe, Some(cpu), PollStrategy::default())
+    }
+
+    /// Creates an event loop with the queue size and polling strategy of the config.
+    pub fn create_with_config(
+        devices: &Devices,
+        config: &EventLoopConfig,
+        cpu: Option<usize>,
+    ) -> Result<Self> {
+        Self::create_impl(devices, config.max_cqe, cpu, config.poll_strategy)
+    }
+
+    fn create_impl(
+        devices: &Devices,
+        max_cqe: u32,
+        cpu: Option<usize>,
+        poll_strategy: PollStrategy,
+    ) -> Result<Self> {
+        if let Some(cpu) = cpu {
+            check_cpu(cpu)?;
+        }
+        let comp_queues = match poll_strategy {
+            PollStrategy::Blocking { .. } => CompQueues::create_with_channel(devices, max_cqe)?,
+            _ => CompQueues::create(devices, max_cqe)?,
+        };
         let state = Arc::new(EventLoopState {
             stopping: AtomicBool::new(false),
             comp_queues,
+            cpu,
+            poll_strategy,
+            counters: Default::default(),
         });
 
         let handle = std::thread::spawn({
@@ -36,42 +108,126 @@ impl EventLoop {
         })
     }
 
+    /// Returns the completion queues polled by this loop. Queue pairs created on them are served by this loop.
+    pub fn comp_queues(&self) -> &Arc<CompQueues> {
+        &self.state.comp_queues
+    }
+
+    /// Returns the CPU the polling thread is pinned to.
+    pub fn cpu(&self) -> Option<usize> {
+        self.state.cpu
+    }
+
+    pub fn poll_strategy(&self) -> PollStrategy {
+        self.state.poll_strategy
+    }
+
+    pub fn stats(&self) -> EventLoopStats {
+        let counters = &self.state.counters;
+        EventLoopStats {
+            polls: counters.polls.load(Ordering::Relaxed),
+            empty_polls: counters.empty_polls.load(Ordering::Relaxed),
+            completions: counters.completions.load(Ordering::Relaxed),
+            wakeups: counters.wakeups.load(Ordering::Relaxed),
+        }
+    }
+
     pub fn stop_and_join(&mut self) {
         self.state.stopping.store(true, Ordering::Release);
         if let Some(handle) = self.handle.take() {
+            handle.thread().unpark();
             handle.join().unwrap();
         }
     }
 
     pub fn run(state: Arc<EventLoopState>) {
+        if let Some(cpu) = state.cpu {
+            if let Err(err) = pin_current_thread(cpu) {
+                tracing::warn!("pin event loop to cpu {} failed: {}", cpu, err);
+            }
+        }
+
         let comp_queues = state.comp_queues.clone();
+        let counters = &state.counters;
         let num_entiries = comp_queues.num_entries();
         let mut wcs = vec![verbs::ibv_wc::default(); num_entiries];
+        let mut idle_polls = 0u32;
+        let mut armed = false;
 
         while !state.stopping.load(Ordering::Acquire) {
             // poll for events.
             let wcs = comp_queues.poll_cq(&mut wcs).unwrap();
+            counters.polls.fetch_add(1, Ordering::Relaxed);
             if wcs.is_empty() {
-                std::thread::sleep(std::time::Duration::from_millis(1));
+                counters.empty_polls.fetch_add(1, Ordering::Relaxed);
+                idle_polls = idle_polls.saturating_add(1);
+                Self::idle(&state, idle_polls, &mut armed);
                 continue;
             }
+            idle_polls = 0;
+            counters
+                .completions
+                .fetch_add(wcs.len() as u64, Ordering::Relaxed);
 
             // handle events.
-            for wc in wcs {
-                if wc.is_recv() {
-                    tracing::info!(
-                        "wc is recv id {}, result {}, status {:?}",
+            for (device_index, wc) in wcs.iter_with_device() {
+                // release the buffer of the work request.
+                let _ = comp_queues.take_buffer(device_index, wc);
+                let direction = if wc.is_recv() { "recv" } else { "send" };
+                match wc.result() {
+                    Ok(byte_len) => {
+                        tracing::info!("wc is {} id {}, result {}", direction, wc.wr_id, byte_len)
+                    }
+                    Err(err) => tracing::error!(
+                        "wc is {} id {}, qp_num {}, error {}",
+                        direction,
                         wc.wr_id,
-                        wc.byte_len,
-                        wc.status
-                    );
+                        wc.qp_num,
+                        err
+                    ),
+                }
+            }
+        }
+    }
+
+    fn idle(state: &EventLoopState, idle_polls: u32, armed: &mut bool) {
+        match state.poll_strategy {
+            PollStrategy::BusyPoll => std::hint::spin_loop(),
+            PollStrategy::Backoff {
+                spin_polls,
+                yield_polls,
+                park,
+            } => {
+                if idle_polls <= spin_polls {
+                    std::hint::spin_loop();
+                } else if idle_polls - spin_polls <= yield_polls {
+                    std::thread::yield_now();
                 } else {
-                    tracing::info!(
-                        "wc is send id {}, result {}, status {:?}",
-                        wc.wr_id,
-                        wc.byte_len,
-                        wc.status
-                    );
+                    std::thread::park_timeout(park);
+                }
+            }
+            PollStrategy::Blocking { timeout } => {
+                // arm first and poll once more, so completions which arrived before arming are not missed.
+                if !*armed {
+                    match state.comp_queues.req_notify() {
+                        Ok(()) => *armed = true,
+                        Err(err) => {
+                            tracing::error!("request cq notification failed: {}", err);
+                            std::thread::park_timeout(timeout);
+                        }
+                    }
+                    return;
+                }
+                match state.comp_queues.wait(timeout) {
+                    Ok(0) => {}
+                    Ok(_) => {
+                        *armed = false;
+                        state.counters.wakeups.fetch_add(1, Ordering::Relaxed);
+                    }
+                    Err(err) => {
+                        tracing::error!("wait cq notification failed: {}", err);
+                        std::thread::park_timeout(timeout);
+                    }
                 }
             }
         }
@@ -84,9 +240,44 @@ impl Drop for EventLoop {
     }
 }
 
+/// Checks that the CPU fits in a `cpu_set_t`, which `CPU_SET` asserts.
+pub(crate) fn check_cpu(cpu: usize) -> Result<()> {
+    if cpu < libc::CPU_SETSIZE as usize {
+        Ok(())
+    } else {
+        Err(Error::new(
+            ErrorKind::InvalidArgument,
+            format!("cpu {cpu} exceeds CPU_SETSIZE {}", libc::CPU_SETSIZE),
+        ))
+    }
+}
+
+pub(crate) fn pin_current_thread(cpu: usize) -> std::io::Result<()> {
+    if cpu >= libc::CPU_SETSIZE as usize {
+        return Err(std::io::ErrorKind::InvalidInput.into());
+    }
+    unsafe {
+        let mut set = std::mem::zeroed::<libc::cpu_set_t>();
+        libc::CPU_SET(cpu, &mut set);
+        let ret = libc::sched_setaffinity(0, std::mem::size_of::<libc::cpu_set_t>(), &set);
+        if ret == 0 {
+            Ok(())
+        } else {
+            Err(std::io::Error::last_os_error())
+        }
+    }
+}
+
+/// Returns the CPU the calling thread is running on.
+pub(crate) fn current_cpu() -> Option<usize> {
+    let cpu = unsafe { libc::sched_getcpu() };
+    (cpu >= 0).then_some(cpu as usize)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::testing::{connect, socket_pair};
 
     #[test]
     fn test_event_loop() {
@@ -95,4 +286,74 @@ mod tests {
         std::thread::sleep(std::time::Duration::from_millis(200));
         drop(event_loop);
     }
+
+    #[test]
+    fn test_event_loop_mock() {
+        let devices = crate::MockVerbs::devices(2).unwrap();
+        let mut event_loop = EventLoop::create(&devices, 32).unwrap();
+        event_loop.stop_and_join();
+        assert!(event_loop.handle.is_none());
+    }
+
+    fn wait_for_completions(event_loop: &EventLoop, completions: u64) -> EventLoopStats {
+        let deadline = std::time::Instant::now() + std::time::Duration::from_secs(5);
+        loop {
+            let stats = event_loop.stats();
+            if stats.completions >= completions || std::time::Instant::now() > deadline {
+                return stats;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(1));
+        }
+    }
+
+    #[test]
+    fn test_poll_strategies() {
+        use crate::*;
+
+        let devices = MockVerbs::devices(1).unwrap();
+        let buffer_pool = BufferPool::create(4096, 4, &devices).unwrap();
+
+        for poll_strategy in [
+            PollStrategy::BusyPoll,
+            PollStrategy::Backoff {
+                spin_polls: 16,
+                yield_polls: 16,
+                park: std::time::Duration::from_millis(1),
+            },
+            PollStrategy::Blocking {
+                timeout: std::time::Duration::from_millis(10),
+            },
+        ] {
+            let config = EventLoopConfig {
+                max_cqe: 64,
+                poll_strategy,
+                ..Default::default()
+            };
+            let mut event_loop = EventLoop::create_with_config(&devices, &config, None).unwrap();
+            assert_eq!(event_loop.poll_strategy(), poll_strategy);
+            let comp_queues = event_loop.comp_queues();
+            let (socket_a, socket_b) =
+                socket_pair(&devices, comp_queues, comp_queues, Socket::create);
+            connect(&socket_a, &socket_b);
+
+            // let the loop go idle before the completions arrive.
+            std::thread::sleep(std::time::Duration::from_millis(20));
+            socket_b
+                .post_recv(1, buffer_pool.allocate().unwrap())
+                .unwrap();
+            socket_a
+                .post_send(2, buffer_pool.allocate().unwrap())
+                .unwrap();
+
+            let stats = wait_for_completions(&event_loop, 2);
+            assert_eq!(stats.completions, 2);
+            assert!(stats.empty_polls > 0);
+            assert!(stats.completions_per_poll() >= 1.0);
+            if let PollStrategy::Blocking { .. } = poll_strategy {
+                assert!(stats.wakeups >= 1);
+            }
+            event_loop.stop_and_join();
+            println!("{:?}: {:?}", poll_strategy, event_loop.stats());
+        }
+    }
 }
@@ -0,0 +1,186 @@
+use super::{
+    event_loop::{check_cpu, current_cpu},
+    Devices, EventLoop, EventLoopConfig, EventLoopStats, LoopAssignment, QueuePair,
+};
+use crate::{verbs, Result};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// A group of event loops, each polling its own completion queue per device in a separate thread.
+/// New queue pairs are spread across the loops according to `LoopAssignment`.
+pub struct EventLoopGroup {
+    loops: Vec<EventLoop>,
+    assignment: LoopAssignment,
+    next: AtomicUsize,
+    devices: Devices,
+}
+
+impl EventLoopGroup {
+    /// Creates the loops of the config. Fails if a CPU to pin a loop to is out of range.
+    pub fn create(devices: &Devices, config: &EventLoopConfig) -> Result<Self> {
+        let num_loops = config.num_loops.max(1);
+        if config.assignment == LoopAssignment::CpuAffinity {
+            for &cpu in &config.cpus {
+                check_cpu(cpu)?;
+            }
+        }
+        let mut loops = Vec::with_capacity(num_loops);
+        for index in 0..num_loops {
+            let cpu = match config.assignment {
+                LoopAssignment::RoundRobin => None,
+                LoopAssignment::CpuAffinity => {
+                    Some(config.cpus.get(index).copied().unwrap_or(index))
+                }
+            };
+            loops.push(EventLoop::create_with_config(devices, config, cpu)?);
+        }
+
+        Ok(Self {
+            loops,
+            assignment: config.assignment,
+            next: AtomicUsize::new(0),
+            devices: devices.clone(),
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.loops.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.loops.is_empty()
+    }
+
+    pub fn event_loop(&self, index: usize) -> &EventLoop {
+        &self.loops[index]
+    }
+
+    /// Returns the statistics of each loop.
+    pub fn stats(&self) -> Vec<EventLoopStats> {
+        self.loops.iter().map(EventLoop::stats).collect()
+    }
+
+    /// Picks the loop for a new queue pair and returns its index.
+    pub fn assign(&self) -> usize {
+        if self.assignment == LoopAssignment::CpuAffinity {
+            let pinned = current_cpu().and_then(|cpu| {
+                self.loops
+                    .iter()
+                    .position(|event_loop| event_loop.cpu() == Some(cpu))
+            });
+            if let Some(index) = pinned {
+                return index;
+            }
+        }
+        self.next.fetch_add(1, Ordering::Relaxed) % self.loops.len()
+    }
+
+    /// Creates a queue pair on the completion queues of an assigned loop.
+    /// Returns the loop index along with the queue pair.
+    pub fn create_queue_pair(
+        &self,
+        device_index: usize,
+        cap: verbs::ibv_qp_cap,
+    ) -> Result<(usize, QueuePair)> {
+        let index = self.assign();
+        let comp_queues = self.loops[index].comp_queues();
+        let queue_pair = QueuePair::create(&self.devices, device_index, comp_queues, cap)?;
+        Ok((index, queue_pair))
+    }
+
+    pub fn stop_and_join(&mut self) {
+        for event_loop in &mut self.loops {
+            event_loop.stop_and_join();
+        }
+    }
+}
+
+impl std::fmt::Debug for EventLoopGroup {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("EventLoopGroup")
+            .field("num_loops", &self.loops.len())
+            .field("assignment", &self.assignment)
+            .field(
+                "cpus",
+                &self.loops.iter().map(EventLoop::cpu).collect::<Vec<_>>(),
+            )
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{core::event_loop::pin_current_thread, testing::cap, ErrorKind, MockVerbs};
+
+    #[test]
+    fn test_round_robin() {
+        let devices = MockVerbs::devices(2).unwrap();
+        let config = EventLoopConfig {
+            num_loops: 3,
+            max_cqe: 64,
+            ..Default::default()
+        };
+        let mut group = EventLoopGroup::create(&devices, &config).unwrap();
+        assert_eq!(group.len(), 3);
+        println!("{:#?}", group);
+
+        let indices = (0..6)
+            .map(|i| group.create_queue_pair(i % 2, cap()).unwrap().0)
+            .collect::<Vec<_>>();
+        assert_eq!(indices, [0, 1, 2, 0, 1, 2]);
+        group.stop_and_join();
+    }
+
+    /// Returns the CPUs the calling thread is allowed to run on.
+    fn allowed_cpus() -> Vec<usize> {
+        let mut set = unsafe { std::mem::zeroed::<libc::cpu_set_t>() };
+        let ret =
+            unsafe { libc::sched_getaffinity(0, std::mem::size_of::<libc::cpu_set_t>(), &mut set) };
+        assert_eq!(ret, 0);
+        (0..libc::CPU_SETSIZE as usize)
+            .filter(|&cpu| unsafe { libc::CPU_ISSET(cpu, &set) })
+            .collect()
+    }
+
+    #[test]
+    fn test_cpu_affinity() {
+        let cpus = allowed_cpus();
+        let devices = MockVerbs::devices(1).unwrap();
+        let config = EventLoopConfig {
+            num_loops: 2,
+            max_cqe: 64,
+            assignment: LoopAssignment::CpuAffinity,
+            cpus: vec![cpus[0], cpus[0]],
+            ..Default::default()
+        };
+        let group = EventLoopGroup::create(&devices, &config).unwrap();
+        assert_eq!(group.event_loop(0).cpu(), Some(cpus[0]));
+        assert_eq!(group.event_loop(1).cpu(), Some(cpus[0]));
+
+        // pinned in threads of their own, leaving the affinity of the test thread alone.
+        let group = &group;
+        std::thread::scope(|s| {
+            s.spawn(|| {
+                pin_current_thread(cpus[0]).unwrap();
+                assert_eq!(group.assign(), 0);
+                let (index, _queue_pair) = group.create_queue_pair(0, cap()).unwrap();
+                assert_eq!(index, 0);
+            });
+            // threads on a CPU without a loop are assigned round-robin.
+            if let Some(&cpu) = cpus.get(1) {
+                s.spawn(move || {
+                    pin_current_thread(cpu).unwrap();
+                    let indices = (0..3).map(|_| group.assign()).collect::<Vec<_>>();
+                    assert_eq!(indices, [0, 1, 0]);
+                });
+            }
+        });
+
+        let config = EventLoopConfig {
+            cpus: vec![0, libc::CPU_SETSIZE as usize],
+            ..config
+        };
+        let err = EventLoopGroup::create(&devices, &config).unwrap_err();
+        assert_eq!(err.kind, ErrorKind::InvalidArgument);
+    }
+}
@@ -0,0 +1,115 @@
+use super::{AtomicCap, DeviceInfo};
+use crate::{verbs, ErrorKind, Result};
+
+fn check_limit(field: &str, requested: u64, limit: u64) -> Result<()> {
+    if requested <= limit {
+        Ok(())
+    } else {
+        Err(ErrorKind::IBExceedDeviceLimit {
+            field: field.to_string(),
+            requested,
+            limit,
+        }
+        .into())
+    }
+}
+
+/// Validates resource requests against the queried device limits,
+/// so that violations are reported before the values are handed to verbs.
+impl DeviceInfo {
+    pub fn check_qp_cap(&self, cap: &verbs::ibv_qp_cap) -> Result<()> {
+        let max_qp_wr = self.device_attr.max_qp_wr.max(0) as u64;
+        let max_sge = self.device_attr.max_sge.max(0) as u64;
+        check_limit("max_send_wr", cap.max_send_wr as u64, max_qp_wr)?;
+        check_limit("max_recv_wr", cap.max_recv_wr as u64, max_qp_wr)?;
+        check_limit("max_send_sge", cap.max_send_sge as u64, max_sge)?;
+        check_limit("max_recv_sge", cap.max_recv_sge as u64, max_sge)
+    }
+
+    pub fn check_cqe(&self, cqe: u32) -> Result<()> {
+        check_limit(
+            "max_cqe",
+            cqe as u64,
+            self.device_attr.max_cqe.max(0) as u64,
+        )
+    }
+
+    pub fn check_mr_size(&self, size: usize) -> Result<()> {
+        check_limit("max_mr_size", size as u64, self.device_attr.max_mr_size)
+    }
+
+    pub fn check_srq(&self, max_wr: u32, max_sge: u32) -> Result<()> {
+        let attr = &self.device_attr;
+        check_limit("max_srq_wr", max_wr as u64, attr.max_srq_wr.max(0) as u64)?;
+        check_limit(
+            "max_srq_sge",
+            max_sge as u64,
+            attr.max_srq_sge.max(0) as u64,
+        )
+    }
+
+    pub fn check_atomic(&self) -> Result<()> {
+        if AtomicCap::from(self.device_attr.atomic_cap) == AtomicCap::None {
+            Err(ErrorKind::IBAtomicNotSupported.into())
+        } else {
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Error;
+
+    fn device_info() -> DeviceInfo {
+        DeviceInfo {
+            device_attr: verbs::ibv_device_attr {
+                max_qp_wr: 1024,
+                max_sge: 4,
+                max_cqe: 4096,
+                max_mr_size: 1 << 30,
+                max_srq_wr: 512,
+                max_srq_sge: 2,
+                atomic_cap: verbs::ibv_atomic_cap::IBV_ATOMIC_NONE,
+                ..Default::default()
+            },
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_check_limits() {
+        let info = device_info();
+        let mut cap = verbs::ibv_qp_cap {
+            max_send_wr: 1024,
+            max_recv_wr: 64,
+            max_send_sge: 1,
+            max_recv_sge: 1,
+            max_inline_data: 0,
+        };
+        info.check_qp_cap(&cap).unwrap();
+
+        cap.max_recv_sge = 8;
+        let err = info.check_qp_cap(&cap).unwrap_err();
+        assert_eq!(
+            err,
+            Error::from(ErrorKind::IBExceedDeviceLimit {
+                field: "max_recv_sge".to_string(),
+                requested: 8,
+                limit: 4,
+            })
+        );
+
+        info.check_cqe(4096).unwrap();
+        assert!(info.check_cqe(4097).is_err());
+        info.check_mr_size(1 << 30).unwrap();
+        assert!(info.check_mr_size((1 << 30) + 1).is_err());
+        info.check_srq(512, 2).unwrap();
+        assert!(info.check_srq(512, 3).is_err());
+        assert_eq!(
+            info.check_atomic().unwrap_err().kind,
+            ErrorKind::IBAtomicNotSupported
+        );
+    }
+}
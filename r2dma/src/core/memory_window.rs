@@ -0,0 +1,170 @@
+use super::Devices;
+use crate::{verbs, Buffer, ErrorKind, Result, VerbsBackend};
+use std::sync::{Arc, Mutex};
+
+struct RawMemoryWindow(*mut verbs::ibv_mw, Arc<dyn VerbsBackend>);
+impl Drop for RawMemoryWindow {
+    fn drop(&mut self) {
+        let _ = unsafe { self.1.dealloc_mw(self.0) };
+    }
+}
+unsafe impl Send for RawMemoryWindow {}
+unsafe impl Sync for RawMemoryWindow {}
+
+pub(crate) struct WindowState {
+    pub rkey: u32,
+    /// The buffer exposed by the window, held until the window is invalidated.
+    pub bound: Option<Buffer>,
+}
+
+/// A type 2 memory window, which grants the peer of a single queue pair access to a range of a
+/// buffer instead of the whole registered pool, until it is invalidated.
+/// It is bound and invalidated through `Socket::bind_window` and `Socket::invalidate_window`.
+pub struct MemoryWindow {
+    // dropped first, so the window is deallocated before the buffer it exposes is released.
+    mw: RawMemoryWindow,
+    state: Mutex<WindowState>,
+    device_index: usize,
+    _devices: Devices,
+}
+
+impl MemoryWindow {
+    pub fn alloc(devices: &Devices, device_index: usize) -> Result<Self> {
+        let device = &devices[device_index];
+        let mw = unsafe {
+            device
+                .backend()
+                .alloc_mw(device.pd_ptr(), verbs::ibv_mw_type::IBV_MW_TYPE_2)
+        };
+        if mw.is_null() {
+            return Err(ErrorKind::IBAllocMemoryWindowFail.with_errno());
+        }
+        let rkey = unsafe { (*mw).rkey };
+        Ok(Self {
+            mw: RawMemoryWindow(mw, device.backend().clone()),
+            state: Mutex::new(WindowState { rkey, bound: None }),
+            device_index,
+            _devices: devices.clone(),
+        })
+    }
+
+    pub fn device_index(&self) -> usize {
+        self.device_index
+    }
+
+    /// Returns the rkey of the latest bind, which a peer uses to access the window.
+    pub fn rkey(&self) -> u32 {
+        self.state.lock().unwrap().rkey
+    }
+
+    pub fn is_bound(&self) -> bool {
+        self.state.lock().unwrap().bound.is_some()
+    }
+
+    pub(crate) fn as_ptr(&self) -> *mut verbs::ibv_mw {
+        self.mw.0
+    }
+
+    pub(crate) fn state(&self) -> std::sync::MutexGuard<'_, WindowState> {
+        self.state.lock().unwrap()
+    }
+}
+
+impl std::fmt::Debug for MemoryWindow {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let state = self.state.lock().unwrap();
+        f.debug_struct("MemoryWindow")
+            .field("device_index", &self.device_index)
+            .field("rkey", &state.rkey)
+            .field("bound", &state.bound.is_some())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{testing::connected_sockets, *};
+
+    #[test]
+    fn test_memory_window() {
+        let (devices, comp_queues, socket_a, socket_b) = connected_sockets(1);
+        let queue_pair_b = socket_b.queue_pair();
+
+        let access = AccessFlags::local().memory_windows();
+        let buffer_pool = BufferPool::create_with_access(4096, 4, &devices, access).unwrap();
+        let mut exposed = buffer_pool.allocate().unwrap();
+        exposed.fill(0);
+        let exposed_addr = exposed.as_ptr() as u64;
+        let window = MemoryWindow::alloc(&devices, 0).unwrap();
+        println!("{:?}", window);
+
+        let err = socket_a
+            .bind_window(
+                0,
+                &window,
+                buffer_pool.allocate().unwrap(),
+                0..8192,
+                verbs::ibv_access_flags::IBV_ACCESS_REMOTE_WRITE.0,
+            )
+            .unwrap_err();
+        assert_eq!(err.kind, ErrorKind::IBBindMemoryWindowFail);
+
+        // only the first 1 KiB is writable by the peer of socket a.
+        let rkey = socket_a
+            .bind_window(
+                1,
+                &window,
+                exposed,
+                0..1024,
+                verbs::ibv_access_flags::IBV_ACCESS_REMOTE_WRITE.0,
+            )
+            .unwrap();
+        assert_eq!(rkey, window.rkey());
+        assert!(window.is_bound());
+        assert_eq!(buffer_pool.stats().in_use, 1);
+
+        let local = buffer_pool.allocate().unwrap();
+        let device = &devices[0];
+        let mut sge = verbs::ibv_sge {
+            addr: local.as_ptr() as _,
+            length: 1024,
+            lkey: local.lkey(device),
+        };
+        let mut wr = verbs::ibv_send_wr {
+            wr_id: 2,
+            sg_list: &mut sge,
+            num_sge: 1,
+            opcode: verbs::ibv_wr_opcode::IBV_WR_RDMA_WRITE,
+            send_flags: verbs::ibv_send_flags::IBV_SEND_SIGNALED.0,
+            ..Default::default()
+        };
+        wr.wr.rdma.remote_addr = exposed_addr;
+        wr.wr.rdma.rkey = rkey;
+        assert_eq!(queue_pair_b.post_send(&mut wr), 0);
+
+        let mut wcs = vec![verbs::ibv_wc::default(); 128];
+        let comp = comp_queues.poll_cq(&mut wcs).unwrap();
+        assert_eq!(comp.len(), 2);
+        assert_eq!(comp[0].opcode, verbs::ibv_wc_opcode::IBV_WC_BIND_MW);
+        assert_eq!(comp[1].opcode, verbs::ibv_wc_opcode::IBV_WC_RDMA_WRITE);
+        assert!(comp.iter().all(|wc| wc.result().is_ok()));
+
+        // the buffer is released once the invalidation completes.
+        socket_a.invalidate_window(3, &window).unwrap();
+        assert!(!window.is_bound());
+        let comp = comp_queues.poll_cq(&mut wcs).unwrap();
+        assert_eq!(comp.len(), 1);
+        assert_eq!(comp[0].opcode, verbs::ibv_wc_opcode::IBV_WC_LOCAL_INV);
+        assert!(comp[0].result().is_ok());
+        drop(comp_queues.take_buffer(0, &comp[0]));
+        assert_eq!(buffer_pool.stats().in_use, 1);
+
+        // the revoked rkey fails with a remote access error.
+        wr.wr_id = 4;
+        assert_eq!(queue_pair_b.post_send(&mut wr), 0);
+        let comp = comp_queues.poll_cq(&mut wcs).unwrap();
+        assert_eq!(comp.len(), 1);
+        assert_eq!(comp[0].wc_status(), WcStatus::RemoteAccessError);
+    }
+}
@@ -1,20 +1,64 @@
 mod config;
-pub use config::{Config, DeviceConfig, GidType};
+pub use config::{
+    Config, DeviceConfig, EventLoopConfig, FlowControlConfig, GidType, LoopAssignment, PollStrategy,
+};
+
+mod attributes;
+pub use attributes::{
+    link_speed_gbps, link_width_lanes, AtomicCap, DeviceAttr, LinkLayer, Mtu, OdpCaps, PortAttr,
+    PortState,
+};
 
 mod devices;
-pub use devices::{Device, Devices};
+pub use devices::{
+    Device, DeviceDiff, DeviceInfo, Devices, DevicesDiff, GidChange, Port, PortStateChange,
+};
+
+mod async_event;
+pub use async_event::{AsyncEvent, AsyncEventKind, AsyncEventMonitor, AsyncEventTarget};
+
+mod limits;
+
+mod counters;
+pub use counters::{DeviceCounterRates, DeviceCounters, PortCounterRates, PortCounters};
 
 mod comp_queues;
-pub use comp_queues::CompQueues;
+pub use comp_queues::{CompQueues, Completions};
+
+mod shared_recv_queue;
+pub use shared_recv_queue::SharedRecvQueue;
 
 mod queue_pair;
-pub use queue_pair::{Endpoint, QueuePair};
+pub use queue_pair::{Endpoint, QueuePair, QueuePairAttr, QueuePairCap, QueuePairState};
+
+mod memory_window;
+pub use memory_window::MemoryWindow;
+
+mod address_handle;
+pub use address_handle::AddressHandle;
 
 mod event_loop;
-pub use event_loop::EventLoop;
+pub use event_loop::{EventLoop, EventLoopStats};
+
+mod event_loop_group;
+pub use event_loop_group::EventLoopGroup;
+
+mod credits;
 
 mod socket;
-pub use socket::Socket;
+pub(crate) use socket::PostResult;
+pub use socket::{Socket, CREDIT_UPDATE_WR_ID};
+
+#[cfg(feature = "rdmacm")]
+mod rdma_cm;
+#[cfg(feature = "rdmacm")]
+pub use rdma_cm::{CmConnection, RdmaListener};
+
+mod ud_socket;
+pub use ud_socket::{UdSocket, GRH_SIZE};
+
+mod wc_status;
+pub use wc_status::WcStatus;
 
 mod waiter;
 pub use waiter::Waiter;
@@ -1,5 +1,5 @@
 use super::*;
-use crate::{verbs, ErrorKind, Result};
+use crate::{verbs, Error, ErrorKind, Result, VerbsBackend};
 use serde::{Deserialize, Serialize};
 use std::{ffi::c_int, ops::Deref, sync::Arc};
 
@@ -10,10 +10,83 @@ pub struct Endpoint {
     pub gid: verbs::ibv_gid,
 }
 
-struct RawQueuePair(*mut verbs::ibv_qp);
+/// The state of a queue pair.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum QueuePairState {
+    Reset,
+    Init,
+    ReadyToRecv,
+    ReadyToSend,
+    SendQueueDrained,
+    SendQueueError,
+    Error,
+    Unknown,
+}
+
+impl From<verbs::ibv_qp_state> for QueuePairState {
+    fn from(state: verbs::ibv_qp_state) -> Self {
+        match state {
+            verbs::ibv_qp_state::IBV_QPS_RESET => Self::Reset,
+            verbs::ibv_qp_state::IBV_QPS_INIT => Self::Init,
+            verbs::ibv_qp_state::IBV_QPS_RTR => Self::ReadyToRecv,
+            verbs::ibv_qp_state::IBV_QPS_RTS => Self::ReadyToSend,
+            verbs::ibv_qp_state::IBV_QPS_SQD => Self::SendQueueDrained,
+            verbs::ibv_qp_state::IBV_QPS_SQE => Self::SendQueueError,
+            verbs::ibv_qp_state::IBV_QPS_ERR => Self::Error,
+            _ => Self::Unknown,
+        }
+    }
+}
+
+/// The capabilities of a queue pair.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct QueuePairCap {
+    pub max_send_wr: u32,
+    pub max_recv_wr: u32,
+    pub max_send_sge: u32,
+    pub max_recv_sge: u32,
+    pub max_inline_data: u32,
+}
+
+impl From<verbs::ibv_qp_cap> for QueuePairCap {
+    fn from(cap: verbs::ibv_qp_cap) -> Self {
+        Self {
+            max_send_wr: cap.max_send_wr,
+            max_recv_wr: cap.max_recv_wr,
+            max_send_sge: cap.max_send_sge,
+            max_recv_sge: cap.max_recv_sge,
+            max_inline_data: cap.max_inline_data,
+        }
+    }
+}
+
+/// A snapshot of queue pair attributes queried from the device.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct QueuePairAttr {
+    pub state: QueuePairState,
+    /// The path MTU, which is not set before the queue pair is ready to receive.
+    pub path_mtu: Option<Mtu>,
+    pub rq_psn: u32,
+    pub sq_psn: u32,
+    pub dest_qp_num: u32,
+    pub qkey: u32,
+    pub timeout: u8,
+    pub retry_cnt: u8,
+    pub rnr_retry: u8,
+    pub min_rnr_timer: u8,
+    pub max_rd_atomic: u8,
+    pub max_dest_rd_atomic: u8,
+    pub port_num: u8,
+    pub pkey_index: u16,
+    pub sgid_index: u8,
+    pub dgid: verbs::ibv_gid,
+    pub cap: QueuePairCap,
+}
+
+struct RawQueuePair(*mut verbs::ibv_qp, Arc<dyn VerbsBackend>);
 impl Drop for RawQueuePair {
     fn drop(&mut self) {
-        let _ = unsafe { verbs::ibv_destroy_qp(self.0) };
+        let _ = unsafe { self.1.destroy_qp(self.0) };
     }
 }
 unsafe impl Send for RawQueuePair {}
@@ -24,6 +97,7 @@ unsafe impl Sync for RawQueuePair {}
 pub struct QueuePair {
     queue_pair: RawQueuePair,
     _comp_queues: Arc<CompQueues>,
+    _srq: Option<Arc<SharedRecvQueue>>,
     _device_index: usize,
     _devices: Devices,
 }
@@ -35,31 +109,115 @@ impl QueuePair {
         comp_queues: &Arc<CompQueues>,
         cap: verbs::ibv_qp_cap,
     ) -> Result<Self> {
+        Self::create_impl(
+            devices,
+            device_index,
+            comp_queues,
+            cap,
+            None,
+            verbs::ibv_qp_type::IBV_QPT_RC,
+        )
+    }
+
+    /// Creates an unreliable datagram queue pair.
+    pub fn create_ud(
+        devices: &Devices,
+        device_index: usize,
+        comp_queues: &Arc<CompQueues>,
+        cap: verbs::ibv_qp_cap,
+    ) -> Result<Self> {
+        Self::create_impl(
+            devices,
+            device_index,
+            comp_queues,
+            cap,
+            None,
+            verbs::ibv_qp_type::IBV_QPT_UD,
+        )
+    }
+
+    /// Creates a queue pair whose receives are served by the shared receive queue.
+    /// The shared receive queue must be created on the same device.
+    pub fn create_with_srq(
+        devices: &Devices,
+        device_index: usize,
+        comp_queues: &Arc<CompQueues>,
+        cap: verbs::ibv_qp_cap,
+        srq: &Arc<SharedRecvQueue>,
+    ) -> Result<Self> {
+        if srq.device_index() != device_index {
+            return Err(Error::new(
+                ErrorKind::IBCreateQueuePairFail,
+                format!(
+                    "shared receive queue is on device {}, not device {}",
+                    srq.device_index(),
+                    device_index
+                ),
+            ));
+        }
+        Self::create_impl(
+            devices,
+            device_index,
+            comp_queues,
+            cap,
+            Some(srq),
+            verbs::ibv_qp_type::IBV_QPT_RC,
+        )
+    }
+
+    fn create_impl(
+        devices: &Devices,
+        device_index: usize,
+        comp_queues: &Arc<CompQueues>,
+        cap: verbs::ibv_qp_cap,
+        srq: Option<&Arc<SharedRecvQueue>>,
+        qp_type: verbs::ibv_qp_type,
+    ) -> Result<Self> {
+        devices[device_index].info().check_qp_cap(&cap)?;
+
         let mut attr = verbs::ibv_qp_init_attr {
             qp_context: std::ptr::null_mut(),
             send_cq: comp_queues.comp_queue_ptr(device_index),
             recv_cq: comp_queues.comp_queue_ptr(device_index),
-            srq: std::ptr::null_mut(),
+            srq: srq.map_or(std::ptr::null_mut(), |srq| srq.srq_ptr()),
             cap,
-            qp_type: verbs::ibv_qp_type::IBV_QPT_RC,
+            qp_type,
             sq_sig_all: 0,
         };
-        let ptr = unsafe { verbs::ibv_create_qp(devices[device_index].pd_ptr(), &mut attr) };
+        let device = &devices[device_index];
+        let ptr = unsafe { device.backend().create_qp(device.pd_ptr(), &mut attr) };
         if ptr.is_null() {
             return Err(ErrorKind::IBCreateQueuePairFail.with_errno());
         }
         Ok(Self {
-            queue_pair: RawQueuePair(ptr),
+            queue_pair: RawQueuePair(ptr, device.backend().clone()),
             _comp_queues: comp_queues.clone(),
+            _srq: srq.cloned(),
             _device_index: device_index,
             _devices: devices.clone(),
         })
     }
 
+    pub fn comp_queues(&self) -> &Arc<CompQueues> {
+        &self._comp_queues
+    }
+
+    pub fn shared_recv_queue(&self) -> Option<&Arc<SharedRecvQueue>> {
+        self._srq.as_ref()
+    }
+
     pub fn device(&self) -> &Device {
         &self._devices[self._device_index]
     }
 
+    pub fn devices(&self) -> &Devices {
+        &self._devices
+    }
+
+    pub fn device_index(&self) -> usize {
+        self._device_index
+    }
+
     pub fn init(&self, port_num: u8, pkey_index: u16) -> Result<()> {
         let mut attr = verbs::ibv_qp_attr {
             qp_state: verbs::ibv_qp_state::IBV_QPS_INIT,
@@ -141,7 +299,43 @@ impl QueuePair {
         self.modify_qp(&mut attr, MASK)
     }
 
-    pub fn set_error(&self) {
+    /// Moves an unreliable datagram queue pair through INIT, RTR and RTS.
+    pub fn init_ud(&self, port_num: u8, pkey_index: u16, qkey: u32) -> Result<()> {
+        let mut attr = verbs::ibv_qp_attr {
+            qp_state: verbs::ibv_qp_state::IBV_QPS_INIT,
+            pkey_index,
+            port_num,
+            qkey,
+            ..Default::default()
+        };
+        const INIT_MASK: verbs::ibv_qp_attr_mask = verbs::ibv_qp_attr_mask(
+            verbs::ibv_qp_attr_mask::IBV_QP_STATE.0
+                | verbs::ibv_qp_attr_mask::IBV_QP_PKEY_INDEX.0
+                | verbs::ibv_qp_attr_mask::IBV_QP_PORT.0
+                | verbs::ibv_qp_attr_mask::IBV_QP_QKEY.0,
+        );
+        self.modify_qp(&mut attr, INIT_MASK)?;
+
+        let mut attr = verbs::ibv_qp_attr {
+            qp_state: verbs::ibv_qp_state::IBV_QPS_RTR,
+            ..Default::default()
+        };
+        const RTR_MASK: verbs::ibv_qp_attr_mask = verbs::ibv_qp_attr_mask::IBV_QP_STATE;
+        self.modify_qp(&mut attr, RTR_MASK)?;
+
+        let mut attr = verbs::ibv_qp_attr {
+            qp_state: verbs::ibv_qp_state::IBV_QPS_RTS,
+            sq_psn: 0,
+            ..Default::default()
+        };
+        const RTS_MASK: verbs::ibv_qp_attr_mask = verbs::ibv_qp_attr_mask(
+            verbs::ibv_qp_attr_mask::IBV_QP_STATE.0 | verbs::ibv_qp_attr_mask::IBV_QP_SQ_PSN.0,
+        );
+        self.modify_qp(&mut attr, RTS_MASK)
+    }
+
+    /// Moves the queue pair into the error state, which flushes all outstanding work requests.
+    pub fn set_error(&self) -> Result<()> {
         let mut attr = verbs::ibv_qp_attr {
             qp_state: verbs::ibv_qp_state::IBV_QPS_ERR,
             ..Default::default()
@@ -149,26 +343,95 @@ impl QueuePair {
 
         const MASK: verbs::ibv_qp_attr_mask = verbs::ibv_qp_attr_mask::IBV_QP_STATE;
 
-        // assuming this operation succeeds.
-        self.modify_qp(&mut attr, MASK).unwrap()
+        self.modify_qp(&mut attr, MASK)
     }
 
     pub fn post_send(&self, wr: &mut verbs::ibv_send_wr) -> c_int {
         let mut bad_wr = std::ptr::null_mut();
-        unsafe { verbs::ibv_post_send(self.queue_pair.0, wr, &mut bad_wr) }
+        unsafe {
+            self.queue_pair
+                .1
+                .post_send(self.queue_pair.0, wr, &mut bad_wr)
+        }
     }
 
     pub fn post_recv(&self, wr: &mut verbs::ibv_recv_wr) -> c_int {
         let mut bad_wr = std::ptr::null_mut();
-        unsafe { verbs::ibv_post_recv(self.queue_pair.0, wr, &mut bad_wr) }
+        unsafe {
+            self.queue_pair
+                .1
+                .post_recv(self.queue_pair.0, wr, &mut bad_wr)
+        }
     }
 
-    fn modify_qp(
+    /// Queries the current attributes of the queue pair from the device.
+    pub fn query(&self) -> Result<QueuePairAttr> {
+        const MASK: verbs::ibv_qp_attr_mask = verbs::ibv_qp_attr_mask(
+            verbs::ibv_qp_attr_mask::IBV_QP_STATE.0
+                | verbs::ibv_qp_attr_mask::IBV_QP_PKEY_INDEX.0
+                | verbs::ibv_qp_attr_mask::IBV_QP_PORT.0
+                | verbs::ibv_qp_attr_mask::IBV_QP_QKEY.0
+                | verbs::ibv_qp_attr_mask::IBV_QP_AV.0
+                | verbs::ibv_qp_attr_mask::IBV_QP_PATH_MTU.0
+                | verbs::ibv_qp_attr_mask::IBV_QP_TIMEOUT.0
+                | verbs::ibv_qp_attr_mask::IBV_QP_RETRY_CNT.0
+                | verbs::ibv_qp_attr_mask::IBV_QP_RNR_RETRY.0
+                | verbs::ibv_qp_attr_mask::IBV_QP_RQ_PSN.0
+                | verbs::ibv_qp_attr_mask::IBV_QP_MAX_QP_RD_ATOMIC.0
+                | verbs::ibv_qp_attr_mask::IBV_QP_MIN_RNR_TIMER.0
+                | verbs::ibv_qp_attr_mask::IBV_QP_SQ_PSN.0
+                | verbs::ibv_qp_attr_mask::IBV_QP_MAX_DEST_RD_ATOMIC.0
+                | verbs::ibv_qp_attr_mask::IBV_QP_CAP.0
+                | verbs::ibv_qp_attr_mask::IBV_QP_DEST_QPN.0,
+        );
+
+        let mut attr = verbs::ibv_qp_attr::default();
+        let mut init_attr = verbs::ibv_qp_init_attr::default();
+        let ret = unsafe {
+            self.queue_pair
+                .1
+                .query_qp(self.queue_pair.0, &mut attr, MASK.0 as _, &mut init_attr)
+        };
+        if ret != 0 {
+            return Err(ErrorKind::IBQueryQueuePairFail.with_errno());
+        }
+
+        Ok(QueuePairAttr {
+            state: attr.qp_state.into(),
+            path_mtu: (attr.path_mtu.0 != 0).then(|| attr.path_mtu.into()),
+            rq_psn: attr.rq_psn,
+            sq_psn: attr.sq_psn,
+            dest_qp_num: attr.dest_qp_num,
+            qkey: attr.qkey,
+            timeout: attr.timeout,
+            retry_cnt: attr.retry_cnt,
+            rnr_retry: attr.rnr_retry,
+            min_rnr_timer: attr.min_rnr_timer,
+            max_rd_atomic: attr.max_rd_atomic,
+            max_dest_rd_atomic: attr.max_dest_rd_atomic,
+            port_num: attr.port_num,
+            pkey_index: attr.pkey_index,
+            sgid_index: attr.ah_attr.grh.sgid_index,
+            dgid: attr.ah_attr.grh.dgid,
+            cap: attr.cap.into(),
+        })
+    }
+
+    /// Queries the current state of the queue pair from the device.
+    pub fn state(&self) -> Result<QueuePairState> {
+        self.query().map(|attr| attr.state)
+    }
+
+    pub(crate) fn modify_qp(
         &self,
         attr: &mut verbs::ibv_qp_attr,
         mask: verbs::ibv_qp_attr_mask,
     ) -> Result<()> {
-        let ret = unsafe { verbs::ibv_modify_qp(self.queue_pair.0, attr, mask.0 as _) };
+        let ret = unsafe {
+            self.queue_pair
+                .1
+                .modify_qp(self.queue_pair.0, attr, mask.0 as _)
+        };
         if ret == 0_i32 {
             Ok(())
         } else {
@@ -185,12 +448,20 @@ impl Deref for QueuePair {
     }
 }
 
+impl Drop for QueuePair {
+    fn drop(&mut self) {
+        // completions still queued for this queue pair are ignored from now on.
+        self._comp_queues
+            .unregister(self._device_index, self.qp_num);
+    }
+}
+
 impl std::fmt::Debug for QueuePair {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.debug_struct("QueuePair")
             .field("handle", &self.handle)
             .field("qp_num", &self.qp_num)
-            .field("state", &self.state)
+            .field("state", &self.query().map(|attr| attr.state))
             .field("qp_type", &self.qp_type)
             .field("events_completiond", &self.events_completed)
             .finish()
@@ -200,24 +471,27 @@ impl std::fmt::Debug for QueuePair {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::testing::{cap, connect, socket_pair};
     use crate::*;
 
     #[test]
     fn test_queue_pair_create() {
         let devices = Devices::availables().unwrap();
         let comp_queues = CompQueues::create(&devices, 128).unwrap();
-        let cap = verbs::ibv_qp_cap {
-            max_send_wr: 64,
-            max_recv_wr: 64,
-            max_send_sge: 1,
-            max_recv_sge: 1,
-            max_inline_data: 0,
-        };
-        let queue_pair = QueuePair::create(&devices, 0, &comp_queues, cap).unwrap();
+        let queue_pair = QueuePair::create(&devices, 0, &comp_queues, cap()).unwrap();
         println!("{:#?}", queue_pair);
+        assert_eq!(queue_pair.state().unwrap(), QueuePairState::Reset);
 
         queue_pair.init(1, 0).unwrap();
-        queue_pair.set_error();
+        let attr = queue_pair.query().unwrap();
+        println!("{:#?}", attr);
+        assert_eq!(attr.state, QueuePairState::Init);
+        assert_eq!(attr.port_num, 1);
+        assert_eq!(attr.path_mtu, None);
+        assert!(attr.cap.max_send_wr >= 64);
+
+        queue_pair.set_error().unwrap();
+        assert_eq!(queue_pair.state().unwrap(), QueuePairState::Error);
     }
 
     #[test]
@@ -226,24 +500,20 @@ mod tests {
         let devices = Devices::availables().unwrap();
 
         // 2. create two queue pairs.
-        let cap = verbs::ibv_qp_cap {
-            max_send_wr: 64,
-            max_recv_wr: 64,
-            max_send_sge: 1,
-            max_recv_sge: 1,
-            max_inline_data: 0,
-        };
 
         let comp_queues_a = CompQueues::create(&devices, 128).unwrap();
-        let queue_pair_a = QueuePair::create(&devices, 0, &comp_queues_a, cap).unwrap();
-        let socket_a = Socket::create(Arc::new(queue_pair_a));
         let comp_queues_b = CompQueues::create(&devices, 128).unwrap();
-        let queue_pair_b = QueuePair::create(&devices, 0, &comp_queues_b, cap).unwrap();
-        let socket_b = Socket::create(Arc::new(queue_pair_b));
+        let (socket_a, socket_b) =
+            socket_pair(&devices, &comp_queues_a, &comp_queues_b, Socket::create);
 
         // 3. init all queue pairs.
-        socket_a.init(socket_b.endpoint()).unwrap();
-        socket_b.init(socket_a.endpoint()).unwrap();
+        connect(&socket_a, &socket_b);
+
+        let attr = socket_a.query().unwrap();
+        assert_eq!(attr.state, QueuePairState::ReadyToSend);
+        assert_eq!(attr.dest_qp_num, socket_b.qp_num());
+        assert_eq!(attr.path_mtu, Some(Mtu::Mtu512));
+        assert!(!socket_b.is_error().unwrap());
 
         // 4. post recv wr.
         const LEN: usize = 1 << 20;
@@ -0,0 +1,393 @@
+use super::{CompQueues, Devices, QueuePair, Socket};
+use crate::{verbs, Error, ErrorKind, Result};
+use std::{
+    ffi::CStr,
+    net::{Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4, SocketAddrV6},
+    sync::Arc,
+};
+
+const TIMEOUT_MS: i32 = 2000;
+const BACKLOG: i32 = 128;
+// the private data of a connect request is limited to 56 bytes, the first one carries the length.
+const MAX_PRIVATE_DATA_LEN: usize = 55;
+
+/// A synchronous `rdma_cm_id`, whose calls block until the corresponding event arrives.
+#[derive(Debug)]
+pub(crate) struct CmId(*mut verbs::rdma_cm_id);
+impl Drop for CmId {
+    fn drop(&mut self) {
+        // also acknowledges the last event of the id.
+        let _ = unsafe { verbs::rdma_destroy_id(self.0) };
+    }
+}
+unsafe impl Send for CmId {}
+unsafe impl Sync for CmId {}
+
+impl CmId {
+    fn create() -> Result<Self> {
+        let mut id = std::ptr::null_mut();
+        let ret = unsafe {
+            verbs::rdma_create_id(
+                std::ptr::null_mut(),
+                &mut id,
+                std::ptr::null_mut(),
+                verbs::rdma_port_space::RDMA_PS_TCP,
+            )
+        };
+        if ret != 0 {
+            return Err(ErrorKind::CMCreateIdFail.with_errno());
+        }
+        Ok(Self(id))
+    }
+
+    fn device_index(&self, devices: &Devices) -> Result<usize> {
+        let name = unsafe { CStr::from_ptr((*(*(*self.0).verbs).device).name.as_ptr()) }
+            .to_string_lossy()
+            .to_string();
+        devices
+            .iter()
+            .position(|device| device.info().name == name)
+            .ok_or_else(|| Error::new(ErrorKind::CMDeviceNotFound, name))
+    }
+
+    fn local_addr(&self) -> Option<SocketAddr> {
+        unsafe { from_raw_addr(&(*self.0).route.addr.__bindgen_anon_1 as *const _ as _) }
+    }
+
+    fn peer_addr(&self) -> Option<SocketAddr> {
+        unsafe { from_raw_addr(&(*self.0).route.addr.__bindgen_anon_2 as *const _ as _) }
+    }
+
+    /// Returns the private data carried by the last connection event.
+    fn private_data(&self) -> Result<Vec<u8>> {
+        let event = unsafe { (*self.0).event };
+        if event.is_null() {
+            return Ok(vec![]);
+        }
+        let conn = unsafe { (*event).param.conn };
+        let data = match conn.private_data_len {
+            0 => &[][..],
+            len => unsafe {
+                std::slice::from_raw_parts(conn.private_data as *const u8, len as usize)
+            },
+        };
+        match data.split_first() {
+            None => Ok(vec![]),
+            Some((&len, data)) if len as usize <= data.len() => Ok(data[..len as usize].to_vec()),
+            Some(_) => Err(ErrorKind::CMInvalidPrivateData.into()),
+        }
+    }
+
+    /// Moves the queue pair into the state with the attributes computed by the connection manager.
+    fn modify(&self, queue_pair: &QueuePair, qp_state: verbs::ibv_qp_state) -> Result<()> {
+        let mut attr = verbs::ibv_qp_attr {
+            qp_state,
+            ..Default::default()
+        };
+        let mut mask = 0;
+        let ret = unsafe { verbs::rdma_init_qp_attr(self.0, &mut attr, &mut mask) };
+        if ret != 0 {
+            return Err(ErrorKind::CMInitQueuePairAttrFail.with_errno());
+        }
+        if qp_state == verbs::ibv_qp_state::IBV_QPS_INIT {
+            attr.qp_access_flags = verbs::ACCESS_FLAGS;
+        }
+        queue_pair.modify_qp(&mut attr, verbs::ibv_qp_attr_mask(mask as u32))
+    }
+
+    fn conn_param(
+        devices: &Devices,
+        device_index: usize,
+        queue_pair: &QueuePair,
+        private_data: &mut Vec<u8>,
+    ) -> verbs::rdma_conn_param {
+        let attr = devices[device_index].info().attr();
+        private_data.insert(0, private_data.len() as u8);
+        verbs::rdma_conn_param {
+            private_data: private_data.as_ptr() as _,
+            private_data_len: private_data.len() as u8,
+            responder_resources: attr.max_qp_rd_atom.clamp(0, 16) as u8,
+            initiator_depth: attr.max_qp_init_rd_atom.clamp(0, 16) as u8,
+            retry_count: 7,
+            rnr_retry_count: 7,
+            qp_num: queue_pair.qp_num,
+            ..Default::default()
+        }
+    }
+}
+
+fn check_private_data(private_data: &[u8]) -> Result<()> {
+    if private_data.len() > MAX_PRIVATE_DATA_LEN {
+        return Err(Error::new(
+            ErrorKind::CMInvalidPrivateData,
+            format!(
+                "private data is {} bytes, at most {} bytes are allowed",
+                private_data.len(),
+                MAX_PRIVATE_DATA_LEN
+            ),
+        ));
+    }
+    Ok(())
+}
+
+fn to_raw_addr(addr: &SocketAddr) -> libc::sockaddr_storage {
+    let mut storage = unsafe { std::mem::zeroed::<libc::sockaddr_storage>() };
+    match addr {
+        SocketAddr::V4(addr) => {
+            let sin = unsafe { &mut *(&mut storage as *mut _ as *mut libc::sockaddr_in) };
+            sin.sin_family = libc::AF_INET as _;
+            sin.sin_port = addr.port().to_be();
+            sin.sin_addr.s_addr = u32::from(*addr.ip()).to_be();
+        }
+        SocketAddr::V6(addr) => {
+            let sin6 = unsafe { &mut *(&mut storage as *mut _ as *mut libc::sockaddr_in6) };
+            sin6.sin6_family = libc::AF_INET6 as _;
+            sin6.sin6_port = addr.port().to_be();
+            sin6.sin6_flowinfo = addr.flowinfo();
+            sin6.sin6_addr.s6_addr = addr.ip().octets();
+            sin6.sin6_scope_id = addr.scope_id();
+        }
+    }
+    storage
+}
+
+unsafe fn from_raw_addr(addr: *const libc::sockaddr) -> Option<SocketAddr> {
+    match (*addr).sa_family as i32 {
+        libc::AF_INET => {
+            let sin = &*(addr as *const libc::sockaddr_in);
+            Some(SocketAddr::V4(SocketAddrV4::new(
+                Ipv4Addr::from(u32::from_be(sin.sin_addr.s_addr)),
+                u16::from_be(sin.sin_port),
+            )))
+        }
+        libc::AF_INET6 => {
+            let sin6 = &*(addr as *const libc::sockaddr_in6);
+            Some(SocketAddr::V6(SocketAddrV6::new(
+                Ipv6Addr::from(sin6.sin6_addr.s6_addr),
+                u16::from_be(sin6.sin6_port),
+                sin6.sin6_flowinfo,
+                sin6.sin6_scope_id,
+            )))
+        }
+        _ => None,
+    }
+}
+
+/// A connection established through the RDMA connection manager.
+#[derive(Debug)]
+pub struct CmConnection {
+    pub socket: Socket,
+    pub peer_addr: Option<SocketAddr>,
+    /// The private data sent by the peer.
+    pub private_data: Vec<u8>,
+}
+
+/// Listens for connections by IP address through the RDMA connection manager, like a `TcpListener`.
+/// Accepted queue pairs are created on the device the connection arrives at, using the completion queues.
+pub struct RdmaListener {
+    id: CmId,
+    devices: Devices,
+    comp_queues: Arc<CompQueues>,
+    cap: verbs::ibv_qp_cap,
+}
+
+impl RdmaListener {
+    pub fn bind(
+        addr: SocketAddr,
+        devices: &Devices,
+        comp_queues: &Arc<CompQueues>,
+        cap: verbs::ibv_qp_cap,
+    ) -> Result<Self> {
+        let id = CmId::create()?;
+        let mut raw_addr = to_raw_addr(&addr);
+        let ret = unsafe { verbs::rdma_bind_addr(id.0, &mut raw_addr as *mut _ as _) };
+        if ret != 0 {
+            return Err(ErrorKind::CMBindAddrFail.with_errno());
+        }
+        let ret = unsafe { verbs::rdma_listen(id.0, BACKLOG) };
+        if ret != 0 {
+            return Err(ErrorKind::CMListenFail.with_errno());
+        }
+        Ok(Self {
+            id,
+            devices: devices.clone(),
+            comp_queues: comp_queues.clone(),
+            cap,
+        })
+    }
+
+    pub fn local_addr(&self) -> Option<SocketAddr> {
+        self.id.local_addr()
+    }
+
+    /// Blocks until a connection request arrives, then accepts it with the private data.
+    pub fn accept(&self, private_data: &[u8]) -> Result<CmConnection> {
+        check_private_data(private_data)?;
+        let mut conn_id = std::ptr::null_mut();
+        let ret = unsafe { verbs::rdma_get_request(self.id.0, &mut conn_id) };
+        if ret != 0 {
+            return Err(ErrorKind::CMGetRequestFail.with_errno());
+        }
+        let conn_id = CmId(conn_id);
+
+        let result = self.accept_impl(&conn_id, private_data);
+        if result.is_err() {
+            let _ = unsafe { verbs::rdma_reject(conn_id.0, std::ptr::null(), 0) };
+        }
+        let (queue_pair, peer_private_data) = result?;
+
+        let peer_addr = conn_id.peer_addr();
+        Ok(CmConnection {
+            socket: Socket::create(queue_pair).with_cm_id(conn_id),
+            peer_addr,
+            private_data: peer_private_data,
+        })
+    }
+
+    fn accept_impl(
+        &self,
+        conn_id: &CmId,
+        private_data: &[u8],
+    ) -> Result<(Arc<QueuePair>, Vec<u8>)> {
+        let peer_private_data = conn_id.private_data()?;
+        let device_index = conn_id.device_index(&self.devices)?;
+        let queue_pair = Arc::new(QueuePair::create(
+            &self.devices,
+            device_index,
+            &self.comp_queues,
+            self.cap,
+        )?);
+        conn_id.modify(&queue_pair, verbs::ibv_qp_state::IBV_QPS_INIT)?;
+        conn_id.modify(&queue_pair, verbs::ibv_qp_state::IBV_QPS_RTR)?;
+
+        let mut private_data = private_data.to_vec();
+        let mut param =
+            CmId::conn_param(&self.devices, device_index, &queue_pair, &mut private_data);
+        // blocks until the connection is established.
+        let ret = unsafe { verbs::rdma_accept(conn_id.0, &mut param) };
+        if ret != 0 {
+            return Err(ErrorKind::CMAcceptFail.with_errno());
+        }
+        conn_id.modify(&queue_pair, verbs::ibv_qp_state::IBV_QPS_RTS)?;
+        Ok((queue_pair, peer_private_data))
+    }
+}
+
+impl std::fmt::Debug for RdmaListener {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RdmaListener")
+            .field("local_addr", &self.local_addr())
+            .field("cap", &self.cap)
+            .finish()
+    }
+}
+
+impl Socket {
+    /// Connects to an `RdmaListener` by IP address through the RDMA connection manager.
+    /// The route is resolved to a local device, on which the queue pair is created using the completion queues.
+    pub fn connect(
+        addr: SocketAddr,
+        devices: &Devices,
+        comp_queues: &Arc<CompQueues>,
+        cap: verbs::ibv_qp_cap,
+        private_data: &[u8],
+    ) -> Result<CmConnection> {
+        check_private_data(private_data)?;
+        let id = CmId::create()?;
+        let mut raw_addr = to_raw_addr(&addr);
+        let ret = unsafe {
+            verbs::rdma_resolve_addr(
+                id.0,
+                std::ptr::null_mut(),
+                &mut raw_addr as *mut _ as _,
+                TIMEOUT_MS,
+            )
+        };
+        if ret != 0 {
+            return Err(ErrorKind::CMResolveAddrFail.with_errno());
+        }
+        let ret = unsafe { verbs::rdma_resolve_route(id.0, TIMEOUT_MS) };
+        if ret != 0 {
+            return Err(ErrorKind::CMResolveRouteFail.with_errno());
+        }
+
+        let device_index = id.device_index(devices)?;
+        let queue_pair = Arc::new(QueuePair::create(devices, device_index, comp_queues, cap)?);
+        id.modify(&queue_pair, verbs::ibv_qp_state::IBV_QPS_INIT)?;
+
+        let mut private_data = private_data.to_vec();
+        let mut param = CmId::conn_param(devices, device_index, &queue_pair, &mut private_data);
+        // blocks until the connect response arrives, since the queue pair is not owned by the id.
+        let ret = unsafe { verbs::rdma_connect(id.0, &mut param) };
+        if ret != 0 {
+            return Err(ErrorKind::CMConnectFail.with_errno());
+        }
+        let peer_private_data = id.private_data()?;
+
+        id.modify(&queue_pair, verbs::ibv_qp_state::IBV_QPS_RTR)?;
+        id.modify(&queue_pair, verbs::ibv_qp_state::IBV_QPS_RTS)?;
+        let ret = unsafe { verbs::rdma_establish(id.0) };
+        if ret != 0 {
+            return Err(ErrorKind::CMEstablishFail.with_errno());
+        }
+
+        Ok(CmConnection {
+            peer_addr: id.peer_addr(),
+            socket: Socket::create(queue_pair).with_cm_id(id),
+            private_data: peer_private_data,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::cap;
+    use crate::*;
+
+    #[test]
+    fn test_raw_addr() {
+        for addr in ["10.0.0.1:18515", "[fe80::1]:4791"] {
+            let addr: SocketAddr = addr.parse().unwrap();
+            let raw_addr = to_raw_addr(&addr);
+            let parsed = unsafe { from_raw_addr(&raw_addr as *const _ as _) };
+            assert_eq!(parsed, Some(addr));
+        }
+        assert!(check_private_data(&[0; MAX_PRIVATE_DATA_LEN]).is_ok());
+        assert!(check_private_data(&[0; MAX_PRIVATE_DATA_LEN + 1]).is_err());
+    }
+
+    #[test]
+    fn test_rdma_cm_connect() {
+        let devices = Devices::availables().unwrap();
+        let ip = devices[0].info().ports[0]
+            .gids
+            .iter()
+            .find_map(|(_, gid, _)| gid.as_ipv6().to_ipv4_mapped())
+            .unwrap();
+        let comp_queues = CompQueues::create(&devices, 128).unwrap();
+        let listener =
+            RdmaListener::bind(SocketAddr::new(ip.into(), 0), &devices, &comp_queues, cap())
+                .unwrap();
+        let addr = listener.local_addr().unwrap();
+        println!("{:#?}", listener);
+
+        let server = std::thread::spawn(move || listener.accept(b"server").unwrap());
+        let client = Socket::connect(addr, &devices, &comp_queues, cap(), b"client").unwrap();
+        let server = server.join().unwrap();
+        assert_eq!(client.private_data, b"server");
+        assert_eq!(server.private_data, b"client");
+        assert_eq!(
+            client.socket.query().unwrap().state,
+            QueuePairState::ReadyToSend
+        );
+        assert_eq!(
+            server.socket.query().unwrap().state,
+            QueuePairState::ReadyToSend
+        );
+        assert_eq!(
+            client.socket.query().unwrap().dest_qp_num,
+            server.socket.qp_num()
+        );
+    }
+}
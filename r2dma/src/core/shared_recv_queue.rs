@@ -0,0 +1,262 @@
+use super::{AsyncEvent, AsyncEventKind, AsyncEventTarget, Devices};
+use crate::{verbs, Buffer, BufferPool, ErrorKind, Result, VerbsBackend};
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+};
+
+struct RawSharedRecvQueue(*mut verbs::ibv_srq, Arc<dyn VerbsBackend>);
+impl Drop for RawSharedRecvQueue {
+    fn drop(&mut self) {
+        let _ = unsafe { self.1.destroy_srq(self.0) };
+    }
+}
+unsafe impl Send for RawSharedRecvQueue {}
+unsafe impl Sync for RawSharedRecvQueue {}
+
+/// Represents a shared receive queue, which can be attached by multiple queue pairs on the same device.
+/// Receive buffers are allocated from a buffer pool and kept posted up to `max_wr`.
+pub struct SharedRecvQueue {
+    srq: RawSharedRecvQueue,
+    max_wr: usize,
+    limit: u32,
+    buffer_pool: Arc<BufferPool>,
+    posted: Mutex<HashMap<u64, Buffer>>,
+    next_wr_id: AtomicU64,
+    device_index: usize,
+    devices: Devices,
+}
+
+impl SharedRecvQueue {
+    /// Creates a shared receive queue on the device.
+    /// When `limit` is non-zero, `IBV_EVENT_SRQ_LIMIT_REACHED` is armed once fewer than `limit` receives are posted,
+    /// and the events of an `AsyncEventMonitor` should be passed to `on_async_event`.
+    pub fn create(
+        devices: &Devices,
+        device_index: usize,
+        buffer_pool: &Arc<BufferPool>,
+        max_wr: u32,
+        limit: u32,
+    ) -> Result<Arc<Self>> {
+        devices[device_index].info().check_srq(max_wr, 1)?;
+
+        let mut init_attr = verbs::ibv_srq_init_attr {
+            srq_context: std::ptr::null_mut(),
+            attr: verbs::ibv_srq_attr {
+                max_wr,
+                max_sge: 1,
+                srq_limit: 0,
+            },
+        };
+        let device = &devices[device_index];
+        let ptr = unsafe { device.backend().create_srq(device.pd_ptr(), &mut init_attr) };
+        if ptr.is_null() {
+            return Err(ErrorKind::IBCreateSharedRecvQueueFail.with_errno());
+        }
+
+        let this = Arc::new(Self {
+            srq: RawSharedRecvQueue(ptr, device.backend().clone()),
+            max_wr: max_wr as usize,
+            limit,
+            buffer_pool: buffer_pool.clone(),
+            posted: Default::default(),
+            next_wr_id: AtomicU64::new(0),
+            device_index,
+            devices: devices.clone(),
+        });
+        this.replenish()?;
+        this.arm_limit()?;
+        Ok(this)
+    }
+
+    pub(crate) fn srq_ptr(&self) -> *mut verbs::ibv_srq {
+        self.srq.0
+    }
+
+    /// Returns the handle reported by `AsyncEventTarget::SharedRecvQueue`.
+    pub fn handle(&self) -> u32 {
+        unsafe { (*self.srq.0).handle }
+    }
+
+    pub fn device_index(&self) -> usize {
+        self.device_index
+    }
+
+    /// Returns the number of receives currently posted.
+    pub fn posted(&self) -> usize {
+        self.posted.lock().unwrap().len()
+    }
+
+    /// Takes the buffer of a completed receive, identified by the `wr_id` of the work completion.
+    pub fn take(&self, wr_id: u64) -> Option<Buffer> {
+        self.posted.lock().unwrap().remove(&wr_id)
+    }
+
+    /// Posts receives from the buffer pool until `max_wr` are posted or the pool is exhausted.
+    /// Returns the number of newly posted receives.
+    pub fn replenish(&self) -> Result<usize> {
+        let device = &self.devices[self.device_index];
+        let mut posted = self.posted.lock().unwrap();
+        let mut count = 0;
+        while posted.len() < self.max_wr {
+            let Ok(buf) = self.buffer_pool.allocate() else {
+                tracing::warn!(
+                    "buffer pool is exhausted, {} receives posted in srq",
+                    posted.len()
+                );
+                break;
+            };
+
+            let wr_id = self.next_wr_id.fetch_add(1, Ordering::AcqRel);
+            let mut recv_sge = verbs::ibv_sge {
+                addr: buf.as_ptr() as _,
+                length: buf.len() as _,
+                lkey: buf.lkey(device),
+            };
+            let mut recv_wr = verbs::ibv_recv_wr {
+                wr_id,
+                sg_list: &mut recv_sge as *mut _,
+                num_sge: 1,
+                next: std::ptr::null_mut(),
+            };
+            let mut bad_wr = std::ptr::null_mut();
+            let ret = unsafe {
+                self.srq
+                    .1
+                    .post_srq_recv(self.srq.0, &mut recv_wr, &mut bad_wr)
+            };
+            if ret != 0 {
+                return Err(ErrorKind::IBPostSharedRecvFailed.with_errno());
+            }
+            posted.insert(wr_id, buf);
+            count += 1;
+        }
+        Ok(count)
+    }
+
+    /// Arms the low watermark event. The event fires once and must be re-armed afterwards.
+    pub fn arm_limit(&self) -> Result<()> {
+        if self.limit == 0 {
+            return Ok(());
+        }
+        let mut attr = verbs::ibv_srq_attr {
+            srq_limit: self.limit,
+            ..Default::default()
+        };
+        let mask = verbs::ibv_srq_attr_mask::IBV_SRQ_LIMIT;
+        let ret = unsafe { self.srq.1.modify_srq(self.srq.0, &mut attr, mask.0 as _) };
+        if ret == 0 {
+            Ok(())
+        } else {
+            Err(ErrorKind::IBModifySharedRecvQueueFail.with_errno())
+        }
+    }
+
+    /// Handles `IBV_EVENT_SRQ_LIMIT_REACHED`: replenishes the queue and re-arms the low watermark.
+    pub fn on_limit_reached(&self) -> Result<usize> {
+        let count = self.replenish()?;
+        self.arm_limit()?;
+        Ok(count)
+    }
+
+    /// Passes an event of `AsyncEventMonitor` to `on_limit_reached` if it is the low watermark event of
+    /// this queue, which is how the callback of the monitor keeps the queue replenished.
+    /// Returns the number of newly posted receives, or `None` if the event is not for this queue.
+    pub fn on_async_event(&self, event: &AsyncEvent) -> Result<Option<usize>> {
+        let target = AsyncEventTarget::SharedRecvQueue {
+            handle: self.handle(),
+        };
+        if event.kind != AsyncEventKind::SharedRecvQueueLimitReached
+            || event.device_index != self.device_index
+            || event.target != target
+        {
+            return Ok(None);
+        }
+        self.on_limit_reached().map(Some)
+    }
+}
+
+impl std::fmt::Debug for SharedRecvQueue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SharedRecvQueue")
+            .field("max_wr", &self.max_wr)
+            .field("limit", &self.limit)
+            .field("posted", &self.posted())
+            .field("device_index", &self.device_index)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{cap, connect};
+    use crate::*;
+
+    #[test]
+    fn test_shared_recv_queue() {
+        const LEN: usize = 4096;
+        let devices = Devices::availables().unwrap();
+        let buffer_pool = BufferPool::create(LEN, 64, &devices).unwrap();
+        let srq = SharedRecvQueue::create(&devices, 0, &buffer_pool, 16, 4).unwrap();
+        assert_eq!(srq.posted(), 16);
+        assert_eq!(buffer_pool.stats().in_use, 16);
+        println!("{:#?}", srq);
+
+        let comp_queues_a = CompQueues::create(&devices, 128).unwrap();
+        let queue_pair_a = QueuePair::create(&devices, 0, &comp_queues_a, cap()).unwrap();
+        let socket_a = Socket::create(Arc::new(queue_pair_a));
+        let comp_queues_b = CompQueues::create(&devices, 128).unwrap();
+        let queue_pair_b =
+            QueuePair::create_with_srq(&devices, 0, &comp_queues_b, cap(), &srq).unwrap();
+        let socket_b = Socket::create(Arc::new(queue_pair_b));
+
+        connect(&socket_a, &socket_b);
+
+        let mut send_buf = buffer_pool.allocate().unwrap();
+        send_buf.fill(3);
+        socket_a.post_send(1, send_buf).unwrap();
+
+        std::thread::sleep(std::time::Duration::from_millis(100));
+        let mut wcs = vec![verbs::ibv_wc::default(); 128];
+        let comp_b = comp_queues_b.poll_cq(&mut wcs).unwrap();
+        assert_eq!(comp_b.len(), 1);
+        assert_eq!(comp_b[0].status, verbs::ibv_wc_status::IBV_WC_SUCCESS);
+        assert_eq!(comp_b[0].qp_num, socket_b.qp_num());
+
+        // receives of the queue pair are served by the shared receive queue.
+        let recv_buf = comp_queues_b.take_buffer(0, &comp_b[0]).unwrap();
+        assert!(recv_buf.iter().all(|&x| x == 3));
+        assert_eq!(srq.posted(), 15);
+        drop(recv_buf);
+
+        let event = AsyncEvent {
+            device_index: 0,
+            device_name: devices[0].info().name.clone(),
+            kind: AsyncEventKind::SharedRecvQueueLimitReached,
+            target: AsyncEventTarget::SharedRecvQueue {
+                handle: srq.handle(),
+            },
+        };
+        assert_eq!(srq.on_async_event(&event).unwrap(), Some(1));
+        assert_eq!(srq.posted(), 16);
+        let event = AsyncEvent {
+            kind: AsyncEventKind::SharedRecvQueueError,
+            ..event
+        };
+        assert_eq!(srq.on_async_event(&event).unwrap(), None);
+    }
+
+    #[test]
+    fn test_shared_recv_queue_other_device() {
+        let devices = MockVerbs::devices(2).unwrap();
+        let buffer_pool = BufferPool::create(4096, 16, &devices).unwrap();
+        let srq = SharedRecvQueue::create(&devices, 0, &buffer_pool, 4, 0).unwrap();
+        let comp_queues = CompQueues::create(&devices, 128).unwrap();
+        let err = QueuePair::create_with_srq(&devices, 1, &comp_queues, cap(), &srq).unwrap_err();
+        assert_eq!(err.kind, ErrorKind::IBCreateQueuePairFail);
+    }
+}
@@ -1,22 +1,173 @@
-use crate::{verbs, Buffer, ErrorKind, Result};
-use std::sync::Arc;
+use crate::{verbs, Buffer, Error, ErrorKind, Result};
+use std::{
+    collections::HashMap,
+    ops::Range,
+    sync::{Arc, Condvar, Mutex},
+    time::Duration,
+};
 
-use super::{Endpoint, QueuePair};
+use super::{
+    credits::{Credits, CREDIT_UPDATE},
+    AddressHandle, Endpoint, FlowControlConfig, MemoryWindow, QueuePair, QueuePairAttr,
+    QueuePairState, SharedRecvQueue,
+};
 
+/// The result of posting a buffer, which carries the buffer back if it was not posted.
+pub(crate) type PostResult = std::result::Result<(), (Error, Option<Buffer>)>;
+
+/// The `wr_id` of the credit updates sent by sockets with flow control, which hold no buffer.
+pub const CREDIT_UPDATE_WR_ID: u64 = u64::MAX;
+
+/// A posted work request which has not completed yet.
+struct PostedWork {
+    buf: Buffer,
+    // keeps the address handle of an unreliable datagram send alive until it completes.
+    _ah: Option<Arc<AddressHandle>>,
+}
+
+/// Buffers of the work requests posted by a socket which have not completed yet, keyed by `wr_id`.
+#[derive(Default)]
+pub(crate) struct OutstandingWork {
+    posted: Mutex<HashMap<u64, PostedWork>>,
+    drained: Condvar,
+    credits: Option<Credits>,
+    // serves the receives of a queue pair attached to a shared receive queue.
+    srq: Option<Arc<SharedRecvQueue>>,
+}
+
+impl OutstandingWork {
+    pub(super) fn insert(&self, wr_id: u64, buf: Buffer) -> std::result::Result<(), Buffer> {
+        self.insert_with_ah(wr_id, buf, None)
+    }
+
+    pub(super) fn insert_with_ah(
+        &self,
+        wr_id: u64,
+        buf: Buffer,
+        ah: Option<Arc<AddressHandle>>,
+    ) -> std::result::Result<(), Buffer> {
+        let mut posted = self.posted.lock().unwrap();
+        if posted.contains_key(&wr_id) {
+            return Err(buf);
+        }
+        posted.insert(wr_id, PostedWork { buf, _ah: ah });
+        Ok(())
+    }
+
+    pub(super) fn remove(&self, wr_id: u64) -> Option<Buffer> {
+        let mut posted = self.posted.lock().unwrap();
+        let work = posted.remove(&wr_id);
+        if posted.is_empty() {
+            self.drained.notify_all();
+        }
+        work.map(|work| work.buf)
+    }
+
+    pub(crate) fn complete(&self, wc: &verbs::ibv_wc) -> Option<Buffer> {
+        if let Some(credits) = &self.credits {
+            credits.on_completion(wc);
+        }
+        let Some(srq) = &self.srq else {
+            return self.remove(wc.wr_id);
+        };
+        if wc.wc_status().is_success() {
+            match wc.is_recv() {
+                true => srq.take(wc.wr_id),
+                false => self.remove(wc.wr_id),
+            }
+        } else {
+            // the opcode of a failed completion is undefined, so both are looked up.
+            self.remove(wc.wr_id).or_else(|| srq.take(wc.wr_id))
+        }
+    }
+
+    pub(super) fn len(&self) -> usize {
+        self.posted.lock().unwrap().len()
+    }
+
+    /// Waits until no work request is outstanding. Returns the number still outstanding on timeout.
+    fn wait_drained(&self, timeout: Duration) -> usize {
+        let posted = self.posted.lock().unwrap();
+        let (posted, _) = self
+            .drained
+            .wait_timeout_while(posted, timeout, |posted| !posted.is_empty())
+            .unwrap();
+        posted.len()
+    }
+}
+
+impl std::fmt::Debug for OutstandingWork {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("OutstandingWork")
+            .field("len", &self.len())
+            .finish()
+    }
+}
+
+/// A reliable connection over a queue pair.
+/// Posted buffers are held until their work requests complete, and released through
+/// `complete` or `CompQueues::take_buffer` by whoever polls the completion queue.
 #[derive(Debug, Clone)]
 pub struct Socket {
+    // dropped first, so the queue pair is destroyed before the buffers it may access are released.
     queue_pair: Arc<QueuePair>,
+    outstanding: Arc<OutstandingWork>,
+    #[cfg(feature = "rdmacm")]
+    cm_id: Option<Arc<super::rdma_cm::CmId>>,
 }
 
 impl Socket {
     pub fn create(queue_pair: Arc<QueuePair>) -> Self {
-        Socket { queue_pair }
+        Self::create_impl(queue_pair, OutstandingWork::default())
+    }
+
+    /// Creates a socket whose sends wait for credits, so they never outrun the receives posted by
+    /// the peer, which must use flow control with the same config. Both sides post
+    /// `initial_credits` receives before sending, and every completion of the socket must be
+    /// handled by `complete` or `CompQueues::take_buffer` to collect the credits it carries.
+    /// Receives consumed by credit updates are completed with no payload, see `is_credit_update`.
+    pub fn create_with_flow_control(queue_pair: Arc<QueuePair>, config: FlowControlConfig) -> Self {
+        let outstanding = OutstandingWork {
+            credits: Some(Credits::new(config)),
+            ..Default::default()
+        };
+        Self::create_impl(queue_pair, outstanding)
+    }
+
+    fn create_impl(queue_pair: Arc<QueuePair>, outstanding: OutstandingWork) -> Self {
+        let outstanding = Arc::new(OutstandingWork {
+            srq: queue_pair.shared_recv_queue().cloned(),
+            ..outstanding
+        });
+        queue_pair.comp_queues().register(
+            queue_pair.device_index(),
+            queue_pair.qp_num,
+            &outstanding,
+        );
+        Socket {
+            queue_pair,
+            outstanding,
+            #[cfg(feature = "rdmacm")]
+            cm_id: None,
+        }
+    }
+
+    /// Keeps the connection manager id alive as long as the socket, as destroying it disconnects.
+    #[cfg(feature = "rdmacm")]
+    pub(crate) fn with_cm_id(mut self, cm_id: super::rdma_cm::CmId) -> Self {
+        self.cm_id = Some(Arc::new(cm_id));
+        self
     }
 
     pub fn qp_num(&self) -> u32 {
         self.queue_pair.qp_num
     }
 
+    #[cfg(test)]
+    pub(crate) fn queue_pair(&self) -> &Arc<QueuePair> {
+        &self.queue_pair
+    }
+
     pub fn endpoint(&self) -> Endpoint {
         Endpoint {
             qp_num: self.queue_pair.qp_num,
@@ -25,6 +176,16 @@ impl Socket {
         }
     }
 
+    /// Queries the attributes of the underlying queue pair, for diagnostics.
+    pub fn query(&self) -> Result<QueuePairAttr> {
+        self.queue_pair.query()
+    }
+
+    /// Returns true if the underlying queue pair has moved to the error state.
+    pub fn is_error(&self) -> Result<bool> {
+        Ok(self.queue_pair.state()? == QueuePairState::Error)
+    }
+
     pub fn init(&self, endpoint: Endpoint) -> Result<()> {
         self.queue_pair.init(1, 0)?;
         self.queue_pair.ready_to_recv(&endpoint)?;
@@ -32,7 +193,23 @@ impl Socket {
         Ok(())
     }
 
+    /// Returns the sends allowed by the peer, if the socket uses flow control.
+    pub fn send_credits(&self) -> Option<u32> {
+        self.outstanding.credits.as_ref().map(Credits::send_credits)
+    }
+
+    /// Returns true if the completion is a receive consumed by a credit update of the peer.
+    pub fn is_credit_update(wc: &verbs::ibv_wc) -> bool {
+        wc.imm_data()
+            .is_some_and(|imm_data| imm_data & CREDIT_UPDATE != 0)
+    }
+
     pub fn post_recv(&self, wr_id: u64, buf: Buffer) -> Result<()> {
+        self.try_post_recv(wr_id, buf).map_err(|(err, _)| err)
+    }
+
+    /// Posts a receive, handing the buffer back if it fails before the buffer is posted.
+    pub(crate) fn try_post_recv(&self, wr_id: u64, buf: Buffer) -> PostResult {
         let mut recv_sge = verbs::ibv_sge {
             addr: buf.as_ptr() as _,
             length: buf.len() as _,
@@ -45,13 +222,57 @@ impl Socket {
             next: std::ptr::null_mut(),
         };
 
-        match self.queue_pair.post_recv(&mut recv_wr) {
-            0 => Ok(()),
-            _ => Err(ErrorKind::IBPostRecvFailed.with_errno()),
+        if let Err(buf) = self.outstanding.insert(wr_id, buf) {
+            let err = Error::new(
+                ErrorKind::IBPostRecvFailed,
+                format!("wr_id {wr_id} is outstanding"),
+            );
+            return Err((err, Some(buf)));
+        }
+        if self.queue_pair.post_recv(&mut recv_wr) != 0 {
+            let err = ErrorKind::IBPostRecvFailed.with_errno();
+            return Err((err, self.outstanding.remove(wr_id)));
+        }
+
+        let update = self
+            .outstanding
+            .credits
+            .as_ref()
+            .and_then(Credits::on_post_recv);
+        if let Some(credits) = update {
+            self.post_credit_update(credits);
+        }
+        Ok(())
+    }
+
+    /// Advertises credits in a send without payload. The receive is posted already, so a failure only
+    /// leaves the credits unadvertised, to go with the next send or update.
+    fn post_credit_update(&self, advertised: u32) {
+        let mut send_wr = verbs::ibv_send_wr {
+            wr_id: CREDIT_UPDATE_WR_ID,
+            opcode: verbs::ibv_wr_opcode::IBV_WR_SEND_WITH_IMM,
+            send_flags: verbs::ibv_send_flags::IBV_SEND_SIGNALED.0,
+            ..Default::default()
+        };
+        send_wr.__bindgen_anon_1.imm_data = (CREDIT_UPDATE | advertised).to_be();
+        if self.queue_pair.post_send(&mut send_wr) != 0 {
+            let err = ErrorKind::IBPostSendFailed.with_errno();
+            tracing::warn!("post credit update failed: {}", err);
+            if let Some(credits) = &self.outstanding.credits {
+                credits.release(advertised);
+            }
         }
     }
 
+    /// Posts a send. With flow control enabled, it blocks until the peer has a receive posted, up to
+    /// `send_timeout`, so it must not be called from a thread polling the completion queues or from an
+    /// async task, which would stall the completions returning the credits.
     pub fn post_send(&self, wr_id: u64, buf: Buffer) -> Result<()> {
+        self.try_post_send(wr_id, buf).map_err(|(err, _)| err)
+    }
+
+    /// Posts a send, handing the buffer back if it fails.
+    pub(crate) fn try_post_send(&self, wr_id: u64, buf: Buffer) -> PostResult {
         let mut send_sge = verbs::ibv_sge {
             addr: buf.as_ptr() as _,
             length: buf.len() as _,
@@ -66,9 +287,391 @@ impl Socket {
             ..Default::default()
         };
 
+        let credits = self.outstanding.credits.as_ref();
+        if credits.is_some() && wr_id == CREDIT_UPDATE_WR_ID {
+            let err = Error::new(
+                ErrorKind::IBPostSendFailed,
+                format!("wr_id {wr_id} is reserved for credit updates"),
+            );
+            return Err((err, Some(buf)));
+        }
+        if let Err(buf) = self.outstanding.insert(wr_id, buf) {
+            let err = Error::new(
+                ErrorKind::IBPostSendFailed,
+                format!("wr_id {wr_id} is outstanding"),
+            );
+            return Err((err, Some(buf)));
+        }
+        let advertised = match credits.map(Credits::acquire).transpose() {
+            Ok(advertised) => advertised,
+            Err(err) => return Err((err, self.outstanding.remove(wr_id))),
+        };
+        if let Some(advertised) = advertised {
+            send_wr.opcode = verbs::ibv_wr_opcode::IBV_WR_SEND_WITH_IMM;
+            send_wr.__bindgen_anon_1.imm_data = advertised.to_be();
+        }
         match self.queue_pair.post_send(&mut send_wr) {
             0 => Ok(()),
+            _ => {
+                let err = ErrorKind::IBPostSendFailed.with_errno();
+                let buf = self.outstanding.remove(wr_id);
+                if let (Some(credits), Some(advertised)) = (credits, advertised) {
+                    credits.release(advertised);
+                }
+                Err((err, buf))
+            }
+        }
+    }
+
+    /// Binds the memory window to `range` of the buffer, granting the peer `access` to it, a
+    /// combination of remote `verbs::ibv_access_flags`. Returns the rkey the peer accesses it with.
+    /// The buffer must be registered with `AccessFlags::memory_windows`, and is held by the window until
+    /// it is invalidated.
+    pub fn bind_window(
+        &self,
+        wr_id: u64,
+        window: &MemoryWindow,
+        buf: Buffer,
+        range: Range<usize>,
+        access: u32,
+    ) -> Result<u32> {
+        let device = self.queue_pair.device();
+        if window.device_index() != device.index() {
+            return Err(Error::new(
+                ErrorKind::IBBindMemoryWindowFail,
+                format!(
+                    "window on device {} bound on device {}",
+                    window.device_index(),
+                    device.index()
+                ),
+            ));
+        }
+        if range.start >= range.end || range.end > buf.len() {
+            return Err(Error::new(
+                ErrorKind::IBBindMemoryWindowFail,
+                format!("range {range:?} exceeds buffer of {} bytes", buf.len()),
+            ));
+        }
+
+        let mut state = window.state();
+        if state.bound.is_some() {
+            return Err(Error::new(
+                ErrorKind::IBBindMemoryWindowFail,
+                "window is bound already".to_string(),
+            ));
+        }
+        let rkey = verbs::ibv_inc_rkey(state.rkey);
+        let mut send_wr = verbs::ibv_send_wr {
+            wr_id,
+            opcode: verbs::ibv_wr_opcode::IBV_WR_BIND_MW,
+            send_flags: verbs::ibv_send_flags::IBV_SEND_SIGNALED.0,
+            ..Default::default()
+        };
+        send_wr.__bindgen_anon_2.bind_mw.mw = window.as_ptr();
+        send_wr.__bindgen_anon_2.bind_mw.rkey = rkey;
+        send_wr.__bindgen_anon_2.bind_mw.bind_info = verbs::ibv_mw_bind_info {
+            mr: buf.memory_region(device),
+            addr: buf.as_ptr() as u64 + range.start as u64,
+            length: (range.end - range.start) as u64,
+            mw_access_flags: access,
+        };
+        match self.queue_pair.post_send(&mut send_wr) {
+            0 => {
+                state.rkey = rkey;
+                state.bound = Some(buf);
+                Ok(rkey)
+            }
             _ => Err(ErrorKind::IBPostSendFailed.with_errno()),
         }
     }
+
+    /// Revokes the access granted by the window. Its buffer is released once the invalidation
+    /// completes, through `complete` or `CompQueues::take_buffer`.
+    pub fn invalidate_window(&self, wr_id: u64, window: &MemoryWindow) -> Result<()> {
+        let mut state = window.state();
+        let Some(buf) = state.bound.take() else {
+            return Err(Error::new(
+                ErrorKind::IBBindMemoryWindowFail,
+                "window is not bound".to_string(),
+            ));
+        };
+        let mut send_wr = verbs::ibv_send_wr {
+            wr_id,
+            opcode: verbs::ibv_wr_opcode::IBV_WR_LOCAL_INV,
+            send_flags: verbs::ibv_send_flags::IBV_SEND_SIGNALED.0,
+            ..Default::default()
+        };
+        send_wr.__bindgen_anon_1.invalidate_rkey = state.rkey;
+
+        if let Err(buf) = self.outstanding.insert(wr_id, buf) {
+            state.bound = Some(buf);
+            return Err(Error::new(
+                ErrorKind::IBPostSendFailed,
+                format!("wr_id {wr_id} is outstanding"),
+            ));
+        }
+        match self.queue_pair.post_send(&mut send_wr) {
+            0 => Ok(()),
+            _ => {
+                let err = ErrorKind::IBPostSendFailed.with_errno();
+                state.bound = self.outstanding.remove(wr_id);
+                Err(err)
+            }
+        }
+    }
+
+    /// Returns the number of posted work requests which have not completed yet.
+    pub fn outstanding(&self) -> usize {
+        self.outstanding.len()
+    }
+
+    /// Takes the buffer of a work request completed on this socket.
+    pub fn complete(&self, wc: &verbs::ibv_wc) -> Option<Buffer> {
+        debug_assert_eq!(wc.qp_num, self.qp_num());
+        self.outstanding.complete(wc)
+    }
+
+    /// Moves the queue pair into the error state, so all outstanding work requests are flushed.
+    pub fn shutdown(&self) -> Result<()> {
+        self.queue_pair.set_error()
+    }
+
+    /// Shuts down the socket, waits until every outstanding work request has flushed through the
+    /// completion queue, which must be polled meanwhile, e.g. by an event loop, and destroys the
+    /// queue pair.
+    ///
+    /// Fails without shutting down if the queue pair is still shared, e.g. by clones of the socket.
+    /// The queue pair is destroyed even if draining times out.
+    pub fn close(self, timeout: Duration) -> Result<()> {
+        let references = Arc::strong_count(&self.queue_pair);
+        if references > 1 {
+            return Err(Error::new(
+                ErrorKind::InvalidArgument,
+                format!("queue pair is shared by {references} references"),
+            ));
+        }
+
+        self.shutdown()?;
+        match self.outstanding.wait_drained(timeout) {
+            0 => Ok(()),
+            pending => Err(Error::new(
+                ErrorKind::IBDrainQueuePairTimeout,
+                format!("{pending} work requests outstanding"),
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{connect, socket_pair};
+    use crate::*;
+
+    #[test]
+    fn test_socket_mock() {
+        let devices = MockVerbs::devices(1).unwrap();
+        let comp_queues = CompQueues::create(&devices, 128).unwrap();
+        let (socket_a, socket_b) =
+            socket_pair(&devices, &comp_queues, &comp_queues, Socket::create);
+
+        // posting a receive before the queue pair is initialized fails.
+        let buffer_pool = BufferPool::create(4096, 4, &devices).unwrap();
+        assert!(socket_a
+            .post_recv(0, buffer_pool.allocate().unwrap())
+            .is_err());
+
+        connect(&socket_a, &socket_b);
+        assert!(!socket_a.is_error().unwrap());
+
+        socket_b
+            .post_recv(1, buffer_pool.allocate().unwrap())
+            .unwrap();
+        socket_a
+            .post_send(2, buffer_pool.allocate().unwrap())
+            .unwrap();
+        let mut wcs = vec![verbs::ibv_wc::default(); 128];
+        let comp = comp_queues.poll_cq(&mut wcs).unwrap();
+        let mut wr_ids = comp.iter().map(|wc| wc.wr_id).collect::<Vec<_>>();
+        wr_ids.sort();
+        assert_eq!(wr_ids, [1, 2]);
+        assert!(comp.iter().all(|wc| wc.result() == Ok(4096)));
+        assert_eq!(socket_a.outstanding() + socket_b.outstanding(), 2);
+        for wc in comp.iter() {
+            assert!(comp_queues.take_buffer(0, wc).is_some());
+        }
+        assert_eq!(buffer_pool.stats().in_use, 0);
+
+        // wr_id of an outstanding work request can not be reused.
+        socket_b
+            .post_recv(3, buffer_pool.allocate().unwrap())
+            .unwrap();
+        assert!(socket_b
+            .post_recv(3, buffer_pool.allocate().unwrap())
+            .is_err());
+        assert_eq!(buffer_pool.stats().in_use, 1);
+    }
+
+    #[test]
+    fn test_socket_close() {
+        let devices = MockVerbs::devices(1).unwrap();
+        let buffer_pool = BufferPool::create(4096, 8, &devices).unwrap();
+        let mut event_loop = EventLoop::create(&devices, 128).unwrap();
+        let comp_queues = CompQueues::create(&devices, 128).unwrap();
+        let (socket_a, socket_b) = socket_pair(
+            &devices,
+            event_loop.comp_queues(),
+            &comp_queues,
+            Socket::create,
+        );
+        connect(&socket_a, &socket_b);
+
+        for wr_id in 0..4 {
+            socket_a
+                .post_recv(wr_id, buffer_pool.allocate().unwrap())
+                .unwrap();
+            socket_b
+                .post_recv(wr_id, buffer_pool.allocate().unwrap())
+                .unwrap();
+        }
+        assert_eq!(buffer_pool.stats().in_use, 8);
+
+        // clones keep the queue pair alive.
+        let err = socket_a.clone().close(Duration::from_secs(5)).unwrap_err();
+        assert_eq!(err.kind, ErrorKind::InvalidArgument);
+        assert!(!socket_a.is_error().unwrap());
+
+        // flushed receives are released by the event loop.
+        let queue_pair = Arc::downgrade(socket_a.queue_pair());
+        socket_a.close(Duration::from_secs(5)).unwrap();
+        assert!(queue_pair.upgrade().is_none());
+        event_loop.stop_and_join();
+        assert_eq!(buffer_pool.stats().in_use, 4);
+
+        // nobody polls the completion queue of socket b.
+        let queue_pair = Arc::downgrade(socket_b.queue_pair());
+        let err = socket_b.close(Duration::from_millis(10)).unwrap_err();
+        assert_eq!(err.kind, ErrorKind::IBDrainQueuePairTimeout);
+        assert!(queue_pair.upgrade().is_none());
+        assert_eq!(buffer_pool.stats().in_use, 0);
+    }
+
+    #[test]
+    fn test_socket_flow_control() {
+        let devices = MockVerbs::devices(1).unwrap();
+        let config = FlowControlConfig {
+            initial_credits: 2,
+            update_threshold: 1,
+            send_timeout: Duration::from_millis(10),
+        };
+        let comp_queues = CompQueues::create(&devices, 128).unwrap();
+        let (socket_a, socket_b) =
+            socket_pair(&devices, &comp_queues, &comp_queues, |queue_pair| {
+                Socket::create_with_flow_control(queue_pair, config)
+            });
+        connect(&socket_a, &socket_b);
+
+        let buffer_pool = BufferPool::create(4096, 8, &devices).unwrap();
+        for wr_id in 0..2 {
+            socket_a
+                .post_recv(wr_id, buffer_pool.allocate().unwrap())
+                .unwrap();
+            socket_b
+                .post_recv(wr_id, buffer_pool.allocate().unwrap())
+                .unwrap();
+        }
+        assert_eq!(socket_a.send_credits(), Some(2));
+
+        // the last credit is kept for advertising receives.
+        socket_a
+            .post_send(10, buffer_pool.allocate().unwrap())
+            .unwrap();
+        assert_eq!(socket_a.send_credits(), Some(1));
+        let err = socket_a
+            .post_send(11, buffer_pool.allocate().unwrap())
+            .unwrap_err();
+        assert_eq!(err.kind, ErrorKind::IBWaitSendCreditTimeout);
+
+        let mut wcs = vec![verbs::ibv_wc::default(); 128];
+        let comp = comp_queues.poll_cq(&mut wcs).unwrap();
+        assert_eq!(comp.len(), 2);
+        let mut recv = None;
+        for wc in comp.iter() {
+            assert_eq!(wc.result(), Ok(4096));
+            assert!(!Socket::is_credit_update(wc));
+            let buf = comp_queues.take_buffer(0, wc).unwrap();
+            if wc.qp_num == socket_b.qp_num() {
+                recv = Some(buf);
+            }
+        }
+
+        // reposting the consumed receive advertises it right away.
+        socket_b.post_recv(2, recv.unwrap()).unwrap();
+        assert_eq!(socket_b.send_credits(), Some(1));
+        let comp = comp_queues.poll_cq(&mut wcs).unwrap();
+        assert_eq!(comp.len(), 2);
+        for wc in comp.iter() {
+            assert!(wc.result().is_ok());
+            if wc.qp_num == socket_a.qp_num() {
+                assert!(Socket::is_credit_update(wc));
+                assert_eq!(wc.byte_len, 0);
+            } else {
+                assert_eq!(wc.wr_id, CREDIT_UPDATE_WR_ID);
+            }
+            drop(comp_queues.take_buffer(0, wc));
+        }
+        assert_eq!(socket_a.send_credits(), Some(2));
+        socket_a
+            .post_send(12, buffer_pool.allocate().unwrap())
+            .unwrap();
+    }
+
+    #[test]
+    fn test_socket_credit_update_failed() {
+        let devices = MockVerbs::devices(1).unwrap();
+        let config = FlowControlConfig {
+            initial_credits: 1,
+            update_threshold: 1,
+            send_timeout: Duration::from_millis(10),
+        };
+        let comp_queues = CompQueues::create(&devices, 128).unwrap();
+        let (socket_a, socket_b) =
+            socket_pair(&devices, &comp_queues, &comp_queues, |queue_pair| {
+                Socket::create_with_flow_control(queue_pair, config)
+            });
+        let queue_pair_a = socket_a.queue_pair();
+        socket_b.init(socket_a.endpoint()).unwrap();
+        queue_pair_a.init(1, 0).unwrap();
+        queue_pair_a.ready_to_recv(&socket_b.endpoint()).unwrap();
+
+        let buffer_pool = BufferPool::create(4096, 4, &devices).unwrap();
+        socket_b
+            .post_recv(0, buffer_pool.allocate().unwrap())
+            .unwrap();
+        socket_a
+            .post_recv(0, buffer_pool.allocate().unwrap())
+            .unwrap();
+
+        // the queue pair cannot send yet, so the receive is posted and its credit kept.
+        socket_a
+            .post_recv(1, buffer_pool.allocate().unwrap())
+            .unwrap();
+        assert_eq!(socket_a.outstanding(), 2);
+        assert_eq!(socket_a.send_credits(), Some(1));
+
+        // the next receive advertises both.
+        queue_pair_a.ready_to_send().unwrap();
+        socket_a
+            .post_recv(2, buffer_pool.allocate().unwrap())
+            .unwrap();
+        assert_eq!(socket_a.send_credits(), Some(0));
+        let mut wcs = vec![verbs::ibv_wc::default(); 128];
+        let comp = comp_queues.poll_cq(&mut wcs).unwrap();
+        let update = comp
+            .iter()
+            .find(|wc| wc.qp_num == socket_b.qp_num())
+            .unwrap();
+        assert!(Socket::is_credit_update(update));
+        assert_eq!(update.imm_data().unwrap() & !CREDIT_UPDATE, 2);
+    }
 }
@@ -0,0 +1,246 @@
+use super::{socket::OutstandingWork, AddressHandle, Endpoint, QueuePair};
+use crate::{verbs, Buffer, BufferMut, Error, ErrorKind, Result};
+use std::sync::Arc;
+
+/// The size of the global routing header placed in front of every unreliable datagram receive.
+pub const GRH_SIZE: usize = 40;
+
+/// A socket over an unreliable datagram queue pair.
+/// It can talk to many peers without connection setup, which suits service discovery and heartbeats.
+/// Posted buffers are held until their work requests complete, and released through
+/// `complete` or `CompQueues::take_buffer` by whoever polls the completion queue.
+#[derive(Debug, Clone)]
+pub struct UdSocket {
+    // dropped first, so the queue pair is destroyed before the buffers it may access are released.
+    queue_pair: Arc<QueuePair>,
+    outstanding: Arc<OutstandingWork>,
+    qkey: u32,
+}
+
+impl UdSocket {
+    pub fn create(queue_pair: Arc<QueuePair>, qkey: u32) -> Result<Self> {
+        if queue_pair.qp_type != verbs::ibv_qp_type::IBV_QPT_UD {
+            return Err(Error::new(
+                ErrorKind::IBCreateQueuePairFail,
+                format!(
+                    "unreliable datagram socket over a {:?} queue pair",
+                    queue_pair.qp_type
+                ),
+            ));
+        }
+        let outstanding = Arc::new(OutstandingWork::default());
+        queue_pair.comp_queues().register(
+            queue_pair.device_index(),
+            queue_pair.qp_num,
+            &outstanding,
+        );
+        Ok(UdSocket {
+            queue_pair,
+            outstanding,
+            qkey,
+        })
+    }
+
+    pub fn qp_num(&self) -> u32 {
+        self.queue_pair.qp_num
+    }
+
+    pub fn qkey(&self) -> u32 {
+        self.qkey
+    }
+
+    pub fn endpoint(&self) -> Endpoint {
+        Endpoint {
+            qp_num: self.queue_pair.qp_num,
+            lid: 0,
+            gid: self.queue_pair.device().info().ports[0].gids[1].1,
+        }
+    }
+
+    pub fn init(&self) -> Result<()> {
+        self.queue_pair.init_ud(1, 0, self.qkey)
+    }
+
+    /// Creates an address handle to the remote endpoint, which can be reused for all sends to it.
+    pub fn address_handle(&self, remote: &Endpoint) -> Result<Arc<AddressHandle>> {
+        AddressHandle::create(
+            self.queue_pair.devices(),
+            self.queue_pair.device_index(),
+            1,
+            1,
+            remote,
+        )
+        .map(Arc::new)
+    }
+
+    /// Posts a receive. The buffer needs room for `GRH_SIZE` extra bytes in front of the payload.
+    pub fn post_recv(&self, wr_id: u64, buf: Buffer) -> Result<()> {
+        let mut recv_sge = verbs::ibv_sge {
+            addr: buf.as_ptr() as _,
+            length: buf.len() as _,
+            lkey: buf.lkey(self.queue_pair.device()),
+        };
+        let mut recv_wr = verbs::ibv_recv_wr {
+            wr_id,
+            sg_list: &mut recv_sge as *mut _,
+            num_sge: 1,
+            next: std::ptr::null_mut(),
+        };
+
+        if self.outstanding.insert(wr_id, buf).is_err() {
+            return Err(Error::new(
+                ErrorKind::IBPostRecvFailed,
+                format!("wr_id {wr_id} is outstanding"),
+            ));
+        }
+        match self.queue_pair.post_recv(&mut recv_wr) {
+            0 => Ok(()),
+            _ => {
+                let err = ErrorKind::IBPostRecvFailed.with_errno();
+                self.outstanding.remove(wr_id);
+                Err(err)
+            }
+        }
+    }
+
+    /// Sends the written part of the buffer to the remote queue pair.
+    /// The buffer and the address handle are held until the send completes.
+    pub fn post_send(
+        &self,
+        wr_id: u64,
+        buf: BufferMut,
+        ah: &Arc<AddressHandle>,
+        remote_qpn: u32,
+        remote_qkey: u32,
+    ) -> Result<()> {
+        let mut send_sge = verbs::ibv_sge {
+            addr: buf.as_ptr() as _,
+            length: buf.len() as _,
+            lkey: buf.lkey(self.queue_pair.device()),
+        };
+        let mut send_wr = verbs::ibv_send_wr {
+            wr_id,
+            sg_list: &mut send_sge as *mut _,
+            num_sge: 1,
+            opcode: verbs::ibv_wr_opcode::IBV_WR_SEND,
+            send_flags: verbs::ibv_send_flags::IBV_SEND_SIGNALED.0,
+            ..Default::default()
+        };
+        send_wr.wr.ud.ah = ah.ah_ptr();
+        send_wr.wr.ud.remote_qpn = remote_qpn;
+        send_wr.wr.ud.remote_qkey = remote_qkey;
+
+        let ah = Some(ah.clone());
+        if self
+            .outstanding
+            .insert_with_ah(wr_id, buf.into_inner(), ah)
+            .is_err()
+        {
+            return Err(Error::new(
+                ErrorKind::IBPostSendFailed,
+                format!("wr_id {wr_id} is outstanding"),
+            ));
+        }
+        match self.queue_pair.post_send(&mut send_wr) {
+            0 => Ok(()),
+            _ => {
+                let err = ErrorKind::IBPostSendFailed.with_errno();
+                self.outstanding.remove(wr_id);
+                Err(err)
+            }
+        }
+    }
+
+    /// Returns the number of posted work requests which have not completed yet.
+    pub fn outstanding(&self) -> usize {
+        self.outstanding.len()
+    }
+
+    /// Takes the buffer of a work request completed on this socket.
+    pub fn complete(&self, wc: &verbs::ibv_wc) -> Option<Buffer> {
+        debug_assert_eq!(wc.qp_num, self.qp_num());
+        self.outstanding.complete(wc)
+    }
+
+    /// Returns the received payload with the global routing header stripped.
+    /// It is empty if the completion is too short to carry the header.
+    pub fn payload<'a>(buf: &'a Buffer, wc: &verbs::ibv_wc) -> &'a [u8] {
+        let len = (wc.byte_len as usize).min(buf.len());
+        buf.get(GRH_SIZE..len).unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::cap;
+    use crate::*;
+    use bytes::BufMut;
+
+    #[test]
+    fn test_ud_socket_send_recv() {
+        const QKEY: u32 = 0x11111111;
+        let devices = Devices::availables().unwrap();
+
+        let comp_queues_a = CompQueues::create(&devices, 128).unwrap();
+        let queue_pair_a = QueuePair::create_ud(&devices, 0, &comp_queues_a, cap()).unwrap();
+        let socket_a = UdSocket::create(Arc::new(queue_pair_a), QKEY).unwrap();
+        let comp_queues_b = CompQueues::create(&devices, 128).unwrap();
+        let queue_pair_b = QueuePair::create_ud(&devices, 0, &comp_queues_b, cap()).unwrap();
+        let socket_b = UdSocket::create(Arc::new(queue_pair_b), QKEY).unwrap();
+        socket_a.init().unwrap();
+        socket_b.init().unwrap();
+
+        let buffer_pool = BufferPool::create(1024, 4, &devices).unwrap();
+        socket_b
+            .post_recv(1, buffer_pool.allocate().unwrap())
+            .unwrap();
+
+        let mut send_buf = BufferMut::from(buffer_pool.allocate().unwrap());
+        send_buf.put_slice(b"heartbeat");
+        let ah = socket_a.address_handle(&socket_b.endpoint()).unwrap();
+        println!("{:#?}", ah);
+        let send_len = send_buf.len();
+        socket_a
+            .post_send(2, send_buf, &ah, socket_b.qp_num(), QKEY)
+            .unwrap();
+        assert_eq!(socket_a.outstanding(), 1);
+
+        std::thread::sleep(std::time::Duration::from_millis(100));
+        let mut wcs = vec![verbs::ibv_wc::default(); 128];
+        let comp_a = comp_queues_a.poll_cq(&mut wcs).unwrap();
+        assert_eq!(comp_a.len(), 1);
+        assert_eq!(comp_a[0].wr_id, 2);
+        assert_eq!(comp_a[0].status, verbs::ibv_wc_status::IBV_WC_SUCCESS);
+        assert!(socket_a.complete(&comp_a[0]).is_some());
+        assert_eq!(socket_a.outstanding(), 0);
+
+        let comp_b = comp_queues_b.poll_cq(&mut wcs).unwrap();
+        assert_eq!(comp_b.len(), 1);
+        assert_eq!(comp_b[0].wr_id, 1);
+        assert_eq!(comp_b[0].status, verbs::ibv_wc_status::IBV_WC_SUCCESS);
+        assert_eq!(comp_b[0].src_qp, socket_a.qp_num());
+        assert_eq!(comp_b[0].byte_len as usize, GRH_SIZE + send_len);
+        let recv_buf = comp_queues_b.take_buffer(0, &comp_b[0]).unwrap();
+        assert_eq!(UdSocket::payload(&recv_buf, &comp_b[0]), b"heartbeat");
+        assert_eq!(buffer_pool.stats().in_use, 1);
+    }
+
+    #[test]
+    fn test_ud_socket_checks() {
+        let devices = MockVerbs::devices(1).unwrap();
+        let comp_queues = CompQueues::create(&devices, 128).unwrap();
+        let queue_pair = QueuePair::create(&devices, 0, &comp_queues, cap()).unwrap();
+        let err = UdSocket::create(Arc::new(queue_pair), 1).unwrap_err();
+        assert_eq!(err.kind, ErrorKind::IBCreateQueuePairFail);
+
+        // a block shorter than the global routing header has no payload.
+        let buffer_pool = BufferPool::create(16, 1, &devices).unwrap();
+        let buf = buffer_pool.allocate().unwrap();
+        let wc = verbs::ibv_wc {
+            byte_len: 16,
+            ..Default::default()
+        };
+        assert!(UdSocket::payload(&buf, &wc).is_empty());
+    }
+}
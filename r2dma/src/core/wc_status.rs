@@ -0,0 +1,160 @@
+use crate::{verbs, ErrorKind, Result};
+use serde::{Deserialize, Serialize};
+
+/// The status of a work completion.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum WcStatus {
+    Success,
+    LocalLengthError,
+    LocalQueuePairOperationError,
+    LocalEecOperationError,
+    LocalProtectionError,
+    WorkRequestFlushed,
+    MemoryWindowBindError,
+    BadResponseError,
+    LocalAccessError,
+    RemoteInvalidRequestError,
+    RemoteAccessError,
+    RemoteOperationError,
+    RetryExceeded,
+    RnrRetryExceeded,
+    LocalRddViolationError,
+    RemoteInvalidRdRequestError,
+    RemoteAbortError,
+    InvalidEecnError,
+    InvalidEecStateError,
+    FatalError,
+    ResponseTimeoutError,
+    GeneralError,
+    TagMatchingError,
+    TagMatchingRendezvousIncomplete,
+    Unknown(u32),
+}
+
+impl From<verbs::ibv_wc_status> for WcStatus {
+    fn from(status: verbs::ibv_wc_status) -> Self {
+        use verbs::ibv_wc_status;
+        match status {
+            ibv_wc_status::IBV_WC_SUCCESS => Self::Success,
+            ibv_wc_status::IBV_WC_LOC_LEN_ERR => Self::LocalLengthError,
+            ibv_wc_status::IBV_WC_LOC_QP_OP_ERR => Self::LocalQueuePairOperationError,
+            ibv_wc_status::IBV_WC_LOC_EEC_OP_ERR => Self::LocalEecOperationError,
+            ibv_wc_status::IBV_WC_LOC_PROT_ERR => Self::LocalProtectionError,
+            ibv_wc_status::IBV_WC_WR_FLUSH_ERR => Self::WorkRequestFlushed,
+            ibv_wc_status::IBV_WC_MW_BIND_ERR => Self::MemoryWindowBindError,
+            ibv_wc_status::IBV_WC_BAD_RESP_ERR => Self::BadResponseError,
+            ibv_wc_status::IBV_WC_LOC_ACCESS_ERR => Self::LocalAccessError,
+            ibv_wc_status::IBV_WC_REM_INV_REQ_ERR => Self::RemoteInvalidRequestError,
+            ibv_wc_status::IBV_WC_REM_ACCESS_ERR => Self::RemoteAccessError,
+            ibv_wc_status::IBV_WC_REM_OP_ERR => Self::RemoteOperationError,
+            ibv_wc_status::IBV_WC_RETRY_EXC_ERR => Self::RetryExceeded,
+            ibv_wc_status::IBV_WC_RNR_RETRY_EXC_ERR => Self::RnrRetryExceeded,
+            ibv_wc_status::IBV_WC_LOC_RDD_VIOL_ERR => Self::LocalRddViolationError,
+            ibv_wc_status::IBV_WC_REM_INV_RD_REQ_ERR => Self::RemoteInvalidRdRequestError,
+            ibv_wc_status::IBV_WC_REM_ABORT_ERR => Self::RemoteAbortError,
+            ibv_wc_status::IBV_WC_INV_EECN_ERR => Self::InvalidEecnError,
+            ibv_wc_status::IBV_WC_INV_EEC_STATE_ERR => Self::InvalidEecStateError,
+            ibv_wc_status::IBV_WC_FATAL_ERR => Self::FatalError,
+            ibv_wc_status::IBV_WC_RESP_TIMEOUT_ERR => Self::ResponseTimeoutError,
+            ibv_wc_status::IBV_WC_GENERAL_ERR => Self::GeneralError,
+            ibv_wc_status::IBV_WC_TM_ERR => Self::TagMatchingError,
+            ibv_wc_status::IBV_WC_TM_RNDV_INCOMPLETE => Self::TagMatchingRendezvousIncomplete,
+            _ => Self::Unknown(status.0),
+        }
+    }
+}
+
+impl WcStatus {
+    pub fn is_success(&self) -> bool {
+        *self == Self::Success
+    }
+
+    /// Returns true if the work request was not executed because the queue pair had entered the error state.
+    pub fn is_flush(&self) -> bool {
+        *self == Self::WorkRequestFlushed
+    }
+
+    /// Returns true if re-issuing the operation, possibly on a reconnected queue pair, may succeed.
+    /// Errors caused by the request itself, e.g. bad lengths or keys, will fail again.
+    pub fn is_retryable(&self) -> bool {
+        matches!(
+            self,
+            Self::WorkRequestFlushed
+                | Self::RetryExceeded
+                | Self::RnrRetryExceeded
+                | Self::ResponseTimeoutError
+        )
+    }
+
+    /// Returns true if the completion moves a reliable connected queue pair into the error state.
+    /// Every error completion does, so the queue pair must be recreated to continue.
+    pub fn is_fatal_for_qp(&self) -> bool {
+        !self.is_success()
+    }
+}
+
+impl verbs::ibv_wc {
+    /// Returns the typed status of the work completion.
+    pub fn wc_status(&self) -> WcStatus {
+        self.status.into()
+    }
+
+    /// Returns the number of bytes transferred, or the typed error of the work completion.
+    pub fn result(&self) -> Result<u32> {
+        match self.wc_status() {
+            WcStatus::Success => Ok(self.byte_len),
+            status => Err(ErrorKind::IBWorkCompletion {
+                status,
+                vendor_err: self.vendor_err,
+            }
+            .into()),
+        }
+    }
+
+    /// Returns the immediate data in host byte order, if the completion carries any.
+    pub fn imm_data(&self) -> Option<u32> {
+        if self.wc_flags & verbs::ibv_wc_flags::IBV_WC_WITH_IMM.0 != 0 {
+            Some(u32::from_be(unsafe { self.__bindgen_anon_1.imm_data }))
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_wc_status() {
+        let mut wc = verbs::ibv_wc {
+            status: verbs::ibv_wc_status::IBV_WC_SUCCESS,
+            byte_len: 16,
+            ..Default::default()
+        };
+        assert_eq!(wc.wc_status(), WcStatus::Success);
+        assert_eq!(wc.result().unwrap(), 16);
+
+        wc.status = verbs::ibv_wc_status::IBV_WC_RETRY_EXC_ERR;
+        wc.vendor_err = 0x81;
+        let status = wc.wc_status();
+        assert!(status.is_retryable());
+        assert!(status.is_fatal_for_qp());
+        assert_eq!(
+            wc.result().unwrap_err().kind,
+            ErrorKind::IBWorkCompletion {
+                status: WcStatus::RetryExceeded,
+                vendor_err: 0x81,
+            }
+        );
+
+        let status = WcStatus::from(verbs::ibv_wc_status::IBV_WC_REM_ACCESS_ERR);
+        assert_eq!(status, WcStatus::RemoteAccessError);
+        assert!(!status.is_retryable());
+        assert!(WcStatus::from(verbs::ibv_wc_status::IBV_WC_WR_FLUSH_ERR).is_flush());
+
+        let status = WcStatus::from(verbs::ibv_wc_status(100));
+        assert_eq!(status, WcStatus::Unknown(100));
+        assert!(status.is_fatal_for_qp());
+    }
+}
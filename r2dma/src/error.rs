@@ -1,8 +1,11 @@
+use crate::WcStatus;
 use serde::{Deserialize, Serialize};
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
 pub enum ErrorKind {
     AllocMemoryFailed,
+    InvalidArgument,
+    SerializeFail,
     IBGetDeviceListFail,
     IBDeviceNotFound,
     IBOpenDeviceFail,
@@ -10,6 +13,8 @@ pub enum ErrorKind {
     IBQueryGidFail,
     IBQueryGidTypeFail,
     IBQueryPortFail,
+    IBReadCountersFail,
+    IBSetAsyncFdNonBlockFail,
     IBAllocPDFail,
     IBCreateCompChannelFail,
     IBSetCompChannelNonBlockFail,
@@ -18,10 +23,47 @@ pub enum ErrorKind {
     IBReqNotifyCompQueueFail,
     IBPollCompQueueFail,
     IBRegMemoryRegionFail,
+    IBAllocMemoryWindowFail,
+    IBBindMemoryWindowFail,
     IBCreateQueuePairFail,
     IBModifyQueuePairFail,
+    IBQueryQueuePairFail,
+    IBDrainQueuePairTimeout,
+    IBWaitSendCreditTimeout,
+    IBCreateAddressHandleFail,
     IBPostRecvFailed,
     IBPostSendFailed,
+    IBCreateSharedRecvQueueFail,
+    IBModifySharedRecvQueueFail,
+    IBPostSharedRecvFailed,
+    IBExceedDeviceLimit {
+        field: String,
+        requested: u64,
+        limit: u64,
+    },
+    IBAtomicNotSupported,
+    IBWorkCompletion {
+        status: WcStatus,
+        vendor_err: u32,
+    },
+    CMCreateIdFail,
+    CMBindAddrFail,
+    CMListenFail,
+    CMGetRequestFail,
+    CMResolveAddrFail,
+    CMResolveRouteFail,
+    CMInitQueuePairAttrFail,
+    CMConnectFail,
+    CMAcceptFail,
+    CMEstablishFail,
+    CMDeviceNotFound,
+    CMInvalidPrivateData,
+    HandshakeIOFail,
+    HandshakeInvalidMessage,
+    HandshakeVersionMismatch {
+        local: u16,
+        remote: u16,
+    },
     #[serde(untagged)]
     Unknown(String),
 }
@@ -100,5 +142,27 @@ mod tests {
 
         let err: Error = ErrorKind::IBGetDeviceListFail.into();
         assert_eq!(err.to_string(), "IBGetDeviceListFail");
+
+        let err: Error = ErrorKind::IBExceedDeviceLimit {
+            field: "max_send_wr".to_string(),
+            requested: 65536,
+            limit: 32768,
+        }
+        .into();
+        let json = serde_json::to_value(&err).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({
+                "kind": {
+                    "IBExceedDeviceLimit": {
+                        "field": "max_send_wr",
+                        "requested": 65536,
+                        "limit": 32768,
+                    }
+                },
+                "msg": null
+            })
+        );
+        assert_eq!(serde_json::from_value::<Error>(json).unwrap(), err);
     }
 }
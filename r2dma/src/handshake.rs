@@ -0,0 +1,405 @@
+//! Out-of-band queue pair handshake over TCP, for connecting sockets without librdmacm.
+//!
+//! Both sides send a fixed-size message carrying a version, a capability mask and their `Endpoint`,
+//! bring their queue pairs to RTS, and then confirm readiness with a single byte.
+
+use crate::{verbs, CompQueues, Devices, Endpoint, Error, ErrorKind, QueuePair, Result, Socket};
+use std::{net::SocketAddr, sync::Arc, time::Duration};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream, ToSocketAddrs},
+};
+
+pub const VERSION: u16 = 1;
+
+const MAGIC: [u8; 4] = *b"R2DM";
+const MESSAGE_LEN: usize = 32;
+const READY: u8 = 1;
+
+#[derive(Debug, Clone, Copy)]
+pub struct HandshakeConfig {
+    /// The device to create queue pairs on.
+    pub device_index: usize,
+    pub cap: verbs::ibv_qp_cap,
+    /// Application-defined capability bits, reported to the peer.
+    pub capabilities: u32,
+    pub timeout: Duration,
+}
+
+impl Default for HandshakeConfig {
+    fn default() -> Self {
+        Self {
+            device_index: 0,
+            cap: verbs::ibv_qp_cap {
+                max_send_wr: 64,
+                max_recv_wr: 64,
+                max_send_sge: 1,
+                max_recv_sge: 1,
+                max_inline_data: 0,
+            },
+            capabilities: 0,
+            timeout: Duration::from_secs(10),
+        }
+    }
+}
+
+/// The message exchanged by both sides of a handshake.
+#[derive(Debug)]
+pub struct HandshakeMessage {
+    pub version: u16,
+    pub capabilities: u32,
+    pub endpoint: Endpoint,
+}
+
+impl HandshakeMessage {
+    pub fn encode(&self) -> [u8; MESSAGE_LEN] {
+        let mut buf = [0u8; MESSAGE_LEN];
+        buf[0..4].copy_from_slice(&MAGIC);
+        buf[4..6].copy_from_slice(&self.version.to_be_bytes());
+        buf[6..10].copy_from_slice(&self.capabilities.to_be_bytes());
+        buf[10..14].copy_from_slice(&self.endpoint.qp_num.to_be_bytes());
+        buf[14..16].copy_from_slice(&self.endpoint.lid.to_be_bytes());
+        buf[16..32].copy_from_slice(self.endpoint.gid.as_raw());
+        buf
+    }
+
+    pub fn decode(buf: &[u8; MESSAGE_LEN]) -> Result<Self> {
+        if buf[0..4] != MAGIC {
+            return Err(Error::new(
+                ErrorKind::HandshakeInvalidMessage,
+                format!("invalid magic {:?}", &buf[0..4]),
+            ));
+        }
+        let mut gid = verbs::ibv_gid::default();
+        gid.raw = buf[16..32].try_into().unwrap();
+        Ok(Self {
+            version: u16::from_be_bytes(buf[4..6].try_into().unwrap()),
+            capabilities: u32::from_be_bytes(buf[6..10].try_into().unwrap()),
+            endpoint: Endpoint {
+                qp_num: u32::from_be_bytes(buf[10..14].try_into().unwrap()),
+                lid: u16::from_be_bytes(buf[14..16].try_into().unwrap()),
+                gid,
+            },
+        })
+    }
+}
+
+/// A socket connected through the handshake.
+#[derive(Debug)]
+pub struct Connection {
+    pub socket: Socket,
+    pub peer_addr: SocketAddr,
+    pub peer_capabilities: u32,
+}
+
+/// Accepts handshakes over TCP, like an `RdmaListener` without librdmacm.
+pub struct Listener {
+    listener: TcpListener,
+    devices: Devices,
+    comp_queues: Arc<CompQueues>,
+    config: HandshakeConfig,
+}
+
+impl Listener {
+    pub async fn bind(
+        addr: impl ToSocketAddrs,
+        devices: &Devices,
+        comp_queues: &Arc<CompQueues>,
+        config: HandshakeConfig,
+    ) -> Result<Self> {
+        let listener = TcpListener::bind(addr).await.map_err(io_error)?;
+        Ok(Self {
+            listener,
+            devices: devices.clone(),
+            comp_queues: comp_queues.clone(),
+            config,
+        })
+    }
+
+    pub fn local_addr(&self) -> Result<SocketAddr> {
+        self.listener.local_addr().map_err(io_error)
+    }
+
+    /// Waits for a peer to connect over TCP. The handshake is left to `Incoming::handshake`, which
+    /// can be spawned so a slow peer does not hold up accepting the others.
+    pub async fn accept(&self) -> Result<Incoming> {
+        let (stream, peer_addr) = self.listener.accept().await.map_err(io_error)?;
+        Ok(Incoming {
+            stream,
+            peer_addr,
+            devices: self.devices.clone(),
+            comp_queues: self.comp_queues.clone(),
+            config: self.config,
+        })
+    }
+}
+
+/// A peer accepted by `Listener::accept`, which has not completed the handshake yet.
+pub struct Incoming {
+    stream: TcpStream,
+    peer_addr: SocketAddr,
+    devices: Devices,
+    comp_queues: Arc<CompQueues>,
+    config: HandshakeConfig,
+}
+
+impl Incoming {
+    pub fn peer_addr(&self) -> SocketAddr {
+        self.peer_addr
+    }
+
+    /// Completes the handshake with the peer, within the timeout of the config.
+    pub async fn handshake(self) -> Result<Connection> {
+        handshake(
+            self.stream,
+            self.peer_addr,
+            &self.devices,
+            &self.comp_queues,
+            &self.config,
+        )
+        .await
+    }
+}
+
+impl std::fmt::Debug for Incoming {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Incoming")
+            .field("peer_addr", &self.peer_addr)
+            .finish()
+    }
+}
+
+impl std::fmt::Debug for Listener {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Listener")
+            .field("local_addr", &self.listener.local_addr())
+            .field("config", &self.config)
+            .finish()
+    }
+}
+
+/// Connects to a `Listener` and completes the handshake with it.
+pub async fn connect(
+    addr: impl ToSocketAddrs,
+    devices: &Devices,
+    comp_queues: &Arc<CompQueues>,
+    config: HandshakeConfig,
+) -> Result<Connection> {
+    let stream = TcpStream::connect(addr).await.map_err(io_error)?;
+    let peer_addr = stream.peer_addr().map_err(io_error)?;
+    handshake(stream, peer_addr, devices, comp_queues, &config).await
+}
+
+async fn handshake(
+    stream: TcpStream,
+    peer_addr: SocketAddr,
+    devices: &Devices,
+    comp_queues: &Arc<CompQueues>,
+    config: &HandshakeConfig,
+) -> Result<Connection> {
+    let fut = handshake_impl(stream, devices, comp_queues, config);
+    let (socket, peer_capabilities) = match tokio::time::timeout(config.timeout, fut).await {
+        Ok(result) => result?,
+        Err(_) => {
+            return Err(Error::new(
+                ErrorKind::HandshakeIOFail,
+                format!("handshake with {peer_addr} timed out"),
+            ))
+        }
+    };
+    Ok(Connection {
+        socket,
+        peer_addr,
+        peer_capabilities,
+    })
+}
+
+async fn handshake_impl(
+    mut stream: TcpStream,
+    devices: &Devices,
+    comp_queues: &Arc<CompQueues>,
+    config: &HandshakeConfig,
+) -> Result<(Socket, u32)> {
+    stream.set_nodelay(true).map_err(io_error)?;
+    let queue_pair = QueuePair::create(devices, config.device_index, comp_queues, config.cap)?;
+    let socket = Socket::create(Arc::new(queue_pair));
+
+    let local = HandshakeMessage {
+        version: VERSION,
+        capabilities: config.capabilities,
+        endpoint: socket.endpoint(),
+    };
+    stream.write_all(&local.encode()).await.map_err(io_error)?;
+    let mut buf = [0u8; MESSAGE_LEN];
+    stream.read_exact(&mut buf).await.map_err(io_error)?;
+    let remote = HandshakeMessage::decode(&buf)?;
+    if remote.version != VERSION {
+        return Err(ErrorKind::HandshakeVersionMismatch {
+            local: VERSION,
+            remote: remote.version,
+        }
+        .into());
+    }
+
+    // the peer drops the connection instead of confirming if it fails to reach RTS.
+    socket.init(remote.endpoint)?;
+    stream.write_all(&[READY]).await.map_err(io_error)?;
+    let mut ready = [0u8];
+    stream.read_exact(&mut ready).await.map_err(io_error)?;
+    if ready[0] != READY {
+        return Err(Error::new(
+            ErrorKind::HandshakeInvalidMessage,
+            format!("invalid ready byte {}", ready[0]),
+        ));
+    }
+    Ok((socket, remote.capabilities))
+}
+
+fn io_error(err: std::io::Error) -> Error {
+    Error::new(ErrorKind::HandshakeIOFail, err.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::*;
+
+    #[test]
+    fn test_handshake_message() {
+        let devices = MockVerbs::devices(1).unwrap();
+        let message = HandshakeMessage {
+            version: VERSION,
+            capabilities: 0b101,
+            endpoint: Endpoint {
+                qp_num: 0x123456,
+                lid: 7,
+                gid: devices[0].info().ports[0].gids[1].1,
+            },
+        };
+        let buf = message.encode();
+        let decoded = HandshakeMessage::decode(&buf).unwrap();
+        assert_eq!(decoded.version, VERSION);
+        assert_eq!(decoded.capabilities, 0b101);
+        assert_eq!(decoded.endpoint.qp_num, 0x123456);
+        assert_eq!(decoded.endpoint.lid, 7);
+        assert_eq!(decoded.endpoint.gid.as_raw(), message.endpoint.gid.as_raw());
+
+        let mut invalid = buf;
+        invalid[0] = b'X';
+        let err = HandshakeMessage::decode(&invalid).unwrap_err();
+        assert_eq!(err.kind, ErrorKind::HandshakeInvalidMessage);
+    }
+
+    #[tokio::test]
+    async fn test_handshake() {
+        let devices = MockVerbs::devices(1).unwrap();
+        let comp_queues = CompQueues::create(&devices, 128).unwrap();
+        let config = HandshakeConfig {
+            capabilities: 1,
+            ..Default::default()
+        };
+        let listener = Listener::bind("127.0.0.1:0", &devices, &comp_queues, config)
+            .await
+            .unwrap();
+        let addr = listener.local_addr().unwrap();
+        println!("{:?}", listener);
+
+        let server = tokio::spawn(async move {
+            let incoming = listener.accept().await.unwrap();
+            incoming.handshake().await.unwrap()
+        });
+        let config = HandshakeConfig {
+            capabilities: 2,
+            ..Default::default()
+        };
+        let client = connect(addr, &devices, &comp_queues, config).await.unwrap();
+        let server = server.await.unwrap();
+        assert_eq!(client.peer_capabilities, 1);
+        assert_eq!(server.peer_capabilities, 2);
+
+        let attr = client.socket.query().unwrap();
+        assert_eq!(attr.state, QueuePairState::ReadyToSend);
+        assert_eq!(attr.dest_qp_num, server.socket.qp_num());
+
+        let buffer_pool = BufferPool::create(4096, 2, &devices).unwrap();
+        server
+            .socket
+            .post_recv(1, buffer_pool.allocate().unwrap())
+            .unwrap();
+        client
+            .socket
+            .post_send(2, buffer_pool.allocate().unwrap())
+            .unwrap();
+        let mut wcs = vec![verbs::ibv_wc::default(); 128];
+        let comp = comp_queues.poll_cq(&mut wcs).unwrap();
+        assert_eq!(comp.len(), 2);
+        assert!(comp.iter().all(|wc| wc.result().is_ok()));
+    }
+
+    #[tokio::test]
+    async fn test_handshake_version_mismatch() {
+        let devices = MockVerbs::devices(1).unwrap();
+        let comp_queues = CompQueues::create(&devices, 128).unwrap();
+        let listener = Listener::bind("127.0.0.1:0", &devices, &comp_queues, Default::default())
+            .await
+            .unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let client = tokio::spawn(async move {
+            let mut stream = TcpStream::connect(addr).await.unwrap();
+            let message = HandshakeMessage {
+                version: VERSION + 1,
+                capabilities: 0,
+                endpoint: Endpoint {
+                    qp_num: 1,
+                    lid: 0,
+                    gid: Default::default(),
+                },
+            };
+            stream.write_all(&message.encode()).await.unwrap();
+            let mut buf = [0u8; MESSAGE_LEN];
+            stream.read_exact(&mut buf).await.unwrap();
+            // the listener drops the connection instead of confirming.
+            let mut ready = [0u8];
+            assert!(stream.read_exact(&mut ready).await.is_err());
+        });
+
+        let incoming = listener.accept().await.unwrap();
+        let err = incoming.handshake().await.unwrap_err();
+        assert_eq!(
+            err.kind,
+            ErrorKind::HandshakeVersionMismatch {
+                local: VERSION,
+                remote: VERSION + 1
+            }
+        );
+        client.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_handshake_concurrent() {
+        let devices = MockVerbs::devices(1).unwrap();
+        let comp_queues = CompQueues::create(&devices, 128).unwrap();
+        let listener = Listener::bind("127.0.0.1:0", &devices, &comp_queues, Default::default())
+            .await
+            .unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        // a peer which connects and never sends its message.
+        let stalled = TcpStream::connect(addr).await.unwrap();
+        let stalled_incoming = listener.accept().await.unwrap();
+        assert_eq!(stalled_incoming.peer_addr(), stalled.local_addr().unwrap());
+
+        let client = tokio::spawn({
+            let (devices, comp_queues) = (devices.clone(), comp_queues.clone());
+            async move { connect(addr, &devices, &comp_queues, Default::default()).await }
+        });
+        let server = listener.accept().await.unwrap().handshake().await.unwrap();
+        let client = client.await.unwrap().unwrap();
+        assert_eq!(
+            client.socket.query().unwrap().dest_qp_num,
+            server.socket.qp_num()
+        );
+        drop(stalled_incoming);
+    }
+}
@@ -3,6 +3,13 @@
 //! A Rust RDMA library.
 pub mod verbs;
 
+pub mod handshake;
+
+pub mod reconnect;
+
+mod backend;
+pub use backend::*;
+
 mod core;
 pub use core::*;
 
@@ -0,0 +1,434 @@
+//! A socket which recreates its queue pair after it fails, reconnecting through the handshake.
+
+use crate::{
+    handshake::{self, HandshakeConfig},
+    verbs, Buffer, CompQueues, Devices, Error, ErrorKind, PostResult, Result, Socket,
+};
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tokio::net::ToSocketAddrs;
+
+/// What happens to the work requests which fail along with the queue pair.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum PendingPolicy {
+    /// Returns their buffers to the caller, and rejects new work until reconnected.
+    #[default]
+    Fail,
+    /// Keeps them, along with new work, and posts them again once reconnected.
+    Resend,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct ReconnectConfig {
+    pub pending: PendingPolicy,
+    pub max_attempts: usize,
+    /// The delay between failed attempts, doubled after each one.
+    pub backoff: Duration,
+}
+
+impl Default for ReconnectConfig {
+    fn default() -> Self {
+        Self {
+            pending: PendingPolicy::Fail,
+            max_attempts: 5,
+            backoff: Duration::from_millis(100),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum WorkKind {
+    Send,
+    Recv,
+}
+
+struct State {
+    socket: Socket,
+    // replaced sockets, kept registered until their work requests have flushed.
+    retired: Vec<Socket>,
+    kinds: HashMap<(u32, u64), WorkKind>,
+    pending: Vec<(WorkKind, u64, Buffer)>,
+    broken: bool,
+    reconnects: usize,
+}
+
+impl State {
+    fn post(&mut self, kind: WorkKind, wr_id: u64, buf: Buffer) -> Result<()> {
+        self.try_post(kind, wr_id, buf).map_err(|(err, _)| err)
+    }
+
+    fn try_post(&mut self, kind: WorkKind, wr_id: u64, buf: Buffer) -> PostResult {
+        let result = match kind {
+            WorkKind::Send => self.socket.try_post_send(wr_id, buf),
+            WorkKind::Recv => self.socket.try_post_recv(wr_id, buf),
+        };
+        match result {
+            Ok(()) => {
+                self.kinds.insert((self.socket.qp_num(), wr_id), kind);
+                Ok(())
+            }
+            Err(err) => {
+                // usage errors, e.g. a duplicate wr_id or a full queue, leave the queue pair working.
+                if self.socket.is_error().unwrap_or(true) {
+                    self.broken = true;
+                }
+                Err(err)
+            }
+        }
+    }
+
+    /// Posts the pending work requests in order. On failure, the failed one and those after it stay
+    /// pending.
+    fn repost(&mut self) -> Result<()> {
+        let mut pending = std::mem::take(&mut self.pending).into_iter();
+        while let Some((kind, wr_id, buf)) = pending.next() {
+            if let Err((err, buf)) = self.try_post(kind, wr_id, buf) {
+                self.pending.extend(buf.map(|buf| (kind, wr_id, buf)));
+                self.pending.extend(pending);
+                return Err(err);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A socket connected through `handshake::connect`, which reconnects to the same listener when its
+/// queue pair moves to the error state. The new queue pair is created on the same completion queues.
+///
+/// Only the connecting side recovers. The listener gets the new queue pair as a new `Incoming`,
+/// with no link to the connection it replaces, so the application must match them itself, e.g. by
+/// an identifier sent once connected, and drop the failed `Socket` on its side.
+///
+/// Completions of its work requests must be passed to `complete`, which detects the failure.
+pub struct ReconnectingSocket {
+    addr: SocketAddr,
+    devices: Devices,
+    comp_queues: Arc<CompQueues>,
+    handshake: HandshakeConfig,
+    config: ReconnectConfig,
+    state: Mutex<State>,
+}
+
+impl ReconnectingSocket {
+    pub async fn connect(
+        addr: impl ToSocketAddrs,
+        devices: &Devices,
+        comp_queues: &Arc<CompQueues>,
+        handshake: HandshakeConfig,
+        config: ReconnectConfig,
+    ) -> Result<Self> {
+        let connection = handshake::connect(addr, devices, comp_queues, handshake).await?;
+        Ok(Self {
+            addr: connection.peer_addr,
+            devices: devices.clone(),
+            comp_queues: comp_queues.clone(),
+            handshake,
+            config,
+            state: Mutex::new(State {
+                socket: connection.socket,
+                retired: vec![],
+                kinds: HashMap::new(),
+                pending: vec![],
+                broken: false,
+                reconnects: 0,
+            }),
+        })
+    }
+
+    /// Returns the current socket, which is replaced on reconnection.
+    pub fn socket(&self) -> Socket {
+        self.state.lock().unwrap().socket.clone()
+    }
+
+    pub fn qp_num(&self) -> u32 {
+        self.state.lock().unwrap().socket.qp_num()
+    }
+
+    /// Returns true if a failed completion has been seen since the last reconnection.
+    pub fn is_broken(&self) -> bool {
+        self.state.lock().unwrap().broken
+    }
+
+    pub fn reconnects(&self) -> usize {
+        self.state.lock().unwrap().reconnects
+    }
+
+    /// Returns the number of work requests waiting to be posted again.
+    pub fn pending(&self) -> usize {
+        self.state.lock().unwrap().pending.len()
+    }
+
+    pub fn post_send(&self, wr_id: u64, buf: Buffer) -> Result<()> {
+        self.post(WorkKind::Send, wr_id, buf)
+    }
+
+    pub fn post_recv(&self, wr_id: u64, buf: Buffer) -> Result<()> {
+        self.post(WorkKind::Recv, wr_id, buf)
+    }
+
+    fn post(&self, kind: WorkKind, wr_id: u64, buf: Buffer) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        if !state.broken {
+            return state.post(kind, wr_id, buf);
+        }
+        match self.config.pending {
+            PendingPolicy::Resend => {
+                state.pending.push((kind, wr_id, buf));
+                Ok(())
+            }
+            PendingPolicy::Fail => {
+                let kind = match kind {
+                    WorkKind::Send => ErrorKind::IBPostSendFailed,
+                    WorkKind::Recv => ErrorKind::IBPostRecvFailed,
+                };
+                Err(Error::new(kind, "socket is reconnecting".to_string()))
+            }
+        }
+    }
+
+    /// Handles a completion of this socket, or of a socket it replaced.
+    /// Returns the buffer unless it is kept to be posted again.
+    pub fn complete(&self, wc: &verbs::ibv_wc) -> Option<Buffer> {
+        let mut state = self.state.lock().unwrap();
+        let kind = state.kinds.remove(&(wc.qp_num, wc.wr_id));
+        let current = wc.qp_num == state.socket.qp_num();
+        let status = wc.wc_status();
+        // checked before taking the buffer, so a failed completion without one still marks it.
+        if current && status.is_fatal_for_qp() {
+            state.broken = true;
+        }
+        // the sockets share a device, so the queue pair number identifies the one which posted it.
+        let buf = std::iter::once(&state.socket)
+            .chain(&state.retired)
+            .find(|socket| socket.qp_num() == wc.qp_num)
+            .and_then(|socket| socket.complete(wc));
+        state.retired.retain(|socket| socket.outstanding() > 0);
+        let buf = buf?;
+
+        if status.is_success() {
+            return Some(buf);
+        }
+        match (self.config.pending, kind) {
+            (PendingPolicy::Resend, Some(kind)) if status.is_retryable() => {
+                state.pending.push((kind, wc.wr_id, buf));
+                // flushed late from a replaced socket, so the current one can take it now.
+                if !current && !state.broken && state.repost().is_err() {
+                    tracing::warn!("failed to repost work requests of {}", wc.qp_num);
+                }
+                None
+            }
+            _ => Some(buf),
+        }
+    }
+
+    /// Reconnects if the queue pair has failed. Returns true if it has reconnected.
+    pub async fn recover(&self) -> Result<bool> {
+        let socket = {
+            let state = self.state.lock().unwrap();
+            if state.broken {
+                None
+            } else {
+                Some(state.socket.clone())
+            }
+        };
+        if let Some(socket) = socket {
+            if !socket.is_error()? {
+                return Ok(false);
+            }
+            self.state.lock().unwrap().broken = true;
+        }
+        self.reconnect().await?;
+        Ok(true)
+    }
+
+    /// Replaces the queue pair with a new one connected to the same listener, then posts the
+    /// pending work requests on it.
+    pub async fn reconnect(&self) -> Result<()> {
+        let old = {
+            let mut state = self.state.lock().unwrap();
+            state.broken = true;
+            state.socket.clone()
+        };
+        // flushes the work requests which are still posted.
+        old.shutdown()?;
+
+        let mut backoff = self.config.backoff;
+        let mut attempt = 1;
+        let connection = loop {
+            match handshake::connect(self.addr, &self.devices, &self.comp_queues, self.handshake)
+                .await
+            {
+                Ok(connection) => break connection,
+                Err(err) if attempt < self.config.max_attempts => {
+                    tracing::warn!("reconnect to {} attempt {attempt} failed: {err}", self.addr);
+                    tokio::time::sleep(backoff).await;
+                    backoff *= 2;
+                    attempt += 1;
+                }
+                Err(err) => return Err(err),
+            }
+        };
+
+        let mut state = self.state.lock().unwrap();
+        let old = std::mem::replace(&mut state.socket, connection.socket);
+        if old.outstanding() > 0 {
+            state.retired.push(old);
+        }
+        state.broken = false;
+        state.reconnects += 1;
+        state.repost()
+    }
+}
+
+impl std::fmt::Debug for ReconnectingSocket {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let state = self.state.lock().unwrap();
+        f.debug_struct("ReconnectingSocket")
+            .field("addr", &self.addr)
+            .field("socket", &state.socket)
+            .field("retired", &state.retired.len())
+            .field("pending", &state.pending.len())
+            .field("broken", &state.broken)
+            .field("reconnects", &state.reconnects)
+            .field("config", &self.config)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{handshake::Listener, *};
+
+    async fn setup(
+        policy: PendingPolicy,
+    ) -> (Devices, Arc<CompQueues>, Arc<Listener>, ReconnectingSocket) {
+        let devices = MockVerbs::devices(1).unwrap();
+        let comp_queues = CompQueues::create(&devices, 128).unwrap();
+        let listener = Listener::bind("127.0.0.1:0", &devices, &comp_queues, Default::default())
+            .await
+            .unwrap();
+        let listener = Arc::new(listener);
+        let addr = listener.local_addr().unwrap();
+
+        let accept = tokio::spawn({
+            let listener = listener.clone();
+            async move {
+                let incoming = listener.accept().await.unwrap();
+                incoming.handshake().await.unwrap()
+            }
+        });
+        let config = ReconnectConfig {
+            pending: policy,
+            ..Default::default()
+        };
+        let socket =
+            ReconnectingSocket::connect(addr, &devices, &comp_queues, Default::default(), config)
+                .await
+                .unwrap();
+        accept.await.unwrap();
+        (devices, comp_queues, listener, socket)
+    }
+
+    async fn reconnect(listener: &Arc<Listener>, socket: &ReconnectingSocket) -> Socket {
+        let accept = tokio::spawn({
+            let listener = listener.clone();
+            async move {
+                let incoming = listener.accept().await.unwrap();
+                incoming.handshake().await.unwrap()
+            }
+        });
+        assert!(socket.recover().await.unwrap());
+        accept.await.unwrap().socket
+    }
+
+    #[tokio::test]
+    async fn test_reconnect_fail() {
+        let (devices, comp_queues, listener, socket) = setup(PendingPolicy::Fail).await;
+        let buffer_pool = BufferPool::create(4096, 4, &devices).unwrap();
+        let mut wcs = vec![verbs::ibv_wc::default(); 128];
+
+        // the peer is gone, so the send exceeds its retries.
+        socket
+            .post_send(1, buffer_pool.allocate().unwrap())
+            .unwrap();
+        let comp = comp_queues.poll_cq(&mut wcs).unwrap();
+        assert_eq!(comp.len(), 1);
+        assert!(comp[0].wc_status().is_retryable());
+        assert!(socket.complete(&comp[0]).is_some());
+        assert!(socket.is_broken());
+        assert!(socket
+            .post_send(2, buffer_pool.allocate().unwrap())
+            .is_err());
+        assert_eq!(buffer_pool.stats().in_use, 0);
+
+        let qp_num = socket.qp_num();
+        let peer = reconnect(&listener, &socket).await;
+        assert_ne!(socket.qp_num(), qp_num);
+        assert_eq!(socket.reconnects(), 1);
+        assert!(!socket.is_broken());
+
+        peer.post_recv(3, buffer_pool.allocate().unwrap()).unwrap();
+        socket
+            .post_send(4, buffer_pool.allocate().unwrap())
+            .unwrap();
+        let comp = comp_queues.poll_cq(&mut wcs).unwrap();
+        assert_eq!(comp.len(), 2);
+        assert!(comp.iter().all(|wc| wc.result() == Ok(4096)));
+    }
+
+    #[tokio::test]
+    async fn test_reconnect_usage_error() {
+        let (devices, _comp_queues, _listener, socket) = setup(PendingPolicy::Fail).await;
+        let buffer_pool = BufferPool::create(4096, 4, &devices).unwrap();
+
+        // a duplicate wr_id leaves the queue pair working.
+        socket
+            .post_recv(1, buffer_pool.allocate().unwrap())
+            .unwrap();
+        assert!(socket
+            .post_recv(1, buffer_pool.allocate().unwrap())
+            .is_err());
+        assert!(!socket.is_broken());
+        assert_eq!(buffer_pool.stats().in_use, 1);
+    }
+
+    #[tokio::test]
+    async fn test_reconnect_resend() {
+        let (devices, comp_queues, listener, socket) = setup(PendingPolicy::Resend).await;
+        let buffer_pool = BufferPool::create(4096, 4, &devices).unwrap();
+        let mut wcs = vec![verbs::ibv_wc::default(); 128];
+
+        socket
+            .post_recv(1, buffer_pool.allocate().unwrap())
+            .unwrap();
+        socket.socket().shutdown().unwrap();
+        let comp = comp_queues.poll_cq(&mut wcs).unwrap();
+        assert_eq!(comp.len(), 1);
+        assert!(comp[0].wc_status().is_flush());
+        assert!(socket.complete(&comp[0]).is_none());
+        assert!(socket.is_broken());
+
+        // new work is kept along with the flushed one.
+        socket
+            .post_recv(2, buffer_pool.allocate().unwrap())
+            .unwrap();
+        assert_eq!(socket.pending(), 2);
+        assert_eq!(buffer_pool.stats().in_use, 2);
+
+        let peer = reconnect(&listener, &socket).await;
+        assert_eq!(socket.pending(), 0);
+        assert_eq!(socket.socket().outstanding(), 2);
+
+        peer.post_send(3, buffer_pool.allocate().unwrap()).unwrap();
+        let comp = comp_queues.poll_cq(&mut wcs).unwrap();
+        let recv = comp.iter().find(|wc| wc.qp_num == socket.qp_num()).unwrap();
+        assert_eq!(recv.wr_id, 1);
+        assert_eq!(recv.result(), Ok(4096));
+        assert!(socket.complete(recv).is_some());
+    }
+}
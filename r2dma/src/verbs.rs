@@ -63,6 +63,71 @@ pub unsafe fn ibv_post_recv(
     (*(*qp).context).ops.post_recv.unwrap_unchecked()(qp, wr, bad_wr)
 }
 
+#[inline(always)]
+pub unsafe fn ibv_post_srq_recv(
+    srq: *mut ibv_srq,
+    wr: *mut ibv_recv_wr,
+    bad_wr: *mut *mut ibv_recv_wr,
+) -> c_int {
+    (*(*srq).context).ops.post_srq_recv.unwrap_unchecked()(srq, wr, bad_wr)
+}
+
+#[inline(always)]
+pub unsafe fn ibv_alloc_mw(pd: *mut ibv_pd, type_: ibv_mw_type) -> *mut ibv_mw {
+    match (*(*pd).context).ops.alloc_mw {
+        Some(alloc_mw) => alloc_mw(pd, type_),
+        None => {
+            *libc::__errno_location() = libc::EOPNOTSUPP;
+            std::ptr::null_mut()
+        }
+    }
+}
+
+#[inline(always)]
+pub unsafe fn ibv_dealloc_mw(mw: *mut ibv_mw) -> c_int {
+    (*(*mw).context).ops.dealloc_mw.unwrap_unchecked()(mw)
+}
+
+/// Returns the extended context of a device context, or null for a provider without one.
+#[inline(always)]
+pub unsafe fn verbs_get_ctx(context: *mut ibv_context) -> *mut verbs_context {
+    if (*context).abi_compat as usize != usize::MAX {
+        return std::ptr::null_mut();
+    }
+    context
+        .byte_sub(std::mem::offset_of!(verbs_context, context))
+        .cast()
+}
+
+/// Queries the extended device attributes, falling back to the legacy attributes with the
+/// extensions zeroed when the provider does not implement the query.
+#[inline(always)]
+pub unsafe fn ibv_query_device_ex(
+    context: *mut ibv_context,
+    input: *const ibv_query_device_ex_input,
+    attr: *mut ibv_device_attr_ex,
+) -> c_int {
+    let vctx = verbs_get_ctx(context);
+    let op_offset = std::mem::offset_of!(verbs_context, query_device_ex);
+    if !vctx.is_null() && (*vctx).sz >= std::mem::size_of::<verbs_context>() - op_offset {
+        if let Some(query_device_ex) = (*vctx).query_device_ex {
+            let size = std::mem::size_of::<ibv_device_attr_ex>();
+            let ret = query_device_ex(context, input, attr, size);
+            if ret != libc::EOPNOTSUPP && ret != libc::ENOSYS {
+                return ret;
+            }
+        }
+    }
+    *attr = ibv_device_attr_ex::default();
+    ibv_query_device(context, &mut (*attr).orig_attr)
+}
+
+/// Increments the key index in the lowest byte of a memory window rkey, for the next bind.
+#[inline(always)]
+pub fn ibv_inc_rkey(rkey: u32) -> u32 {
+    (rkey & 0xffffff00) | (rkey.wrapping_add(1) & 0xff)
+}
+
 impl ibv_gid {
     pub fn as_raw(&self) -> &[u8; 16] {
         unsafe { &self.raw }